@@ -0,0 +1,231 @@
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::cancel::CancelToken;
+use crate::errors::Error;
+use crate::frame::{Frame, ReqBuf};
+use crate::multiplex_client::LazyClient;
+use crate::retry::RetryPolicy;
+use crate::stream_ext::StreamExt;
+use crate::util::random;
+use crate::Client;
+
+use may::sync::Mutex;
+
+/// the strategy to pick the endpoint for each call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    /// pick the endpoints in turn
+    RoundRobin,
+    /// pick the endpoint with the least outstanding calls
+    LeastOutstanding,
+    /// pick two random endpoints and use the less loaded one
+    PowerOfTwo,
+}
+
+// the errors that indicate the endpoint is not healthy
+fn is_failure(err: &Error) -> bool {
+    matches!(err, Error::Io(_) | Error::Timeout | Error::Disconnected)
+}
+
+// count the outstanding call, released even if the call is cancelled
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn new(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        InFlight(count)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct Endpoint<S: StreamExt> {
+    client: LazyClient<S>,
+    // the number of the outstanding calls
+    in_flight: AtomicUsize,
+    // the number of the consecutive failures
+    failures: AtomicUsize,
+    // the endpoint is not picked until then
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+impl<S: StreamExt> Endpoint<S> {
+    fn is_ejected(&self, now: Instant) -> bool {
+        match *self.ejected_until.lock().unwrap() {
+            Some(until) => now < until,
+            None => false,
+        }
+    }
+}
+
+/// client that spreads the calls over the replicas of a service
+/// the endpoints that keep failing are ejected for a while
+pub struct BalanceClient<S: StreamExt> {
+    endpoints: Vec<Endpoint<S>>,
+    balance: Balance,
+    // no timeout by default, the calls wait until the connection is closed
+    timeout: Option<Duration>,
+    // eject the endpoint after this number of the consecutive failures
+    max_failures: usize,
+    // how long the ejected endpoint is not picked
+    eject_time: Duration,
+    next: AtomicUsize,
+}
+
+impl<S: StreamExt> std::fmt::Debug for BalanceClient<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BalanceClient")
+            .field("balance", &self.balance)
+            .field("endpoints", &self.endpoints.len())
+            .field("healthy", &self.healthy())
+            .finish()
+    }
+}
+
+impl<S: StreamExt> BalanceClient<S> {
+    /// create the client over the endpoints, each is given by its connect function
+    /// no connection is created until the endpoint is picked
+    pub fn new<F>(balance: Balance, endpoints: Vec<F>) -> Self
+    where
+        F: Fn() -> io::Result<S> + Send + Sync + 'static,
+    {
+        assert!(!endpoints.is_empty(), "no endpoints");
+        let endpoints = endpoints
+            .into_iter()
+            .map(|connect| Endpoint {
+                client: LazyClient::new(connect),
+                in_flight: AtomicUsize::new(0),
+                failures: AtomicUsize::new(0),
+                ejected_until: Mutex::new(None),
+            })
+            .collect();
+        BalanceClient {
+            endpoints,
+            balance,
+            timeout: None,
+            max_failures: 3,
+            eject_time: Duration::from_secs(10),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// set the default timeout value
+    /// there is no timeout initially, the calls wait until the connection is closed
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// eject the endpoint for `eject_time` after `max_failures` consecutive
+    /// io errors or timeouts, it's picked again after that
+    /// the initial values are 3 failures and 10 seconds
+    pub fn set_ejection(&mut self, max_failures: usize, eject_time: Duration) {
+        self.max_failures = max_failures.max(1);
+        self.eject_time = eject_time;
+    }
+
+    /// the number of the endpoints that are not ejected
+    pub fn healthy(&self) -> usize {
+        let now = Instant::now();
+        self.endpoints.iter().filter(|e| !e.is_ejected(now)).count()
+    }
+
+    // pick the endpoint by the strategy, all are candidates if all are ejected
+    // the avoided endpoint is only picked if it's the only candidate
+    fn pick(&self, avoid: Option<usize>) -> (usize, &Endpoint<S>) {
+        let now = Instant::now();
+        let all = || self.endpoints.iter().enumerate();
+        let mut candidates: Vec<_> = all().filter(|(_, e)| !e.is_ejected(now)).collect();
+        if candidates.is_empty() {
+            candidates = all().collect();
+        }
+        if candidates.len() > 1 {
+            candidates.retain(|&(i, _)| Some(i) != avoid);
+        }
+
+        let n = candidates.len();
+        let load = |e: &(usize, &Endpoint<S>)| e.1.in_flight.load(Ordering::Relaxed);
+        match self.balance {
+            Balance::RoundRobin => candidates[self.next.fetch_add(1, Ordering::Relaxed) % n],
+            Balance::LeastOutstanding => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                (0..n)
+                    .map(|i| candidates[(start + i) % n])
+                    .min_by_key(load)
+                    .unwrap()
+            }
+            Balance::PowerOfTwo if n == 1 => candidates[0],
+            Balance::PowerOfTwo => {
+                // two distinct endpoints
+                let i = random() as usize % n;
+                let j = (i + 1 + random() as usize % (n - 1)) % n;
+                let (a, b) = (candidates[i], candidates[j]);
+                if load(&a) <= load(&b) {
+                    a
+                } else {
+                    b
+                }
+            }
+        }
+    }
+
+    // track the health of the endpoint after the call
+    fn report(&self, endpoint: &Endpoint<S>, ret: &Result<Frame, Error>) {
+        match ret {
+            // the abandoned call tells nothing about the endpoint
+            Err(Error::Cancelled) => {}
+            Err(err) if is_failure(err) => {
+                let failures = endpoint.failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures >= self.max_failures {
+                    warn!("balance client: eject endpoint, err = {:?}", err);
+                    *endpoint.ejected_until.lock().unwrap() =
+                        Some(Instant::now() + self.eject_time);
+                    // connect again when the endpoint is picked
+                    endpoint.client.reset();
+                }
+            }
+            _ => {
+                endpoint.failures.store(0, Ordering::Relaxed);
+                *endpoint.ejected_until.lock().unwrap() = None;
+            }
+        }
+    }
+
+    // call the picked endpoint, the call is abandoned once the token is cancelled
+    // the endpoint is recorded in the token, so the hedge of the call avoids it
+    fn call_with(&self, req: ReqBuf, token: Option<&CancelToken>) -> Result<Frame, Error> {
+        let (i, endpoint) = self.pick(token.and_then(CancelToken::avoid));
+        if let Some(token) = token {
+            token.set_endpoint(i);
+        }
+        let in_flight = InFlight::new(&endpoint.in_flight);
+        let ret = endpoint.client.get().and_then(|client| match token {
+            Some(token) => client.call_cancellable_timeout(req, self.timeout, token),
+            None => client.call_timeout(req, self.timeout),
+        });
+        drop(in_flight);
+        self.report(endpoint, &ret);
+        ret
+    }
+}
+
+impl<S: StreamExt> Client for BalanceClient<S> {
+    fn call_service(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.call_with(req, None)
+    }
+
+    /// each attempt picks the endpoint again, so the retry may go to another endpoint
+    fn call_with_retry(&self, req: ReqBuf, policy: &RetryPolicy) -> Result<Frame, Error> {
+        policy.retry(|| self.call_with(req.clone(), None))
+    }
+
+    /// the abandoned call is not counted as a failure of the endpoint
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        self.call_with(req, Some(token))
+    }
+}
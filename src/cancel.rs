@@ -0,0 +1,122 @@
+use may::sync::Mutex;
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct State {
+    cancelled: bool,
+    // abandon the calls that are in flight, keyed by the registration id
+    hooks: Vec<(u64, Hook)>,
+    next_hook: u64,
+    // the endpoint that the call is sent to, set by the balancing clients
+    endpoint: Option<usize>,
+    // the endpoint that the call should not be sent to
+    avoid: Option<usize>,
+}
+
+/// the token to abandon the calls made by `Client::call_cancellable`
+/// the abandoned calls fail with `Error::Cancelled` without waiting for the response
+#[derive(Default)]
+pub struct CancelToken {
+    state: Mutex<State>,
+}
+
+impl std::fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl CancelToken {
+    /// create the token that is not cancelled
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    /// abandon the calls, it does nothing to the calls that are already answered
+    pub fn cancel(&self) {
+        let hooks = {
+            let mut state = self.state.lock().unwrap();
+            state.cancelled = true;
+            std::mem::take(&mut state.hooks)
+        };
+        for (_, hook) in hooks {
+            hook();
+        }
+    }
+
+    /// whether the token is cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.state.lock().unwrap().cancelled
+    }
+
+    /// run the hook once the token is cancelled, or right now if it's already cancelled
+    /// the clients use it to wake up the waiting call
+    /// the hook is removed when the returned guard is dropped, so hold it during the call
+    pub fn on_cancel<F: FnOnce() + Send + 'static>(&self, hook: F) -> CancelGuard<'_> {
+        let mut state = self.state.lock().unwrap();
+        if state.cancelled {
+            drop(state);
+            hook();
+            return CancelGuard {
+                token: self,
+                id: None,
+            };
+        }
+        let id = state.next_hook;
+        state.next_hook += 1;
+        state.hooks.push((id, Box::new(hook)));
+        CancelGuard {
+            token: self,
+            id: Some(id),
+        }
+    }
+
+    // the token of the hedge, it avoids the endpoint of the first copy
+    pub(crate) fn hedge_of(first: &CancelToken) -> Self {
+        let token = CancelToken::new();
+        token.state.lock().unwrap().avoid = first.endpoint();
+        token
+    }
+
+    // record the endpoint that the call is sent to
+    pub(crate) fn set_endpoint(&self, endpoint: usize) {
+        self.state.lock().unwrap().endpoint = Some(endpoint);
+    }
+
+    pub(crate) fn endpoint(&self) -> Option<usize> {
+        self.state.lock().unwrap().endpoint
+    }
+
+    pub(crate) fn avoid(&self) -> Option<usize> {
+        self.state.lock().unwrap().avoid
+    }
+}
+
+/// the hook registered by `CancelToken::on_cancel`, removed from the token when dropped
+/// so that a long lived token doesn't keep the hooks of the finished calls
+#[must_use = "the hook is removed when the guard is dropped"]
+pub struct CancelGuard<'a> {
+    token: &'a CancelToken,
+    // None if the hook has run already
+    id: Option<u64>,
+}
+
+impl std::fmt::Debug for CancelGuard<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancelGuard").field("id", &self.id).finish()
+    }
+}
+
+impl Drop for CancelGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let mut state = self.token.state.lock().unwrap();
+            if let Some(i) = state.hooks.iter().position(|(hook, _)| *hook == id) {
+                state.hooks.swap_remove(i);
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::io;
+use std::os::unix::io::IntoRawFd;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::Duration;
+
+use crate::compress::Compression;
+use crate::errors::Error;
+use crate::frame::{Frame, ReqBuf};
+use crate::multiplex_client::MultiplexClient;
+use crate::pipe::PipeStream;
+use crate::Client;
+
+use may::sync::Mutex;
+
+/// client that talks to a child process over its stdin/stdout
+/// the child should serve the requests by `PipeStream::stdio`
+/// the child is killed when the client is dropped
+#[derive(Debug)]
+pub struct ChildClient {
+    child: Mutex<Child>,
+    client: MultiplexClient<PipeStream>,
+}
+
+impl Drop for ChildClient {
+    fn drop(&mut self) {
+        let child = self.child.get_mut().unwrap();
+        child.kill().ok();
+        // don't block the coroutine on the exit, reap the killed child off-thread
+        if let Ok(None) = child.try_wait() {
+            let pid = child.id() as libc::pid_t;
+            let reaper = std::thread::Builder::new().name("ChildReaper".to_owned());
+            let ret = reaper.spawn(move || unsafe { libc::waitpid(pid, std::ptr::null_mut(), 0) });
+            if let Err(e) = ret {
+                error!("child client spawn reaper: err = {:?}", e);
+            }
+        }
+    }
+}
+
+impl ChildClient {
+    /// spawn the command with piped stdin/stdout
+    pub fn spawn(cmd: &mut Command) -> io::Result<Self> {
+        let mut child = cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let stdin = child.stdin.take().expect("no child stdin").into_raw_fd();
+        let stdout = child.stdout.take().expect("no child stdout").into_raw_fd();
+        let stream = unsafe { PipeStream::from_raw_fds(stdout, stdin) };
+        let client = match stream.and_then(MultiplexClient::new) {
+            Ok(client) => client,
+            Err(e) => {
+                child.kill().ok();
+                child.wait().ok();
+                return Err(e);
+            }
+        };
+
+        Ok(ChildClient {
+            child: Mutex::new(child),
+            client,
+        })
+    }
+
+    /// set the default timeout value
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.client.set_timeout(timeout);
+    }
+
+    /// set the compression settings, the compression is disabled by default
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.client.set_compression(compression);
+    }
+
+    /// the os assigned process id of the child
+    pub fn id(&self) -> u32 {
+        self.child.lock().unwrap().id()
+    }
+
+    /// return the exit status if the child has exited
+    pub fn try_wait(&self) -> io::Result<Option<ExitStatus>> {
+        self.child.lock().unwrap().try_wait()
+    }
+
+    // return an error if the child has exited
+    fn check_alive(&self) -> Result<(), Error> {
+        match self.try_wait()? {
+            None => Ok(()),
+            Some(status) => {
+                let s = format!("child process exited, {}", status);
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, s).into())
+            }
+        }
+    }
+}
+
+impl Client for ChildClient {
+    fn call_service(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.check_alive()?;
+        self.client.call_service(req).map_err(|e| {
+            // report the child exit instead of the io error or timeout
+            match self.check_alive() {
+                Ok(_) => e,
+                Err(exit) => exit,
+            }
+        })
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::cancel::CancelToken;
+use crate::errors::Error;
+use crate::frame::{Frame, ReqBuf};
+use crate::retry::{is_retryable, RetryPolicy};
+use crate::Client;
+
+use may::sync::Mutex;
+
+/// the state of the `CircuitBreaker`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// the calls are sent to the server
+    Closed,
+    /// the calls fail fast with `Error::CircuitOpen`
+    Open,
+    /// a trial call is sent to check if the server is back
+    HalfOpen,
+}
+
+type OnChange = Box<dyn Fn(CircuitState, CircuitState) + Send + Sync>;
+
+struct Circuit {
+    state: CircuitState,
+    // the finish time and whether it failed, of the calls in the window
+    outcomes: VecDeque<(Instant, bool)>,
+    // the number of the consecutive failures
+    failures: usize,
+    opened_at: Instant,
+    // the trial call is in flight when half open
+    trial: bool,
+}
+
+impl Circuit {
+    // change the state, return the transition
+    fn set(&mut self, state: CircuitState) -> Option<(CircuitState, CircuitState)> {
+        let from = self.state;
+        if from == state {
+            return None;
+        }
+        self.state = state;
+        self.outcomes.clear();
+        self.failures = 0;
+        self.trial = false;
+        if state == CircuitState::Open {
+            self.opened_at = Instant::now();
+        }
+        Some((from, state))
+    }
+}
+
+/// client wrapper that stops calling the failing server for a while
+/// the circuit opens after the consecutive failures or the high error rate,
+/// and closes again once a trial call succeeds
+pub struct CircuitBreaker<C: Client> {
+    client: C,
+    circuit: Mutex<Circuit>,
+    // open after this number of the consecutive failures
+    max_failures: usize,
+    // open when the error rate in the window reaches this
+    error_rate: f64,
+    // the error rate is only checked with enough calls in the window
+    min_calls: usize,
+    window: Duration,
+    // how long the circuit stays open before the trial call
+    open_time: Duration,
+    on_change: Option<OnChange>,
+}
+
+impl<C: Client> std::fmt::Debug for CircuitBreaker<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("state", &self.state())
+            .field("max_failures", &self.max_failures)
+            .field("error_rate", &self.error_rate)
+            .field("open_time", &self.open_time)
+            .finish()
+    }
+}
+
+impl<C: Client> CircuitBreaker<C> {
+    /// wrap the client, the circuit opens after 5 consecutive failures
+    /// or 50% errors of at least 10 calls in 10 seconds, and stays open for 5 seconds
+    pub fn new(client: C) -> Self {
+        CircuitBreaker {
+            client,
+            circuit: Mutex::new(Circuit {
+                state: CircuitState::Closed,
+                outcomes: VecDeque::new(),
+                failures: 0,
+                opened_at: Instant::now(),
+                trial: false,
+            }),
+            max_failures: 5,
+            error_rate: 0.5,
+            min_calls: 10,
+            window: Duration::from_secs(10),
+            open_time: Duration::from_secs(5),
+            on_change: None,
+        }
+    }
+
+    /// get the wrapped client
+    pub fn get_ref(&self) -> &C {
+        &self.client
+    }
+
+    /// open the circuit after this number of the consecutive failures
+    pub fn set_max_failures(&mut self, max_failures: usize) {
+        self.max_failures = max_failures.max(1);
+    }
+
+    /// open the circuit when the error rate of the calls in the sliding window
+    /// reaches `error_rate`, the rate is only checked with at least `min_calls`
+    pub fn set_error_rate(&mut self, error_rate: f64, min_calls: usize, window: Duration) {
+        self.error_rate = error_rate;
+        self.min_calls = min_calls.max(1);
+        self.window = window;
+    }
+
+    /// how long the circuit stays open, the next call after that is the trial call
+    pub fn set_open_time(&mut self, open_time: Duration) {
+        self.open_time = open_time;
+    }
+
+    /// set the callback for the state transitions, called with the old and new state
+    pub fn set_on_state_change<F>(&mut self, f: F)
+    where
+        F: Fn(CircuitState, CircuitState) + Send + Sync + 'static,
+    {
+        self.on_change = Some(Box::new(f));
+    }
+
+    /// the current state
+    pub fn state(&self) -> CircuitState {
+        self.circuit.lock().unwrap().state
+    }
+
+    fn notify(&self, transition: Option<(CircuitState, CircuitState)>) {
+        if let Some((from, to)) = transition {
+            info!("circuit breaker: {:?} -> {:?}", from, to);
+            if let Some(ref f) = self.on_change {
+                f(from, to);
+            }
+        }
+    }
+
+    // run the call if it's allowed and record the result
+    fn call_with<F>(&self, call: F) -> Result<Frame, Error>
+    where
+        F: FnOnce(&C) -> Result<Frame, Error>,
+    {
+        let permit = self.acquire()?;
+        let ret = call(&self.client);
+        match ret {
+            // the abandoned call tells nothing about the server, the permit is dropped
+            Err(Error::Cancelled) => drop(permit),
+            // the errors that indicate the server is not healthy
+            _ => permit.release(matches!(ret, Err(ref e) if is_retryable(e))),
+        }
+        ret
+    }
+
+    // check if the call is allowed, the trial call is tagged by the permit
+    fn acquire(&self) -> Result<Permit<'_, C>, Error> {
+        let mut circuit = self.circuit.lock().unwrap();
+        let mut transition = None;
+        if circuit.state == CircuitState::Open && circuit.opened_at.elapsed() >= self.open_time {
+            transition = circuit.set(CircuitState::HalfOpen);
+        }
+        let ret = match circuit.state {
+            CircuitState::Closed => Ok(false),
+            CircuitState::Open => Err(Error::CircuitOpen),
+            // only one trial call at a time
+            CircuitState::HalfOpen if circuit.trial => Err(Error::CircuitOpen),
+            CircuitState::HalfOpen => {
+                circuit.trial = true;
+                Ok(true)
+            }
+        };
+        drop(circuit);
+        self.notify(transition);
+        ret.map(|trial| Permit {
+            breaker: self,
+            trial,
+        })
+    }
+}
+
+// the permit of the allowed call
+// only the trial call decides the state after half open
+struct Permit<'a, C: Client> {
+    breaker: &'a CircuitBreaker<C>,
+    trial: bool,
+}
+
+impl<C: Client> Drop for Permit<'_, C> {
+    fn drop(&mut self) {
+        // the trial call never finished, e.g. cancelled, allow another one
+        if self.trial {
+            let mut circuit = self.breaker.circuit.lock().unwrap();
+            if circuit.state == CircuitState::HalfOpen {
+                circuit.trial = false;
+            }
+        }
+    }
+}
+
+impl<C: Client> Permit<'_, C> {
+    // record the call result
+    fn release(mut self, failed: bool) {
+        let breaker = self.breaker;
+        let trial = std::mem::replace(&mut self.trial, false);
+        let mut circuit = breaker.circuit.lock().unwrap();
+        let transition = match circuit.state {
+            CircuitState::HalfOpen if trial && failed => circuit.set(CircuitState::Open),
+            CircuitState::HalfOpen if trial => circuit.set(CircuitState::Closed),
+            // the call is started before the circuit is opened
+            CircuitState::HalfOpen | CircuitState::Open => None,
+            CircuitState::Closed => {
+                let now = Instant::now();
+                circuit.outcomes.push_back((now, failed));
+                while let Some(&(t, _)) = circuit.outcomes.front() {
+                    if now.duration_since(t) < breaker.window {
+                        break;
+                    }
+                    circuit.outcomes.pop_front();
+                }
+                circuit.failures = if failed { circuit.failures + 1 } else { 0 };
+
+                let calls = circuit.outcomes.len();
+                let errors = circuit.outcomes.iter().filter(|o| o.1).count();
+                let high_rate = calls >= breaker.min_calls
+                    && errors as f64 >= breaker.error_rate * calls as f64;
+                if circuit.failures >= breaker.max_failures || (failed && high_rate) {
+                    circuit.set(CircuitState::Open)
+                } else {
+                    None
+                }
+            }
+        };
+        drop(circuit);
+        breaker.notify(transition);
+    }
+}
+
+impl<C: Client> Client for CircuitBreaker<C> {
+    fn call_service(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.call_with(|client| client.call_service(req))
+    }
+
+    fn call_idempotent(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.call_with(|client| client.call_idempotent(req))
+    }
+
+    fn call_with_retry(&self, req: ReqBuf, policy: &RetryPolicy) -> Result<Frame, Error> {
+        self.call_with(|client| client.call_with_retry(req, policy))
+    }
+
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        self.call_with(|client| client.call_cancellable(req, token))
+    }
+}
@@ -0,0 +1,231 @@
+use std::io::{self, ErrorKind};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::frame::{self, Frame, FRAME_MAX_LEN};
+
+/// the payload compression codec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// lz4 block format, fast with a moderate ratio
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// zstd with the default level, slower with a better ratio
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+// no codec is enabled without the features, the codec is never constructed
+#[cfg_attr(
+    not(any(feature = "lz4", feature = "zstd")),
+    allow(unused_variables, clippy::ptr_arg)
+)]
+impl Codec {
+    // the bit of the codec in the frame flags
+    fn flag(self) -> u8 {
+        match self {
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => 1,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => 2,
+        }
+    }
+
+    fn from_flag(flag: u8) -> io::Result<Self> {
+        match flag {
+            #[cfg(feature = "lz4")]
+            1 => Ok(Codec::Lz4),
+            #[cfg(feature = "zstd")]
+            2 => Ok(Codec::Zstd),
+            _ => Err(invalid(format!(
+                "unsupported compression codec. flag={}",
+                flag
+            ))),
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => Ok(lz4_flex::block::compress_prepend_size(data)),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => zstd::bulk::compress(data, 0),
+        }
+    }
+
+    // decompress the data and append it to the buf, fail if bigger than the limit
+    fn decompress(self, data: &[u8], buf: &mut Vec<u8>, limit: usize) -> io::Result<()> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => {
+                let (len, data) = lz4_flex::block::uncompressed_size(data).map_err(invalid)?;
+                if len > limit {
+                    return Err(invalid(format!("decompress too big payload. len={}", len)));
+                }
+                let start = buf.len();
+                buf.resize(start + len, 0);
+                let n =
+                    lz4_flex::block::decompress_into(data, &mut buf[start..]).map_err(invalid)?;
+                if n != len {
+                    return Err(invalid("decompress truncated payload"));
+                }
+                Ok(())
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => {
+                use std::io::Read;
+                let start = buf.len();
+                let decoder = zstd::stream::read::Decoder::with_buffer(data)?;
+                decoder.take(limit as u64 + 1).read_to_end(buf)?;
+                if buf.len() - start > limit {
+                    return Err(invalid("decompress too big payload"));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    let s = e.to_string();
+    error!("{}", s);
+    io::Error::new(ErrorKind::InvalidData, s)
+}
+
+/// decompress the payload with the codec in the frame flags
+pub(crate) fn decompress(flag: u8, data: &[u8], buf: &mut Vec<u8>) -> io::Result<()> {
+    // the payload len must be checked again after decompression
+    let limit = FRAME_MAX_LEN as usize - 16;
+    Codec::from_flag(flag)?.decompress(data, buf, limit)
+}
+
+/// payload compression settings
+/// each side advertises the accepted codecs in the frames it sends, and only
+/// compresses the frames with a codec that the peer has advertised
+#[derive(Debug, Clone)]
+pub struct Compression {
+    /// the accepted codecs in the order of preference
+    pub codecs: Vec<Codec>,
+    /// the payloads not bigger than this are sent uncompressed
+    pub threshold: usize,
+}
+
+impl Default for Compression {
+    /// accept all the enabled codecs, compress the payloads bigger than 1k
+    fn default() -> Self {
+        Compression {
+            codecs: vec![
+                #[cfg(feature = "lz4")]
+                Codec::Lz4,
+                #[cfg(feature = "zstd")]
+                Codec::Zstd,
+            ],
+            threshold: 1024,
+        }
+    }
+}
+
+impl Compression {
+    /// disable the compression, the frames are sent as is
+    pub fn none() -> Self {
+        Compression {
+            codecs: Vec::new(),
+            threshold: 0,
+        }
+    }
+
+    // the flags of all the accepted codecs
+    fn accept(&self) -> u8 {
+        self.codecs.iter().fold(0, |flags, c| flags | c.flag())
+    }
+
+    /// write the flags into the frame head and compress the payload
+    /// if the peer accepts one of our codecs
+    pub(crate) fn encode(&self, peer: u8, mut frame: Vec<u8>) -> Vec<u8> {
+        let accept = self.accept();
+        // keep the frame compatible with the peers that don't know the flags
+        if accept == 0 {
+            return frame;
+        }
+
+        let mut codec = 0;
+        let len = frame.len() - 16;
+        if len > self.threshold {
+            if let Some(c) = self.codecs.iter().find(|c| c.flag() & peer != 0) {
+                match c.compress(&frame[16..]) {
+                    // send the raw payload if it doesn't shrink
+                    Ok(data) if data.len() < len => {
+                        frame.truncate(16);
+                        frame.extend_from_slice(&data);
+                        codec = c.flag();
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("compress payload: err = {:?}", e),
+                }
+            }
+        }
+
+        let head = (frame.len() as u64 - 16) | (u64::from(accept) << 56) | (u64::from(codec) << 48);
+        frame[8..16].copy_from_slice(&head.to_be_bytes());
+        frame
+    }
+
+    /// encode the response frame for the request
+    /// the response is kept as is if the client doesn't negotiate the compression
+    /// the id is echoed as it's received, with the offer bit if any
+    pub(crate) fn respond(&self, req: &Frame, frame: Vec<u8>) -> Vec<u8> {
+        let mut frame = if req.negotiated() {
+            self.encode(req.accept(), frame)
+        } else {
+            frame
+        };
+        if req.offered() {
+            frame::offer(&mut frame);
+        }
+        frame
+    }
+}
+
+/// the per connection compression state of the client
+/// the server codecs are learned from the responses
+#[derive(Debug)]
+pub(crate) struct Compressor {
+    config: Compression,
+    peer: AtomicU8,
+}
+
+impl Compressor {
+    pub fn new(config: Compression) -> Self {
+        Compressor {
+            config,
+            peer: AtomicU8::new(0),
+        }
+    }
+
+    pub fn set_config(&mut self, config: Compression) {
+        self.config = config;
+    }
+
+    /// encode the request frame
+    /// only offer the compression until the server has sent the flags back
+    pub fn encode(&self, mut frame: Vec<u8>) -> Vec<u8> {
+        match self.peer.load(Ordering::Relaxed) {
+            _ if self.config.codecs.is_empty() => frame,
+            0 => {
+                frame::offer(&mut frame);
+                frame
+            }
+            peer => self.config.encode(peer, frame),
+        }
+    }
+
+    /// learn the codecs accepted by the server
+    pub fn observe(&self, rsp: &Frame) {
+        self.peer.store(rsp.accept(), Ordering::Relaxed);
+    }
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Compressor::new(Compression::none())
+    }
+}
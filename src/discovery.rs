@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Cursor, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::multiplex_client::MultiplexClient;
+use crate::stream_ext::StreamExt;
+use crate::udp_frag::MAX_DATAGRAM_SIZE;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use may::net::UdpSocket;
+use may::sync::Mutex;
+use may::{coroutine, go};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+// Announcement layout
+// magic(u32) + ttl_ms(u32) + transport(u8)
+// + name_len(u16) + name([u8; name_len]) + addr_len(u16) + addr([u8; addr_len])
+
+const MAGIC: u32 = 0x434e_5459;
+
+/// the default multicast group used for discovery
+pub const DISCOVERY_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 67, 78), 7667);
+
+/// the transport that a service is listening on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Tcp,
+    Udp,
+    Uds,
+}
+
+/// a service announced on the multicast group
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ServiceInfo {
+    /// the service name
+    pub name: String,
+    /// the transport of the service
+    pub transport: Transport,
+    /// the address of the service, a socket address or a uds path
+    pub addr: String,
+}
+
+impl ServiceInfo {
+    /// parse the addr as a socket address
+    /// this is only valid for the tcp and udp services
+    pub fn socket_addr(&self) -> io::Result<SocketAddr> {
+        self.addr
+            .parse()
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))
+    }
+
+    fn encode(&self, ttl: Duration) -> io::Result<Vec<u8>> {
+        fn invalid(what: &str) -> io::Error {
+            let s = format!("announcement {} is too big", what);
+            io::Error::new(ErrorKind::InvalidInput, s)
+        }
+
+        let ttl = u32::try_from(ttl.as_millis()).map_err(|_| invalid("ttl"))?;
+        let name_len = u16::try_from(self.name.len()).map_err(|_| invalid("name"))?;
+        let addr_len = u16::try_from(self.addr.len()).map_err(|_| invalid("addr"))?;
+        let mut buf = Vec::with_capacity(16 + self.name.len() + self.addr.len());
+        buf.write_u32::<BigEndian>(MAGIC).unwrap();
+        buf.write_u32::<BigEndian>(ttl).unwrap();
+        buf.write_u8(self.transport as u8).unwrap();
+        buf.write_u16::<BigEndian>(name_len).unwrap();
+        buf.write_all(self.name.as_bytes()).unwrap();
+        buf.write_u16::<BigEndian>(addr_len).unwrap();
+        buf.write_all(self.addr.as_bytes()).unwrap();
+        if buf.len() > MAX_DATAGRAM_SIZE {
+            return Err(invalid("packet"));
+        }
+        Ok(buf)
+    }
+
+    fn decode(buf: &[u8]) -> io::Result<(Self, Duration)> {
+        fn read_str(r: &mut Cursor<&[u8]>) -> io::Result<String> {
+            let len = r.read_u16::<BigEndian>()? as usize;
+            let mut s = vec![0; len];
+            r.read_exact(&mut s)?;
+            String::from_utf8(s).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+        }
+
+        let mut r = Cursor::new(buf);
+        if r.read_u32::<BigEndian>()? != MAGIC {
+            return Err(io::Error::new(ErrorKind::InvalidData, "invalid magic"));
+        }
+        let ttl = Duration::from_millis(r.read_u32::<BigEndian>()? as u64);
+        let transport = match r.read_u8()? {
+            0 => Transport::Tcp,
+            1 => Transport::Udp,
+            2 => Transport::Uds,
+            ty => {
+                let s = format!("invalid transport. ty={}", ty);
+                return Err(io::Error::new(ErrorKind::InvalidData, s));
+            }
+        };
+        let name = read_str(&mut r)?;
+        let addr = read_str(&mut r)?;
+        Ok((
+            ServiceInfo {
+                name,
+                transport,
+                addr,
+            },
+            ttl,
+        ))
+    }
+}
+
+/// periodically announce the services on the multicast group
+#[derive(Debug)]
+pub struct Announcer {
+    // the announcing coroutine
+    worker: Option<coroutine::JoinHandle<()>>,
+}
+
+impl Drop for Announcer {
+    fn drop(&mut self) {
+        if let Some(h) = self.worker.take() {
+            unsafe { h.coroutine().cancel() };
+            h.join().ok();
+        }
+    }
+}
+
+impl Announcer {
+    /// start announcing the services on the default group every second
+    pub fn start(services: Vec<ServiceInfo>) -> io::Result<Self> {
+        Announcer::start_with(
+            DISCOVERY_GROUP,
+            Ipv4Addr::UNSPECIFIED,
+            services,
+            Duration::from_secs(1),
+        )
+    }
+
+    /// start announcing the services on the group through the interface
+    /// the announced ttl is three times of the interval
+    pub fn start_with(
+        group: SocketAddrV4,
+        interface: Ipv4Addr,
+        services: Vec<ServiceInfo>,
+        interval: Duration,
+    ) -> io::Result<Self> {
+        let sock = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        sock.set_multicast_if_v4(&interface)?;
+        sock.set_multicast_loop_v4(true)?;
+        sock.bind(&SockAddr::from(SocketAddrV4::new(interface, 0)))?;
+        let sock = unsafe { UdpSocket::from_raw_fd(sock.into_raw_fd()) };
+
+        let packets = services
+            .iter()
+            .map(|s| s.encode(interval * 3))
+            .collect::<io::Result<Vec<_>>>()?;
+        let worker = go!(
+            coroutine::Builder::new().name("DiscoveryAnnouncer".to_owned()),
+            move || loop {
+                for packet in packets.iter() {
+                    if let Err(err) = sock.send_to(packet, group) {
+                        error!("discovery announce failed, err={:?}", err);
+                    }
+                }
+                coroutine::sleep(interval);
+            }
+        )?;
+
+        Ok(Announcer {
+            worker: Some(worker),
+        })
+    }
+}
+
+type Services = HashMap<ServiceInfo, Instant>;
+
+/// collect the live services announced on the multicast group
+#[derive(Debug)]
+pub struct Resolver {
+    // the live services and their expire time
+    services: Arc<Mutex<Services>>,
+    // the listening coroutine
+    listener: Option<coroutine::JoinHandle<()>>,
+}
+
+impl Drop for Resolver {
+    fn drop(&mut self) {
+        if let Some(h) = self.listener.take() {
+            unsafe { h.coroutine().cancel() };
+            h.join().ok();
+        }
+    }
+}
+
+impl Resolver {
+    /// listen on the default group
+    pub fn start() -> io::Result<Self> {
+        Resolver::start_with(DISCOVERY_GROUP, Ipv4Addr::UNSPECIFIED)
+    }
+
+    /// listen on the group through the interface
+    pub fn start_with(group: SocketAddrV4, interface: Ipv4Addr) -> io::Result<Self> {
+        // allow multiple resolvers on the same host
+        let sock = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        sock.set_reuse_address(true)?;
+        let any = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, group.port());
+        sock.bind(&SockAddr::from(any))?;
+        sock.join_multicast_v4(group.ip(), &interface)?;
+        let sock = unsafe { UdpSocket::from_raw_fd(sock.into_raw_fd()) };
+
+        let services = Arc::new(Mutex::new(Services::new()));
+        let live = services.clone();
+        let listener = go!(
+            coroutine::Builder::new().name("DiscoveryResolver".to_owned()),
+            move || {
+                // any announcement fits in one datagram
+                let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+                loop {
+                    let (len, addr) = t!(sock.recv_from(&mut buf));
+                    let (info, ttl) = t!(ServiceInfo::decode(&buf[..len]));
+                    info!("discovery: {:?} from {:?}", info, addr);
+
+                    let now = Instant::now();
+                    let mut services = live.lock().unwrap();
+                    services.retain(|_, expire| *expire > now);
+                    services.insert(info, now + ttl);
+                }
+            }
+        )?;
+
+        Ok(Resolver {
+            services,
+            listener: Some(listener),
+        })
+    }
+
+    /// return all the live services
+    pub fn services(&self) -> Vec<ServiceInfo> {
+        let now = Instant::now();
+        let services = self.services.lock().unwrap();
+        services
+            .iter()
+            .filter(|(_, expire)| **expire > now)
+            .map(|(info, _)| info.clone())
+            .collect()
+    }
+
+    /// return the live instances of the named service
+    pub fn resolve(&self, name: &str) -> Vec<ServiceInfo> {
+        let mut services = self.services();
+        services.retain(|info| info.name == name);
+        services
+    }
+
+    /// create a multiplex client to one of the live instances of the named service
+    /// the instances are tried in turn until the connect function succeeds
+    pub fn connect<S, F>(&self, name: &str, mut connect: F) -> io::Result<MultiplexClient<S>>
+    where
+        S: StreamExt,
+        F: FnMut(&ServiceInfo) -> io::Result<S>,
+    {
+        let mut last_err = None;
+        for info in self.resolve(name) {
+            match connect(&info) {
+                Ok(stream) => return MultiplexClient::new(stream),
+                Err(err) => {
+                    error!("discovery: connect to {:?} failed, err={:?}", info, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            let s = format!("no live service found. name={}", name);
+            io::Error::new(ErrorKind::NotFound, s)
+        }))
+    }
+}
@@ -40,6 +40,26 @@ pub enum Error {
     /// Typically this indicates that the server is not healthy
     #[error("The server returns an status error due to different reasons: {0}")]
     Status(String),
+    /// The connection to the server is closed.
+    ///
+    /// The calls that are waiting for the responses and the later calls fail with it
+    #[error("The connection to the server is closed")]
+    Disconnected,
+    /// The circuit breaker is open, the call is not sent.
+    ///
+    /// The server has been failing recently, the calls are allowed again after a while
+    #[error("The circuit breaker is open")]
+    CircuitOpen,
+    /// There is no node to route the call to.
+    ///
+    /// The sharding client has no node, add the nodes before the calls
+    #[error("There is no node to route the call to")]
+    NoNodes,
+    /// The call is abandoned by its `CancelToken`.
+    ///
+    /// The response is discarded if it comes later
+    #[error("The call is cancelled")]
+    Cancelled,
 }
 
 /// A serializable, server-supplied error.
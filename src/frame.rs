@@ -1,11 +1,21 @@
 use std::io::{self, Cursor, ErrorKind, Read, Write};
 
+use crate::compress;
 use crate::{Error, WireError};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 // Frame layout
 // id(u64) + len(u64) + payload([u8; len])
 
+// the top two bytes of len are the compression flags, zero if not negotiated
+// accept(u8) + codec(u8) + len(u48)
+// accept is the codecs that the sender accepts, codec is the one used for the payload
+//
+// the old peers reject the frames with the flags, so the client offers the compression
+// by the top bit of the request id first, the bit is reserved and never used by the ids
+// the servers echo the id back as is, both the old ones and the new ones
+// the flags are only sent to the peer that has sent the flags or the offer
+
 // req frame layout
 // id(u64) + len(u64) + req_data([u8; len])
 
@@ -13,7 +23,21 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 // id(u64) + len(u64) + ty(u8) + len1(u64) + rsp_data([u8; len1])
 
 // max frame len
-const FRAME_MAX_LEN: u64 = 1024 * 1024;
+pub(crate) const FRAME_MAX_LEN: u64 = 1024 * 1024;
+// the len bits without the compression flags
+const LEN_MASK: u64 = (1 << 48) - 1;
+// the reserved top bit of the request id that offers the compression
+const OFFER_BIT: u64 = 1 << 63;
+
+/// mark the encoded frame as offering the compression, or echo the offer back
+pub(crate) fn offer(frame: &mut [u8]) {
+    frame[0] |= (OFFER_BIT >> 56) as u8;
+}
+
+/// the frame id without the offer bit
+pub(crate) fn strip_offer(id: u64) -> u64 {
+    id & !OFFER_BIT
+}
 
 /// raw frame wrapper, low level protocol
 /// TODO: add check sum check
@@ -23,16 +47,26 @@ pub struct Frame {
     pub id: u64,
     /// payload data
     data: Vec<u8>,
+    // the codecs that the peer accepts
+    accept: u8,
+    // the id is sent with the offer bit
+    offered: bool,
+    // the peer understands the compression flags
+    negotiated: bool,
 }
 
 impl Frame {
     /// decode a frame from the reader
     pub fn decode_from<R: Read>(r: &mut R) -> io::Result<Self> {
         use std::mem::MaybeUninit;
-        let id = r.read_u64::<BigEndian>()?;
+        let raw_id = r.read_u64::<BigEndian>()?;
+        let id = strip_offer(raw_id);
         info!("decode id = {:?}", id);
 
-        let len = r.read_u64::<BigEndian>()? + 16;
+        let head = r.read_u64::<BigEndian>()?;
+        let accept = (head >> 56) as u8;
+        let codec = (head >> 48) as u8;
+        let len = (head & LEN_MASK) + 16;
         info!("decode len = {:?}", len);
 
         if len > FRAME_MAX_LEN {
@@ -49,13 +83,57 @@ impl Frame {
         };
         r.read_exact(&mut data[16..])?;
 
+        // the payload is decompressed transparently
+        if codec != 0 {
+            let mut buf = vec![0; 16];
+            compress::decompress(codec, &data[16..], &mut buf)?;
+            data = buf;
+        }
+
         // blow can be skipped, we don't need them in the buffer
+        let len = data.len() as u64;
         let mut cursor = Cursor::new(data);
         cursor.write_u64::<BigEndian>(id).unwrap();
         cursor.write_u64::<BigEndian>(len - 16).unwrap();
         let data = cursor.into_inner();
 
-        Ok(Frame { id, data })
+        let offered = raw_id != id;
+        Ok(Frame {
+            id,
+            data,
+            accept,
+            offered,
+            negotiated: offered || head >> 48 != 0,
+        })
+    }
+
+    // whether the id is sent with the offer bit, the response must echo it
+    pub(crate) fn offered(&self) -> bool {
+        self.offered
+    }
+
+    // the codecs that the peer accepts, zero if it doesn't negotiate the compression
+    pub(crate) fn accept(&self) -> u8 {
+        self.accept
+    }
+
+    // whether the peer understands the compression flags, by the flags or the offer
+    pub(crate) fn negotiated(&self) -> bool {
+        self.negotiated
+    }
+
+    // the total len of the first encoded frame in the buf, None if it's not complete
+    #[cfg(feature = "websocket")]
+    pub(crate) fn encoded_len(buf: &[u8]) -> Option<usize> {
+        if buf.len() < 16 {
+            return None;
+        }
+        let head = (&buf[8..16]).read_u64::<BigEndian>().unwrap();
+        let len = (head & LEN_MASK) as usize + 16;
+        if buf.len() < len {
+            return None;
+        }
+        Some(len)
     }
 
     /// convert self into raw buf that can be re-send as a frame
@@ -112,6 +190,7 @@ impl Frame {
 }
 
 /// req frame buffer that can be serialized into
+#[derive(Clone)]
 pub struct ReqBuf(Cursor<Vec<u8>>);
 
 impl Default for ReqBuf {
@@ -131,10 +210,13 @@ impl ReqBuf {
     }
 
     /// convert self into raw buf that can be send as a frame
+    /// the top bit of the id is reserved for the compression offer,
+    /// it's masked out, so the ids that differ only in the top bit are the same
     pub fn finish(self, id: u64) -> Vec<u8> {
         let mut cursor = self.0;
         let len = cursor.get_ref().len() as u64;
         assert!(len <= FRAME_MAX_LEN);
+        let id = strip_offer(id);
 
         // write from start
         cursor.set_position(0);
@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::cancel::CancelToken;
+use crate::errors::Error;
+use crate::frame::{Frame, ReqBuf};
+use crate::retry::RetryPolicy;
+use crate::Client;
+
+use may::sync::mpsc::{self, RecvTimeoutError, Sender};
+use may::sync::Mutex;
+use may::{coroutine, go};
+
+// the number of the recent latencies that decide the hedge delay
+const MAX_SAMPLES: usize = 256;
+
+// the result of one copy of the request, tagged with whether it's the hedge
+type Attempt = (bool, Result<Frame, Error>);
+
+/// how often the hedges are sent and win
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HedgeStats {
+    /// the number of the hedged calls
+    pub calls: usize,
+    /// the number of the calls that sent the hedge
+    pub fired: usize,
+    /// the number of the calls that are answered by the hedge
+    pub won: usize,
+}
+
+/// client that sends a second copy of the slow calls to cut the tail latency
+/// the wrapped client should spread the calls over the replicas,
+/// the `BalanceClient` never sends the hedge to the endpoint of the first copy
+/// the copy that loses is cancelled by `Client::call_cancellable`,
+/// e.g. the multiplexed clients abandon the call and drop its late response
+#[derive(Debug)]
+pub struct HedgeClient<C> {
+    client: Arc<C>,
+    // the hedge is sent after the latency of this percentile
+    percentile: f64,
+    // the hedge is never sent earlier than this
+    min_delay: Duration,
+    // the latencies of the recent successful calls
+    latencies: Mutex<VecDeque<Duration>>,
+    calls: AtomicUsize,
+    fired: AtomicUsize,
+    won: AtomicUsize,
+}
+
+impl<C: Client + Send + Sync + 'static> HedgeClient<C> {
+    /// wrap the client, the hedge delay is the p95 latency and at least 5ms
+    pub fn new(client: C) -> Self {
+        HedgeClient {
+            client: Arc::new(client),
+            percentile: 0.95,
+            min_delay: Duration::from_millis(5),
+            latencies: Mutex::new(VecDeque::with_capacity(MAX_SAMPLES)),
+            calls: AtomicUsize::new(0),
+            fired: AtomicUsize::new(0),
+            won: AtomicUsize::new(0),
+        }
+    }
+
+    /// get the wrapped client
+    pub fn get_ref(&self) -> &C {
+        &self.client
+    }
+
+    /// send the hedge after the latency of the percentile, e.g. 0.95
+    /// of the recent calls, but not earlier than `min_delay`
+    pub fn set_delay(&mut self, percentile: f64, min_delay: Duration) {
+        self.percentile = percentile.clamp(0.0, 1.0);
+        self.min_delay = min_delay;
+    }
+
+    /// the current delay before sending the hedge
+    pub fn delay(&self) -> Duration {
+        let mut latencies: Vec<_> = self.latencies.lock().unwrap().iter().cloned().collect();
+        if latencies.is_empty() {
+            return self.min_delay;
+        }
+        latencies.sort_unstable();
+        let i = ((latencies.len() - 1) as f64 * self.percentile).round() as usize;
+        latencies[i].max(self.min_delay)
+    }
+
+    /// the statistics of the hedged calls
+    pub fn stats(&self) -> HedgeStats {
+        HedgeStats {
+            calls: self.calls.load(Ordering::Relaxed),
+            fired: self.fired.load(Ordering::Relaxed),
+            won: self.won.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record(&self, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.len() == MAX_SAMPLES {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    // send the copy in a new coroutine, it's abandoned once the returned token is cancelled
+    fn send(
+        &self,
+        req: ReqBuf,
+        tx: Sender<Attempt>,
+        first: Option<&CancelToken>,
+    ) -> Result<Arc<CancelToken>, Error> {
+        let client = self.client.clone();
+        let hedge = first.is_some();
+        let token = Arc::new(first.map_or_else(CancelToken::new, CancelToken::hedge_of));
+        let cancel = token.clone();
+        go!(
+            coroutine::Builder::new().name("HedgeClientCall".to_owned()),
+            move || {
+                tx.send((hedge, client.call_cancellable(req, &cancel))).ok();
+            }
+        )?;
+        Ok(token)
+    }
+
+    /// call the server, and send a copy of the request if it's not answered
+    /// within the hedge delay, the first response wins and the other is cancelled
+    /// only use it for the requests that are safe to run more than once
+    pub fn call_hedged(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.call_with(req, None)
+    }
+
+    // the hedged call, both copies are abandoned once the outer token is cancelled
+    fn call_with(&self, req: ReqBuf, outer: Option<&CancelToken>) -> Result<Frame, Error> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+        let (tx, rx) = mpsc::channel();
+        // the copies are cancelled with the outer token, the hooks are removed after the call
+        let mut guards = Vec::new();
+        let mut watch = |token: &Arc<CancelToken>| {
+            if let Some(outer) = outer {
+                let token = token.clone();
+                guards.push(outer.on_cancel(move || token.cancel()));
+            }
+        };
+        let first = self.send(req.clone(), tx.clone(), None)?;
+        watch(&first);
+        let mut tokens = vec![first.clone()];
+
+        // the copies only exit without the result if the wrapped client panics
+        let lost = || Err(io::Error::new(ErrorKind::Other, "hedged call is lost").into());
+        let ret = match rx.recv_timeout(self.delay()) {
+            Ok((_, ret)) => ret,
+            Err(RecvTimeoutError::Disconnected) => lost(),
+            Err(RecvTimeoutError::Timeout) => {
+                // keep waiting for the first copy if the hedge can't be sent
+                match self.send(req, tx, Some(&first)) {
+                    Ok(token) => {
+                        self.fired.fetch_add(1, Ordering::Relaxed);
+                        watch(&token);
+                        tokens.push(token);
+                    }
+                    Err(e) => error!("hedge client: send hedge err = {:?}", e),
+                }
+                // wait for the other copy if the first one failed
+                let (mut hedged, mut ret) = rx.recv().unwrap_or_else(|_| (false, lost()));
+                if ret.is_err() {
+                    if let Ok(other) = rx.recv() {
+                        hedged = other.0;
+                        ret = other.1;
+                    }
+                }
+                if hedged && ret.is_ok() {
+                    self.won.fetch_add(1, Ordering::Relaxed);
+                }
+                ret
+            }
+        };
+        // cancel the copy that is still in flight, it does nothing to the answered one
+        for token in tokens {
+            token.cancel();
+        }
+
+        if ret.is_ok() {
+            self.record(start.elapsed());
+        }
+        ret
+    }
+}
+
+impl<C: Client + Send + Sync + 'static> Client for HedgeClient<C> {
+    /// the call is not hedged, but its latency is recorded for the hedge delay
+    fn call_service(&self, req: ReqBuf) -> Result<Frame, Error> {
+        let start = Instant::now();
+        let ret = self.client.call_service(req);
+        if ret.is_ok() {
+            self.record(start.elapsed());
+        }
+        ret
+    }
+
+    /// the call is hedged, the same as `call_hedged`
+    fn call_idempotent(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.call_hedged(req)
+    }
+
+    /// the call is not hedged, the wrapped client retries it with the same frame id
+    fn call_with_retry(&self, req: ReqBuf, policy: &RetryPolicy) -> Result<Frame, Error> {
+        let start = Instant::now();
+        let ret = self.client.call_with_retry(req, policy);
+        if ret.is_ok() {
+            self.record(start.elapsed());
+        }
+        ret
+    }
+
+    /// the call is hedged, both copies are abandoned once the token is cancelled
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        self.call_with(req, Some(token))
+    }
+}
@@ -1,16 +1,52 @@
 #[macro_use]
 extern crate log;
 
+pub use balance_client::{Balance, BalanceClient};
+pub use cancel::{CancelGuard, CancelToken};
+pub use circuit_breaker::{CircuitBreaker, CircuitState};
+pub use compress::{Codec, Compression};
 pub use errors::{Error, WireError};
 pub use frame::{Frame, ReqBuf, RspBuf};
-pub use multiplex_client::MultiplexClient;
-pub use server::{ServerInstance, TcpServer, UdpServer};
+pub use hedge_client::{HedgeClient, HedgeStats};
+pub use loopback::{LoopbackReader, LoopbackStream, LoopbackWriter};
+pub use multiplex_client::{CallHandle, MultiplexClient};
+#[cfg(feature = "noise")]
+pub use noise::{NoiseConfig, NoiseReader, NoiseStream, NoiseWriter};
+pub use pool_client::{PoolClient, PoolConn};
+pub use reconnect_client::{ConnState, ReconnectClient};
+pub use retry::{is_retryable, RetryClient, RetryPolicy};
+pub use server::{ServerInstance, StreamServer, TcpServer, UdpServer};
+pub use shard_client::{ShardClient, ShardKey};
 pub use stream_client::StreamClient;
-pub use stream_ext::StreamExt;
+pub use stream_ext::{SocketReader, SocketWriter, StreamExt};
 pub use udp_client::UdpClient;
+pub use udp_frag::{UdpConfig, MAX_DATAGRAM_SIZE};
+pub use udp_multiplex_client::UdpMultiplexClient;
 
 #[cfg(unix)]
-pub use server::UdsServer;
+pub use child_client::ChildClient;
+#[cfg(unix)]
+pub use discovery::{Announcer, Resolver, ServiceInfo, Transport, DISCOVERY_GROUP};
+#[cfg(unix)]
+pub use pipe::{PipeReader, PipeStream, PipeWriter};
+#[cfg(target_os = "linux")]
+pub use seqpacket_client::SeqPacketClient;
+#[cfg(feature = "noise")]
+pub use server::NoiseServer;
+#[cfg(feature = "tls")]
+pub use server::TlsServer;
+#[cfg(target_os = "linux")]
+pub use server::UdsSeqPacketServer;
+#[cfg(unix)]
+pub use server::{UdsDatagramServer, UdsServer, UDS_DGRAM_BUF_SIZE};
+#[cfg(target_os = "linux")]
+pub use shm::{ShmFds, ShmReader, ShmStream, ShmWriter};
+#[cfg(feature = "tls")]
+pub use tls::{PeerIdentity, TlsAcceptor, TlsConnector, TlsReader, TlsStream, TlsWriter};
+#[cfg(unix)]
+pub use uds_datagram_client::UdsDatagramClient;
+#[cfg(feature = "websocket")]
+pub use websocket::{WsReader, WsStream, WsWriter};
 
 macro_rules! t {
     ($e: expr) => {
@@ -30,6 +66,31 @@ pub trait Client {
     /// the request must be encoded into the ReqBuf
     /// the response is the raw frame, you should parsing it into final response
     fn call_service(&self, req: ReqBuf) -> Result<Frame, Error>;
+
+    /// call the server with the request that is safe to run more than once
+    /// the clients that retry or hedge the calls only do it for these calls
+    /// the default implementation is the same as `call_service`
+    fn call_idempotent(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.call_service(req)
+    }
+
+    /// call the server with the idempotent request, and retry it by the policy
+    /// the default implementation sends each attempt by `call_idempotent` as a new frame,
+    /// the udp clients resend the same frame id, so the server with the response
+    /// cache enabled doesn't run the service again, the wrapper clients forward
+    /// the call to the client they wrap
+    fn call_with_retry(&self, req: ReqBuf, policy: &RetryPolicy) -> Result<Frame, Error> {
+        policy.retry(|| self.call_idempotent(req.clone()))
+    }
+
+    /// call the server with the idempotent request that can be abandoned by the token
+    /// the call fails with `Error::Cancelled` once the token is cancelled
+    /// the default implementation ignores the token and runs the call to the end,
+    /// the wrapper clients forward the token to the client they wrap
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        let _ = token;
+        self.call_idempotent(req)
+    }
 }
 
 /// must impl this trait for your server
@@ -41,20 +102,93 @@ pub trait Server: Send + Sync + Sized + 'static {
     /// application error should be encapsulated into the RspBuf
     /// here passed in a self ref to impl stateful service if you want
     fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError>;
+
+    /// the compression settings for the responses
+    /// the responses are only compressed for the clients that negotiate it
+    fn compression(&self) -> Compression {
+        Compression::default()
+    }
 }
 
+/// Provides load balancing client
+mod balance_client;
+/// Provides the token to abandon the calls
+mod cancel;
+/// Provides child process client
+#[cfg(unix)]
+mod child_client;
+/// Provides circuit breaker client
+mod circuit_breaker;
+/// payload compression
+mod compress;
+/// multicast service discovery
+#[cfg(unix)]
+mod discovery;
 /// Provides a few different error types
 mod errors;
+/// owned raw fd
+#[cfg(unix)]
+mod fd;
 /// raw frame protocol
 mod frame;
+/// Provides hedged requests client
+mod hedge_client;
+/// in-process loopback stream
+mod loopback;
 mod multiplex_client;
+/// noise encrypted stream and udp frames
+#[cfg(feature = "noise")]
+mod noise;
+/// pipe stream for the subprocess hosted services
+#[cfg(unix)]
+mod pipe;
+/// Provides pooled client
+mod pool_client;
 mod queued_writer;
+/// Provides reconnecting multiplexed client
+mod reconnect_client;
+/// Provides retry policy and client
+mod retry;
+/// unix seqpacket socket
+#[cfg(target_os = "linux")]
+mod seqpacket;
+/// Provides unix seqpacket client
+#[cfg(target_os = "linux")]
+mod seqpacket_client;
 /// Provides server framework
 mod server;
+/// Provides consistent hash sharding client
+mod shard_client;
+/// shared memory stream
+#[cfg(target_os = "linux")]
+mod shm;
 
 /// Provide stream client
 mod stream_client;
+/// tls stream based on rustls
+#[cfg(feature = "tls")]
+mod tls;
+/// batched datagram io for the udp server
+mod udp_batch;
+/// udp response cache for the retransmitted requests
+mod udp_cache;
 /// Provides udp client
 mod udp_client;
+/// udp fragmentation and reassembly
+mod udp_frag;
+/// Provides multiplexed udp client
+mod udp_multiplex_client;
+/// seal the udp frames if configured
+mod udp_seal;
+/// Provides unix datagram socket client
+#[cfg(unix)]
+mod uds_datagram_client;
+/// shared random helpers
+mod util;
+/// vectored writes of the buffers
+mod vec_bufs;
+/// websocket stream that carries the frames in binary messages
+#[cfg(feature = "websocket")]
+mod websocket;
 
 mod stream_ext;
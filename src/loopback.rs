@@ -0,0 +1,182 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::stream_ext::StreamExt;
+
+use may::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use may::sync::Mutex;
+
+// the read half that shared by the clones of one side
+struct ReadHalf {
+    rx: Receiver<Vec<u8>>,
+    // the data that received but not read yet
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+/// in-process duplex stream based on coroutine channels
+/// it's useful for testing and embedding services without sockets
+pub struct LoopbackStream {
+    tx: Sender<Vec<u8>>,
+    rx: Arc<Mutex<ReadHalf>>,
+    timeout: Option<Duration>,
+}
+
+impl std::fmt::Debug for LoopbackStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("LoopbackStream")
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl LoopbackStream {
+    /// create a connected pair of loopback streams
+    pub fn pair() -> (LoopbackStream, LoopbackStream) {
+        let (tx0, rx0) = mpsc::channel();
+        let (tx1, rx1) = mpsc::channel();
+        (LoopbackStream::new(tx0, rx1), LoopbackStream::new(tx1, rx0))
+    }
+
+    fn new(tx: Sender<Vec<u8>>, rx: Receiver<Vec<u8>>) -> Self {
+        let rx = ReadHalf {
+            rx,
+            buf: Vec::new(),
+            pos: 0,
+        };
+        LoopbackStream {
+            tx,
+            rx: Arc::new(Mutex::new(rx)),
+            timeout: None,
+        }
+    }
+
+    /// create a new handle of the stream
+    /// the handles share the underlying stream
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(LoopbackStream {
+            tx: self.tx.clone(),
+            rx: self.rx.clone(),
+            timeout: self.timeout,
+        })
+    }
+}
+
+// read from the shared half with the timeout
+fn read_half(rx: &Mutex<ReadHalf>, timeout: Option<Duration>, buf: &mut [u8]) -> io::Result<usize> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+
+    let mut half = rx.lock().unwrap();
+    if half.pos == half.buf.len() {
+        let data = match timeout {
+            None => half.rx.recv().ok(),
+            Some(timeout) => match half.rx.recv_timeout(timeout) {
+                Ok(data) => Some(data),
+                Err(RecvTimeoutError::Timeout) => return Err(ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => None,
+            },
+        };
+        match data {
+            Some(data) => {
+                half.buf = data;
+                half.pos = 0;
+            }
+            // all the peers are gone
+            None => return Ok(0),
+        }
+    }
+
+    let pos = half.pos;
+    let n = buf.len().min(half.buf.len() - pos);
+    buf[..n].copy_from_slice(&half.buf[pos..pos + n]);
+    half.pos += n;
+    Ok(n)
+}
+
+fn write_half(tx: &Sender<Vec<u8>>, buf: &[u8]) -> io::Result<usize> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    tx.send(buf.to_vec())
+        .map_err(|_| io::Error::from(ErrorKind::BrokenPipe))?;
+    Ok(buf.len())
+}
+
+impl Read for LoopbackStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_half(&self.rx, self.timeout, buf)
+    }
+}
+
+impl Write for LoopbackStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_half(&self.tx, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl StreamExt for LoopbackStream {
+    type Reader = LoopbackReader;
+    type Writer = LoopbackWriter;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        let reader = LoopbackReader {
+            rx: self.rx,
+            timeout: self.timeout,
+        };
+        Ok((reader, LoopbackWriter { tx: self.tx }))
+    }
+
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = Some(timeout);
+        Ok(())
+    }
+}
+
+/// the read half of the `LoopbackStream`
+pub struct LoopbackReader {
+    rx: Arc<Mutex<ReadHalf>>,
+    timeout: Option<Duration>,
+}
+
+impl std::fmt::Debug for LoopbackReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("LoopbackReader")
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Read for LoopbackReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_half(&self.rx, self.timeout, buf)
+    }
+}
+
+/// the write half of the `LoopbackStream`
+/// the peer reads EOF once it and the other handles of the stream are dropped
+pub struct LoopbackWriter {
+    tx: Sender<Vec<u8>>,
+}
+
+impl std::fmt::Debug for LoopbackWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("LoopbackWriter")
+    }
+}
+
+impl Write for LoopbackWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_half(&self.tx, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,20 +1,56 @@
+use std::collections::HashMap;
 use std::io::{self, BufReader};
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::cancel::CancelToken;
+use crate::compress::{Compression, Compressor};
 use crate::errors::Error;
 use crate::frame::{Frame, ReqBuf};
 use crate::queued_writer::QueuedWriter;
 use crate::stream_ext::StreamExt;
 use crate::Client;
 
+use may::sync::{Condvar, Mutex};
 use may::{coroutine, go};
 use may_waiter::TokenWaiter;
+
+#[derive(Debug, Default)]
+struct Calls {
+    // the frame id on the wire to the waiter id, each waiter is set at most once
+    // the waiter ids are reused, so they are never sent to the server
+    ids: HashMap<u64, usize>,
+    // no more calls are accepted after the listener exits
+    closed: bool,
+}
+
+// the calls that are waiting for the responses
+#[derive(Debug, Default)]
+struct Pending {
+    calls: Mutex<Calls>,
+    // notified when any call is answered
+    ready: Condvar,
+}
+
+// the response or the error that wakes the call
+type Waiter = TokenWaiter<Result<Frame, Error>>;
+
+// the index of the answered call and its result
+type Answered = (usize, Result<Frame, Error>);
+
 #[derive(Debug)]
 pub struct MultiplexClient<S: StreamExt> {
     // default timeout is 10s
     timeout: Option<Duration>,
     // the connection
-    sock: QueuedWriter<S>,
+    sock: QueuedWriter<S::Writer>,
+    // compress the requests if negotiated
+    compressor: Compressor,
+    // the frame id of the next request, never reused on the connection
+    next_id: AtomicU64,
+    // the calls that are waiting for the responses
+    pending: Arc<Pending>,
     // the listening coroutine
     listener: Option<coroutine::JoinHandle<()>>,
 }
@@ -32,10 +68,12 @@ impl<S: StreamExt> Drop for MultiplexClient<S> {
 impl<S: StreamExt> MultiplexClient<S> {
     /// connect to the server address
     pub fn new(stream: S) -> io::Result<Self> {
-        // here we must clone the socket for read
+        // here we must split the stream for read
         // we can't share it between coroutines
-        let stream1 = stream.try_clone()?;
-        let mut r_stream = BufReader::new(stream1);
+        let (r_stream, w_stream) = stream.split()?;
+        let mut r_stream = BufReader::new(r_stream);
+        let pending = Arc::new(Pending::default());
+        let waiting = pending.clone();
         let listener = go!(
             coroutine::Builder::new().name("MultiPlexClientListener".to_owned()),
             move || {
@@ -53,16 +91,41 @@ impl<S: StreamExt> MultiplexClient<S> {
                     };
                     info!("receive rsp, id={}", rsp_frame.id);
 
-                    // set the wait req
-                    let id = unsafe { may_waiter::ID::from_usize(rsp_frame.id as usize) };
-                    TokenWaiter::set_rsp(id, rsp_frame);
+                    // set the wait req while locked, so the waiter is not dropped
+                    // ignore the response that nobody is waiting for, e.g. timed out
+                    let mut calls = waiting.calls.lock().unwrap();
+                    match calls.ids.remove(&rsp_frame.id) {
+                        Some(id) => {
+                            let id = unsafe { may_waiter::ID::from_usize(id) };
+                            Waiter::set_rsp(id, Ok(rsp_frame));
+                        }
+                        None => {
+                            warn!("unknown rsp id={}", rsp_frame.id);
+                            continue;
+                        }
+                    }
+                    drop(calls);
+                    waiting.ready.notify_all();
+                }
+
+                // fail the waiting calls, no response would come
+                let mut calls = waiting.calls.lock().unwrap();
+                calls.closed = true;
+                for (_, id) in calls.ids.drain() {
+                    let id = unsafe { may_waiter::ID::from_usize(id) };
+                    Waiter::set_rsp(id, Err(Error::Disconnected));
                 }
+                drop(calls);
+                waiting.ready.notify_all();
             }
         )?;
 
         Ok(MultiplexClient {
             timeout: None,
-            sock: QueuedWriter::new(stream),
+            sock: QueuedWriter::new(w_stream),
+            compressor: Compressor::default(),
+            next_id: AtomicU64::new(0),
+            pending,
             listener: Some(listener),
         })
     }
@@ -72,21 +135,259 @@ impl<S: StreamExt> MultiplexClient<S> {
     pub fn set_timeout(&mut self, timeout: Duration) {
         self.timeout = Some(timeout);
     }
+
+    // the connection is closed if the listener has exited
+    pub(crate) fn is_closed(&self) -> bool {
+        match self.listener {
+            Some(ref h) => h.is_done(),
+            None => true,
+        }
+    }
+
+    // block until the listener has exited
+    pub(crate) fn wait_closed(&self) {
+        if let Some(ref h) = self.listener {
+            h.wait();
+        }
+    }
+
+    /// set the compression settings, the compression is disabled by default
+    /// the requests are compressed once the server accepts one of the codecs
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compressor.set_config(compression);
+    }
+
+    /// send the request without waiting for the response
+    /// the response is got from the returned handle later,
+    /// so one coroutine can have many calls in flight
+    pub fn start_call(&self, req: ReqBuf) -> Result<CallHandle<'_, S>, Error> {
+        let waiter = Waiter::new();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        info!("request id = {}", id);
+
+        // send the request
+        let buf = self.compressor.encode(req.finish(id));
+
+        {
+            let mut calls = self.pending.calls.lock().unwrap();
+            // don't write into the dead connection
+            if calls.closed {
+                return Err(Error::Disconnected);
+            }
+            calls.ids.insert(id, waiter.id().unwrap().into());
+        }
+        self.sock.write(buf);
+
+        Ok(CallHandle {
+            client: self,
+            waiter,
+            id,
+        })
+    }
+
+    /// wait for all the calls, the results are in the order of the handles
+    /// the default timeout applies to all the calls together
+    pub fn wait_all(&self, handles: Vec<CallHandle<'_, S>>) -> Vec<Result<Frame, Error>> {
+        let deadline = self.timeout.map(|t| Instant::now() + t);
+        handles
+            .into_iter()
+            .map(|h| {
+                let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+                h.wait_for(timeout)
+            })
+            .collect()
+    }
+
+    /// wait until any of the calls is answered, the call is removed from the
+    /// handles and returned with its index, return None if there is no handle
+    /// return `Error::Timeout` if no call is answered within the default timeout,
+    /// the handles are left untouched then
+    /// return `Error::ClientSerialize` if any of the calls is not started by this client
+    pub fn wait_any(
+        &self,
+        handles: &mut Vec<CallHandle<'_, S>>,
+    ) -> Result<Option<Answered>, Error> {
+        if handles.is_empty() {
+            return Ok(None);
+        }
+        if !handles.iter().all(|h| std::ptr::eq(h.client, self)) {
+            let s = "the calls are not started by this client".to_owned();
+            return Err(Error::ClientSerialize(s));
+        }
+
+        let deadline = self.timeout.map(|t| Instant::now() + t);
+        let mut calls = self.pending.calls.lock().unwrap();
+        let i = loop {
+            if let Some(i) = handles.iter().position(|h| !calls.ids.contains_key(&h.id)) {
+                break i;
+            }
+            calls = match deadline {
+                None => self.pending.ready.wait(calls).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(Error::Timeout);
+                    }
+                    self.pending
+                        .ready
+                        .wait_timeout(calls, deadline - now)
+                        .unwrap()
+                        .0
+                }
+            };
+        };
+        drop(calls);
+
+        // the answered call is set when it's removed from the pending
+        let h = handles.remove(i);
+        Ok(Some((i, h.wait_for(None))))
+    }
+
+    // call with the given timeout instead of the default one
+    pub(crate) fn call_timeout(
+        &self,
+        req: ReqBuf,
+        timeout: Option<Duration>,
+    ) -> Result<Frame, Error> {
+        self.start_call(req)?.wait_for(timeout)
+    }
+
+    // call with the given timeout, the call is abandoned once the token is cancelled
+    pub(crate) fn call_cancellable_timeout(
+        &self,
+        req: ReqBuf,
+        timeout: Option<Duration>,
+        token: &CancelToken,
+    ) -> Result<Frame, Error> {
+        if token.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let h = self.start_call(req)?;
+        // wake up the call like the listener does, the frame id is never reused,
+        // so it does nothing once the call is answered or dropped
+        let pending = self.pending.clone();
+        let id = h.id;
+        let _guard = token.on_cancel(move || {
+            let mut calls = pending.calls.lock().unwrap();
+            if let Some(waiter) = calls.ids.remove(&id) {
+                let waiter = unsafe { may_waiter::ID::from_usize(waiter) };
+                Waiter::set_rsp(waiter, Err(Error::Cancelled));
+            }
+            drop(calls);
+            pending.ready.notify_all();
+        });
+        h.wait_for(timeout)
+    }
+}
+
+/// the call that is sent by `MultiplexClient::start_call`
+/// dropping the handle abandons the call, the response is discarded
+pub struct CallHandle<'a, S: StreamExt> {
+    client: &'a MultiplexClient<S>,
+    waiter: Waiter,
+    id: u64,
+}
+
+impl<S: StreamExt> std::fmt::Debug for CallHandle<'_, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallHandle")
+            .field("id", &self.id)
+            .field("ready", &self.is_ready())
+            .finish()
+    }
+}
+
+impl<S: StreamExt> Drop for CallHandle<'_, S> {
+    fn drop(&mut self) {
+        // forget the call, the late response is dropped by the listener
+        let mut calls = self.client.pending.calls.lock().unwrap();
+        calls.ids.remove(&self.id);
+    }
+}
+
+impl<S: StreamExt> CallHandle<'_, S> {
+    /// the frame id of the request
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// whether the call is answered or failed, `wait` returns promptly if so
+    pub fn is_ready(&self) -> bool {
+        let calls = self.client.pending.calls.lock().unwrap();
+        !calls.ids.contains_key(&self.id)
+    }
+
+    /// wait for the response with the default timeout of the client
+    pub fn wait(self) -> Result<Frame, Error> {
+        let timeout = self.client.timeout;
+        self.wait_for(timeout)
+    }
+
+    /// wait for the response with the given timeout
+    pub fn wait_timeout(self, timeout: Duration) -> Result<Frame, Error> {
+        self.wait_for(Some(timeout))
+    }
+
+    fn wait_for(self, timeout: Option<Duration>) -> Result<Frame, Error> {
+        // the call is forgotten when the handle is dropped after timeout
+        let rsp_frame: Frame = self.waiter.wait_rsp(timeout)??;
+        self.client.compressor.observe(&rsp_frame);
+        Ok(rsp_frame)
+    }
 }
 
 impl<S: StreamExt> Client for MultiplexClient<S> {
     fn call_service(&self, req: ReqBuf) -> Result<Frame, Error> {
-        let waiter = TokenWaiter::new();
-        let id = waiter.id().unwrap();
-        info!("request id = {:?}", id);
+        self.call_timeout(req, self.timeout)
+    }
 
-        // send the request
-        let id: usize = id.into();
-        let buf = req.finish(id as u64);
+    /// the response of the abandoned call is dropped by the listener
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        self.call_cancellable_timeout(req, self.timeout, token)
+    }
+}
 
-        self.sock.write(buf);
+// the multiplexed connection that is created on demand
+// and created again after it's closed
+pub(crate) struct LazyClient<S: StreamExt> {
+    connect: Box<dyn Fn() -> io::Result<S> + Send + Sync>,
+    client: Mutex<Option<Arc<MultiplexClient<S>>>>,
+}
+
+impl<S: StreamExt> LazyClient<S> {
+    pub(crate) fn new<F>(connect: F) -> Self
+    where
+        F: Fn() -> io::Result<S> + Send + Sync + 'static,
+    {
+        LazyClient {
+            connect: Box::new(connect),
+            client: Mutex::new(None),
+        }
+    }
+
+    // get the open connection, connect if there is none
+    pub(crate) fn get(&self) -> Result<Arc<MultiplexClient<S>>, Error> {
+        if let Some(ref c) = *self.client.lock().unwrap() {
+            if !c.is_closed() {
+                return Ok(c.clone());
+            }
+        }
+
+        // don't block the other calls when connecting
+        let new_client = Arc::new(MultiplexClient::new((self.connect)()?)?);
+        let mut client = self.client.lock().unwrap();
+        match *client {
+            // another call has connected in the meantime
+            Some(ref c) if !c.is_closed() => Ok(c.clone()),
+            _ => {
+                *client = Some(new_client.clone());
+                Ok(new_client)
+            }
+        }
+    }
 
-        // wait for the rsp
-        Ok(waiter.wait_rsp(self.timeout)?)
+    // drop the connection, a new one is created for the next call
+    pub(crate) fn reset(&self) {
+        *self.client.lock().unwrap() = None;
     }
 }
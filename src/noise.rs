@@ -0,0 +1,763 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, IoSlice, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::stream_ext::StreamExt;
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use may::sync::Mutex;
+use snow::{Builder, HandshakeState, StatelessTransportState};
+
+// Noise stream record layout
+// len(u16) + noise_msg([u8; len])
+//
+// Sealed udp frame layout
+// id(u64) + mark(u64) + handshake_msg([u8; 104]) + nonce(u64) + (len(u16) + noise_msg([u8; len]))*
+// the handshake message starts the session of the sender, the nonce is of the first chunk
+// the sessions are one-way, so the server seals the responses by its own session to the client
+
+const STREAM_PATTERN: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+const DGRAM_PATTERN: &str = "Noise_X_25519_ChaChaPoly_BLAKE2s";
+const MAX_MSG_LEN: usize = 65535;
+const TAG_LEN: usize = 16;
+const MAX_CHUNK_LEN: usize = MAX_MSG_LEN - TAG_LEN;
+// e + encrypted s + encrypted timestamp payload
+const DGRAM_HANDSHAKE_LEN: usize = 32 + 32 + TAG_LEN + 8 + TAG_LEN;
+const SEAL_MARK: u64 = u64::MAX - 1;
+const SEAL_HEAD_LEN: usize = 16;
+// the sender starts a new udp session after this
+const SESSION_LIFETIME: Duration = Duration::from_secs(60);
+// the seconds that the udp session is accepted since the sender started it,
+// the receiver keeps the session until then, so the replayed frames are found
+const MAX_SESSION_AGE: u64 = 120;
+// the max number of the peers that the udp sessions are kept for
+const MAX_PEERS: usize = 4096;
+// the max number of the live udp sessions received from one peer
+const PEER_SESSIONS: usize = 4;
+// the number of the recent nonces that are checked for the replayed frames
+const REPLAY_WINDOW: u64 = 1024;
+
+fn noise_err(e: snow::Error) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, e)
+}
+
+fn builder(pattern: &str) -> Builder<'_> {
+    Builder::new(pattern.parse().expect("invalid noise pattern"))
+}
+
+/// the static keys used by the noise sessions
+#[derive(Clone)]
+pub struct NoiseConfig {
+    private_key: Vec<u8>,
+    // the public keys of the allowed peers
+    peers: Vec<Vec<u8>>,
+    // allow the peers with any key
+    any_peer: bool,
+}
+
+impl std::fmt::Debug for NoiseConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("NoiseConfig")
+            .field("peers", &self.peers.len())
+            .field("any_peer", &self.any_peer)
+            .finish()
+    }
+}
+
+impl NoiseConfig {
+    /// generate a new static keypair, return (private_key, public_key)
+    pub fn generate_keypair() -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let keypair = builder(STREAM_PATTERN)
+            .generate_keypair()
+            .map_err(noise_err)?;
+        Ok((keypair.private, keypair.public))
+    }
+
+    /// create the config with the local private key
+    pub fn new(private_key: &[u8]) -> Self {
+        NoiseConfig {
+            private_key: private_key.to_vec(),
+            peers: Vec::new(),
+            any_peer: false,
+        }
+    }
+
+    /// only allow the peers with the given public keys
+    /// the udp clients seal the requests for the first allowed peer
+    pub fn allow_peer(mut self, public_key: &[u8]) -> Self {
+        self.peers.push(public_key.to_vec());
+        self
+    }
+
+    /// allow the peers with any key, no peer is allowed by default
+    /// the service should check the peer key by itself, and the udp server
+    /// refuses the new peers when there are too many live ones
+    pub fn allow_any_peer(mut self) -> Self {
+        self.any_peer = true;
+        self
+    }
+
+    // check the authenticated peer key
+    fn check_peer(&self, key: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        let key = key.ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "no peer key"))?;
+        if !self.any_peer && !self.peers.iter().any(|k| k == key) {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "noise peer key not allowed",
+            ));
+        }
+        Ok(key.to_vec())
+    }
+
+    // the peer that the udp clients seal the requests for
+    pub(crate) fn server_key(&self) -> io::Result<&[u8]> {
+        match self.peers.first() {
+            Some(key) => Ok(key),
+            None => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "no server public key configured",
+            )),
+        }
+    }
+}
+
+/// check if the udp frame is sealed
+pub(crate) fn is_sealed(buf: &[u8]) -> bool {
+    buf.len() >= SEAL_HEAD_LEN && buf[8..16] == SEAL_MARK.to_be_bytes()
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn invalid_sealed() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "invalid sealed frame")
+}
+
+// the sliding window of the recently received nonces
+struct ReplayWindow {
+    // the next nonce of the highest received one
+    top: u64,
+    bits: [u64; (REPLAY_WINDOW / 64) as usize],
+}
+
+impl ReplayWindow {
+    fn new() -> Self {
+        ReplayWindow {
+            top: 0,
+            bits: [0; (REPLAY_WINDOW / 64) as usize],
+        }
+    }
+
+    fn bit(&mut self, nonce: u64) -> (&mut u64, u64) {
+        let i = nonce % REPLAY_WINDOW;
+        (&mut self.bits[(i / 64) as usize], 1 << (i % 64))
+    }
+
+    // record the nonce, return false if it's received before or too old
+    fn accept(&mut self, nonce: u64) -> bool {
+        if nonce >= self.top {
+            // clear the bits of the skipped nonces
+            if nonce - self.top >= REPLAY_WINDOW {
+                self.bits = [0; (REPLAY_WINDOW / 64) as usize];
+            } else {
+                for n in self.top..nonce {
+                    let (word, mask) = self.bit(n);
+                    *word &= !mask;
+                }
+            }
+            self.top = nonce + 1;
+        } else if self.top - nonce > REPLAY_WINDOW {
+            return false;
+        } else {
+            let (word, mask) = self.bit(nonce);
+            if *word & mask != 0 {
+                return false;
+            }
+        }
+        let (word, mask) = self.bit(nonce);
+        *word |= mask;
+        true
+    }
+}
+
+/// the udp session that is started by the one-way handshake of the sender
+/// the handshake message is sent with each frame, so the receiver can open
+/// the frames without the session, e.g. after restarted
+pub(crate) struct Session {
+    // the handshake message of the sender
+    handshake: Vec<u8>,
+    state: StatelessTransportState,
+    // the static key of the other side
+    peer: Vec<u8>,
+    // the unix time when the sender started the session
+    started: u64,
+    created: Instant,
+    // the nonce of the next sent message
+    nonce: AtomicU64,
+    window: Mutex<ReplayWindow>,
+}
+
+impl Session {
+    fn new(
+        handshake: Vec<u8>,
+        state: StatelessTransportState,
+        peer: Vec<u8>,
+        started: u64,
+    ) -> Self {
+        Session {
+            handshake,
+            state,
+            peer,
+            started,
+            created: Instant::now(),
+            nonce: AtomicU64::new(0),
+            window: Mutex::new(ReplayWindow::new()),
+        }
+    }
+
+    // start the session to the peer, the sending side
+    fn initiate(config: &NoiseConfig, peer: &[u8]) -> io::Result<Self> {
+        let mut hs = builder(DGRAM_PATTERN)
+            .local_private_key(&config.private_key)
+            .remote_public_key(peer)
+            .build_initiator()
+            .map_err(noise_err)?;
+        let started = unix_time();
+        let mut msg = vec![0; MAX_MSG_LEN];
+        let n = hs
+            .write_message(&started.to_be_bytes(), &mut msg)
+            .map_err(noise_err)?;
+        msg.truncate(n);
+        let state = hs.into_stateless_transport_mode().map_err(noise_err)?;
+        Ok(Session::new(msg, state, peer.to_vec(), started))
+    }
+
+    // accept the session from the handshake message, the receiving side
+    fn respond(config: &NoiseConfig, handshake: &[u8]) -> io::Result<Self> {
+        let mut hs = builder(DGRAM_PATTERN)
+            .local_private_key(&config.private_key)
+            .build_responder()
+            .map_err(noise_err)?;
+        let mut payload = vec![0; MAX_MSG_LEN];
+        let n = hs
+            .read_message(handshake, &mut payload)
+            .map_err(noise_err)?;
+        if n != 8 {
+            return Err(invalid_sealed());
+        }
+        let peer = config.check_peer(hs.get_remote_static())?;
+
+        // the old session may be replayed after it's dropped by the server
+        let started = BigEndian::read_u64(&payload);
+        let now = unix_time();
+        if now.max(started) - now.min(started) > MAX_SESSION_AGE {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "noise session expired",
+            ));
+        }
+        let state = hs.into_stateless_transport_mode().map_err(noise_err)?;
+        Ok(Session::new(handshake.to_vec(), state, peer, started))
+    }
+
+    // the session is not accepted after this, so it's safe to drop it
+    fn is_expired(&self, now: u64) -> bool {
+        now > self.started + MAX_SESSION_AGE
+    }
+
+    // seal the encoded frame, the id is kept in plain for fragmentation
+    fn seal(&self, frame: &[u8]) -> io::Result<Vec<u8>> {
+        let chunks = frame.len() / MAX_CHUNK_LEN + 1;
+        let nonce = self.nonce.fetch_add(chunks as u64, Ordering::Relaxed);
+        let mut sealed = Vec::with_capacity(
+            SEAL_HEAD_LEN + self.handshake.len() + 8 + frame.len() + chunks * (2 + TAG_LEN),
+        );
+        sealed.extend_from_slice(&frame[0..8]);
+        sealed.write_u64::<BigEndian>(SEAL_MARK).unwrap();
+        sealed.extend_from_slice(&self.handshake);
+        sealed.write_u64::<BigEndian>(nonce).unwrap();
+
+        let mut msg = vec![0; MAX_MSG_LEN];
+        for (i, chunk) in frame.chunks(MAX_CHUNK_LEN).enumerate() {
+            let n = self
+                .state
+                .write_message(nonce + i as u64, chunk, &mut msg)
+                .map_err(noise_err)?;
+            sealed.write_u16::<BigEndian>(n as u16).unwrap();
+            sealed.extend_from_slice(&msg[..n]);
+        }
+        Ok(sealed)
+    }
+
+    // decrypt the chunks, return the frame and the nonce of the first chunk
+    fn open(&self, mut rest: &[u8]) -> io::Result<(Vec<u8>, u64)> {
+        let first = rest.read_u64::<BigEndian>()?;
+        let mut plain = vec![0; MAX_MSG_LEN];
+        let mut frame = Vec::with_capacity(rest.len());
+        let mut nonce = first;
+        while !rest.is_empty() {
+            let len = rest.read_u16::<BigEndian>()? as usize;
+            if len > rest.len() {
+                return Err(invalid_sealed());
+            }
+            let n = self
+                .state
+                .read_message(nonce, &rest[..len], &mut plain)
+                .map_err(noise_err)?;
+            frame.extend_from_slice(&plain[..n]);
+            rest = &rest[len..];
+            nonce += 1;
+        }
+        Ok((frame, first))
+    }
+}
+
+fn old_session() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "noise session replaced")
+}
+
+// the sessions received from one peer, the newer session replaces the oldest one
+// the replaced sessions are never accepted again, so their frames can't be replayed
+#[derive(Default)]
+struct PeerSessions {
+    sessions: Vec<Arc<Session>>,
+    // the start time of the last replaced session
+    floor: u64,
+}
+
+impl PeerSessions {
+    // add the session, return the replaced one
+    fn add(&mut self, session: Arc<Session>) -> io::Result<Option<Arc<Session>>> {
+        // the sessions started in the same second can't be told apart from the replaced one
+        if session.started <= self.floor {
+            return Err(old_session());
+        }
+        if self.sessions.len() < PEER_SESSIONS {
+            self.sessions.push(session);
+            return Ok(None);
+        }
+        let oldest = (0..self.sessions.len())
+            .min_by_key(|&i| self.sessions[i].started)
+            .unwrap();
+        if session.started <= self.sessions[oldest].started {
+            return Err(old_session());
+        }
+        self.floor = self.sessions[oldest].started;
+        Ok(Some(std::mem::replace(&mut self.sessions[oldest], session)))
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.sessions.iter().all(|s| s.is_expired(now))
+    }
+}
+
+#[derive(Default)]
+struct Sessions {
+    // the sessions that are received, keyed by the handshake message
+    incoming: HashMap<Vec<u8>, Arc<Session>>,
+    // the received sessions of each peer, keyed by the peer key
+    peers: HashMap<Vec<u8>, PeerSessions>,
+    // the sessions that the frames are sealed by, keyed by the peer key
+    outgoing: HashMap<Vec<u8>, Arc<Session>>,
+}
+
+impl Sessions {
+    // add the received session, return the existing one with the same handshake
+    fn add_incoming(
+        &mut self,
+        handshake: &[u8],
+        session: Arc<Session>,
+    ) -> io::Result<Arc<Session>> {
+        if let Some(s) = self.incoming.get(handshake) {
+            return Ok(s.clone());
+        }
+        if !self.peers.contains_key(&session.peer) && self.peers.len() >= MAX_PEERS {
+            // only the peers allowed by `allow_any_peer` may fill the table,
+            // the live peers are kept, or their replaced sessions would be accepted again
+            let now = unix_time();
+            let incoming = &mut self.incoming;
+            let outgoing = &mut self.outgoing;
+            self.peers.retain(|key, p| {
+                let keep = !p.is_expired(now);
+                if !keep {
+                    for s in p.sessions.iter() {
+                        incoming.remove(&s.handshake);
+                    }
+                    outgoing.remove(key);
+                }
+                keep
+            });
+            if self.peers.len() >= MAX_PEERS {
+                warn!("too many noise peers, refuse the new one");
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    "too many noise peers",
+                ));
+            }
+        }
+        let peer = self.peers.entry(session.peer.clone()).or_default();
+        if let Some(old) = peer.add(session.clone())? {
+            self.incoming.remove(&old.handshake);
+        }
+        self.incoming.insert(handshake.to_vec(), session.clone());
+        Ok(session)
+    }
+}
+
+/// seal the udp frames by the noise sessions
+/// the handshake is done once for each session instead of each frame
+pub(crate) struct UdpNoise {
+    config: NoiseConfig,
+    sessions: Mutex<Sessions>,
+}
+
+impl std::fmt::Debug for UdpNoise {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let sessions = self.sessions.lock().unwrap();
+        f.debug_struct("UdpNoise")
+            .field("config", &self.config)
+            .field("incoming", &sessions.incoming.len())
+            .field("outgoing", &sessions.outgoing.len())
+            .finish()
+    }
+}
+
+impl UdpNoise {
+    pub fn new(config: NoiseConfig) -> Self {
+        UdpNoise {
+            config,
+            sessions: Mutex::new(Sessions::default()),
+        }
+    }
+
+    // the session to the peer, a new one is started after the lifetime
+    fn outgoing(&self, peer: &[u8]) -> io::Result<Arc<Session>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(s) = sessions.outgoing.get(peer) {
+            if s.created.elapsed() < SESSION_LIFETIME {
+                return Ok(s.clone());
+            }
+        }
+        let session = Arc::new(Session::initiate(&self.config, peer)?);
+        sessions.outgoing.insert(peer.to_vec(), session.clone());
+        Ok(session)
+    }
+
+    /// seal the encoded frame to the sender of the request,
+    /// the clients pass None to seal for the configured server key
+    pub fn seal(&self, frame: &[u8], session: Option<&Session>) -> io::Result<Vec<u8>> {
+        let peer = match session {
+            Some(session) => &session.peer,
+            None => self.config.server_key()?,
+        };
+        self.outgoing(peer)?.seal(frame)
+    }
+
+    /// open the sealed frame, return the frame, the session and whether
+    /// the frame is fresh, the frame received before is not fresh
+    pub fn open(&self, sealed: &[u8]) -> io::Result<(Vec<u8>, Arc<Session>, bool)> {
+        if !is_sealed(sealed) || sealed.len() < SEAL_HEAD_LEN + DGRAM_HANDSHAKE_LEN + 8 {
+            return Err(invalid_sealed());
+        }
+        let (handshake, rest) = sealed[SEAL_HEAD_LEN..].split_at(DGRAM_HANDSHAKE_LEN);
+        let found = self
+            .sessions
+            .lock()
+            .unwrap()
+            .incoming
+            .get(handshake)
+            .cloned();
+        let session = match found {
+            Some(session) => session,
+            None => {
+                // the handshake is done without the lock
+                let session = Arc::new(Session::respond(&self.config, handshake)?);
+                let mut sessions = self.sessions.lock().unwrap();
+                sessions.add_incoming(handshake, session)?
+            }
+        };
+
+        let (frame, nonce) = session.open(rest)?;
+        // the id in plain is not authenticated, it must match the sealed one
+        if frame.len() < 8 || frame[0..8] != sealed[0..8] {
+            return Err(invalid_sealed());
+        }
+        let fresh = session.window.lock().unwrap().accept(nonce);
+        Ok((frame, session, fresh))
+    }
+}
+
+fn read_msg<'a, R: Read>(io: &mut R, buf: &'a mut [u8]) -> io::Result<&'a [u8]> {
+    let len = io.read_u16::<BigEndian>()? as usize;
+    io.read_exact(&mut buf[..len])?;
+    Ok(&buf[..len])
+}
+
+fn handshake<S: Read + Write>(
+    mut hs: HandshakeState,
+    stream: &mut S,
+    config: &NoiseConfig,
+) -> io::Result<(StatelessTransportState, Vec<u8>)> {
+    let mut buf = vec![0; 2 + MAX_MSG_LEN];
+    let mut payload = vec![0; MAX_MSG_LEN];
+    while !hs.is_handshake_finished() {
+        if hs.is_my_turn() {
+            let n = hs.write_message(&[], &mut buf[2..]).map_err(noise_err)?;
+            BigEndian::write_u16(&mut buf[..2], n as u16);
+            stream.write_all(&buf[..2 + n])?;
+        } else {
+            let msg = read_msg(stream, &mut buf)?;
+            hs.read_message(msg, &mut payload).map_err(noise_err)?;
+        }
+    }
+    let peer = config.check_peer(hs.get_remote_static())?;
+    let state = hs.into_stateless_transport_mode().map_err(noise_err)?;
+    Ok((state, peer))
+}
+
+// the decrypted message that not read yet
+struct RecvBuf {
+    nonce: u64,
+    msg: Vec<u8>,
+    plain: Vec<u8>,
+    pos: usize,
+    len: usize,
+}
+
+impl RecvBuf {
+    fn new() -> Self {
+        RecvBuf {
+            nonce: 0,
+            msg: vec![0; MAX_MSG_LEN],
+            plain: vec![0; MAX_MSG_LEN],
+            pos: 0,
+            len: 0,
+        }
+    }
+}
+
+// the encrypting buffer of the write half
+struct SendBuf {
+    nonce: u64,
+    plain: Vec<u8>,
+    msg: Vec<u8>,
+}
+
+impl SendBuf {
+    fn new() -> Self {
+        SendBuf {
+            nonce: 0,
+            plain: Vec::with_capacity(MAX_CHUNK_LEN),
+            msg: vec![0; 2 + MAX_MSG_LEN],
+        }
+    }
+}
+
+fn read_plain<R: Read>(
+    state: &StatelessTransportState,
+    io: &mut R,
+    rb: &mut RecvBuf,
+    buf: &mut [u8],
+) -> io::Result<usize> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+
+    while rb.pos == rb.len {
+        let len = match io.read_u16::<BigEndian>() {
+            Ok(len) => len as usize,
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        io.read_exact(&mut rb.msg[..len])?;
+        rb.len = state
+            .read_message(rb.nonce, &rb.msg[..len], &mut rb.plain)
+            .map_err(noise_err)?;
+        rb.nonce += 1;
+        rb.pos = 0;
+    }
+
+    let n = buf.len().min(rb.len - rb.pos);
+    buf[..n].copy_from_slice(&rb.plain[rb.pos..rb.pos + n]);
+    rb.pos += n;
+    Ok(n)
+}
+
+// encrypt the buffers into one message as much as possible
+fn write_plain<W: Write>(
+    state: &StatelessTransportState,
+    io: &mut W,
+    sb: &mut SendBuf,
+    bufs: &[IoSlice],
+) -> io::Result<usize> {
+    sb.plain.clear();
+    for buf in bufs {
+        let n = buf.len().min(MAX_CHUNK_LEN - sb.plain.len());
+        sb.plain.extend_from_slice(&buf[..n]);
+        if sb.plain.len() == MAX_CHUNK_LEN {
+            break;
+        }
+    }
+    if sb.plain.is_empty() {
+        return Ok(0);
+    }
+
+    let n = state
+        .write_message(sb.nonce, &sb.plain, &mut sb.msg[2..])
+        .map_err(noise_err)?;
+    sb.nonce += 1;
+    BigEndian::write_u16(&mut sb.msg[..2], n as u16);
+    io.write_all(&sb.msg[..2 + n])?;
+    Ok(sb.plain.len())
+}
+
+/// encrypted stream over the underlying stream
+/// the handshake is done when the stream is created
+pub struct NoiseStream<S: StreamExt> {
+    state: Arc<StatelessTransportState>,
+    stream: S,
+    peer: Vec<u8>,
+    rb: RecvBuf,
+    sb: SendBuf,
+}
+
+impl<S: StreamExt> NoiseStream<S> {
+    /// perform the initiator side handshake on the stream
+    pub fn connect(mut stream: S, config: &NoiseConfig) -> io::Result<Self> {
+        let hs = builder(STREAM_PATTERN)
+            .local_private_key(&config.private_key)
+            .build_initiator()
+            .map_err(noise_err)?;
+        let (state, peer) = handshake(hs, &mut stream, config)?;
+        Ok(NoiseStream::new(state, stream, peer))
+    }
+
+    /// perform the responder side handshake on the stream
+    pub fn accept(mut stream: S, config: &NoiseConfig) -> io::Result<Self> {
+        let hs = builder(STREAM_PATTERN)
+            .local_private_key(&config.private_key)
+            .build_responder()
+            .map_err(noise_err)?;
+        let (state, peer) = handshake(hs, &mut stream, config)?;
+        Ok(NoiseStream::new(state, stream, peer))
+    }
+
+    fn new(state: StatelessTransportState, stream: S, peer: Vec<u8>) -> Self {
+        NoiseStream {
+            state: Arc::new(state),
+            stream,
+            peer,
+            rb: RecvBuf::new(),
+            sb: SendBuf::new(),
+        }
+    }
+
+    /// the authenticated public key of the peer
+    pub fn peer_key(&self) -> &[u8] {
+        &self.peer
+    }
+}
+
+impl<S: StreamExt> std::fmt::Debug for NoiseStream<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("NoiseStream")
+    }
+}
+
+impl<S: StreamExt> Read for NoiseStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_plain(&self.state, &mut self.stream, &mut self.rb, buf)
+    }
+}
+
+impl<S: StreamExt> Write for NoiseStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bufs = [IoSlice::new(buf)];
+        write_plain(&self.state, &mut self.stream, &mut self.sb, &bufs)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        write_plain(&self.state, &mut self.stream, &mut self.sb, bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: StreamExt> StreamExt for NoiseStream<S> {
+    type Reader = NoiseReader<S::Reader>;
+    type Writer = NoiseWriter<S::Writer>;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        let (r, w) = self.stream.split()?;
+        let reader = NoiseReader {
+            state: self.state.clone(),
+            io: r,
+            rb: self.rb,
+        };
+        let writer = NoiseWriter {
+            state: self.state,
+            io: w,
+            sb: self.sb,
+        };
+        Ok((reader, writer))
+    }
+
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
+
+/// the read half of the noise stream
+pub struct NoiseReader<R> {
+    state: Arc<StatelessTransportState>,
+    io: R,
+    rb: RecvBuf,
+}
+
+impl<R> std::fmt::Debug for NoiseReader<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("NoiseReader")
+    }
+}
+
+impl<R: Read> Read for NoiseReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_plain(&self.state, &mut self.io, &mut self.rb, buf)
+    }
+}
+
+/// the write half of the noise stream
+pub struct NoiseWriter<W> {
+    state: Arc<StatelessTransportState>,
+    io: W,
+    sb: SendBuf,
+}
+
+impl<W> std::fmt::Debug for NoiseWriter<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("NoiseWriter")
+    }
+}
+
+impl<W: Write> Write for NoiseWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bufs = [IoSlice::new(buf)];
+        write_plain(&self.state, &mut self.io, &mut self.sb, &bufs)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        write_plain(&self.state, &mut self.io, &mut self.sb, bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::{self, Read, Write};
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::fd::Fd;
+use crate::stream_ext::StreamExt;
+
+use may::io::CoIo;
+
+// the stdin/stdout share the file status flags with their dups, so `CoIo`
+// makes the process stdio non-blocking too, e.g. `println!` may fail with
+// `WouldBlock`, restore the flags when the stream and its clones are dropped
+#[derive(Debug)]
+struct StdioFlags([libc::c_int; 2]);
+
+impl StdioFlags {
+    fn save() -> io::Result<Self> {
+        let mut flags = [0; 2];
+        for (fd, flag) in flags.iter_mut().enumerate() {
+            *flag = unsafe { libc::fcntl(fd as RawFd, libc::F_GETFL) };
+            if *flag < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(StdioFlags(flags))
+    }
+}
+
+impl Drop for StdioFlags {
+    fn drop(&mut self) {
+        for (fd, &flag) in self.0.iter().enumerate() {
+            unsafe { libc::fcntl(fd as RawFd, libc::F_SETFL, flag) };
+        }
+    }
+}
+
+/// stream over a pair of pipes, e.g. the stdin/stdout of a process
+#[derive(Debug)]
+pub struct PipeStream {
+    reader: CoIo<Fd>,
+    writer: CoIo<Fd>,
+    // restored after the fds are dropped
+    stdio: Option<Arc<StdioFlags>>,
+}
+
+impl PipeStream {
+    /// create the stream over the stdin and stdout of current process
+    /// this is used by the child process to serve the parent
+    /// nothing else should be written to stdout after this
+    ///
+    /// the stdin/stdout are non-blocking while the stream is alive,
+    /// which is seen by the other processes that share the same pipes
+    pub fn stdio() -> io::Result<Self> {
+        let flags = Arc::new(StdioFlags::save()?);
+        PipeStream::from_fds(Fd::dup(0)?, Fd::dup(1)?, Some(flags))
+    }
+
+    /// create the stream from the raw fds, the fds are owned by the stream
+    ///
+    /// # Safety
+    ///
+    /// the fds must be valid and not owned by others
+    pub unsafe fn from_raw_fds(read: RawFd, write: RawFd) -> io::Result<Self> {
+        PipeStream::from_fds(Fd(read), Fd(write), None)
+    }
+
+    fn from_fds(reader: Fd, writer: Fd, stdio: Option<Arc<StdioFlags>>) -> io::Result<Self> {
+        Ok(PipeStream {
+            reader: CoIo::new(reader)?,
+            writer: CoIo::new(writer)?,
+            stdio,
+        })
+    }
+
+    /// create a new handle of the stream
+    /// the handles share the underlying stream
+    pub fn try_clone(&self) -> io::Result<Self> {
+        let reader = self.reader.inner().try_clone()?;
+        let writer = self.writer.inner().try_clone()?;
+        PipeStream::from_fds(reader, writer, self.stdio.clone())
+    }
+}
+
+impl Read for PipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl StreamExt for PipeStream {
+    type Reader = PipeReader;
+    type Writer = PipeWriter;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        let reader = PipeReader {
+            reader: self.reader,
+            _stdio: self.stdio.clone(),
+        };
+        let writer = PipeWriter {
+            writer: self.writer,
+            _stdio: self.stdio,
+        };
+        Ok((reader, writer))
+    }
+
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.reader.set_read_timeout(Some(timeout))
+    }
+}
+
+/// the read half of the `PipeStream`
+#[derive(Debug)]
+pub struct PipeReader {
+    reader: CoIo<Fd>,
+    _stdio: Option<Arc<StdioFlags>>,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+/// the write half of the `PipeStream`
+/// the write pipe is closed when it's dropped, so the peer reads EOF
+#[derive(Debug)]
+pub struct PipeWriter {
+    writer: CoIo<Fd>,
+    _stdio: Option<Arc<StdioFlags>>,
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
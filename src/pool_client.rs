@@ -0,0 +1,227 @@
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::cancel::CancelToken;
+use crate::errors::Error;
+use crate::frame::{Frame, ReqBuf};
+use crate::multiplex_client::MultiplexClient;
+use crate::retry::RetryPolicy;
+use crate::stream_client::StreamClient;
+use crate::stream_ext::StreamExt;
+use crate::Client;
+
+use may::sync::Mutex;
+
+/// the connection that can be managed by the `PoolClient`
+pub trait PoolConn: Send + Sync + 'static {
+    /// call the server, the concurrent calls may share the connection
+    fn call(&self, req: ReqBuf) -> Result<Frame, Error>;
+
+    /// call the server, the call is abandoned once the token is cancelled
+    /// the default implementation ignores the token and runs the call to the end
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        let _ = token;
+        self.call(req)
+    }
+
+    /// call the server, and retry it by the policy over this connection
+    /// the default implementation sends each attempt by `call`
+    fn call_with_retry(&self, req: ReqBuf, policy: &RetryPolicy) -> Result<Frame, Error> {
+        policy.retry(|| self.call(req.clone()))
+    }
+
+    /// whether the connection is broken after the call failed with the error
+    /// the broken connection is evicted from the pool
+    fn is_broken(&self, err: Option<&Error>) -> bool;
+}
+
+impl<S: StreamExt> PoolConn for MultiplexClient<S> {
+    fn call(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.call_service(req)
+    }
+
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        Client::call_cancellable(self, req, token)
+    }
+
+    fn call_with_retry(&self, req: ReqBuf, policy: &RetryPolicy) -> Result<Frame, Error> {
+        Client::call_with_retry(self, req, policy)
+    }
+
+    fn is_broken(&self, _err: Option<&Error>) -> bool {
+        self.is_closed()
+    }
+}
+
+// the stream client serves one call at a time
+impl<S: StreamExt> PoolConn for Mutex<StreamClient<S>> {
+    fn call(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.lock().unwrap().call_service(req)
+    }
+
+    fn is_broken(&self, err: Option<&Error>) -> bool {
+        // the stream may be left with a partial frame after the io errors
+        matches!(err, Some(Error::Io(_)) | Some(Error::ClientDeserialize(_)))
+    }
+}
+
+struct Slot<C> {
+    conn: C,
+    // the number of the calls that are using the connection
+    in_flight: AtomicUsize,
+}
+
+struct Slots<C> {
+    slots: Vec<Arc<Slot<C>>>,
+    // the number of the connections that are being created
+    connecting: usize,
+}
+
+/// client that spreads the calls over a pool of connections
+/// the connections are created lazily by the connect function
+pub struct PoolClient<C: PoolConn> {
+    connect: Box<dyn Fn() -> io::Result<C> + Send + Sync>,
+    // the max number of the connections
+    max_conns: usize,
+    // the max number of the idle connections that are kept
+    max_idle: usize,
+    // the number of the calls that share a connection before a new one is created
+    max_concurrent: usize,
+    pool: Mutex<Slots<C>>,
+    // spread the calls when the connections are equally loaded
+    next: AtomicUsize,
+}
+
+impl<C: PoolConn> std::fmt::Debug for PoolClient<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PoolClient")
+            .field("max_conns", &self.max_conns)
+            .field("max_idle", &self.max_idle)
+            .field("max_concurrent", &self.max_concurrent)
+            .field("size", &self.size())
+            .finish()
+    }
+}
+
+impl<C: PoolConn> PoolClient<C> {
+    /// create the pool with at most `max_conns` connections
+    /// no connection is created until the first call
+    pub fn new<F>(max_conns: usize, connect: F) -> Self
+    where
+        F: Fn() -> io::Result<C> + Send + Sync + 'static,
+    {
+        assert!(max_conns > 0, "max_conns must be positive");
+        PoolClient {
+            connect: Box::new(connect),
+            max_conns,
+            max_idle: max_conns,
+            max_concurrent: 1,
+            pool: Mutex::new(Slots {
+                slots: Vec::new(),
+                connecting: 0,
+            }),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// set the max number of the idle connections that are kept in the pool
+    /// the initial value is the max number of the connections
+    pub fn set_max_idle(&mut self, max_idle: usize) {
+        self.max_idle = max_idle;
+    }
+
+    /// set the number of the concurrent calls that a connection takes before
+    /// a new connection is created, the connections are shared beyond that
+    /// only when the pool is full, the initial value is 1
+    /// raise it for the multiplexed connections, which serve many calls at once
+    pub fn set_max_concurrent(&mut self, max_concurrent: usize) {
+        self.max_concurrent = max_concurrent.max(1);
+    }
+
+    /// the number of the open connections
+    pub fn size(&self) -> usize {
+        self.pool.lock().unwrap().slots.len()
+    }
+
+    // pick the least loaded connection, create a new one if all are at the threshold
+    fn get(&self) -> Result<Arc<Slot<C>>, Error> {
+        {
+            let mut pool = self.pool.lock().unwrap();
+            pool.slots.retain(|s| !s.conn.is_broken(None));
+
+            let n = pool.slots.len();
+            let start = self.next.fetch_add(1, Ordering::Relaxed);
+            let best = (0..n)
+                .map(|i| &pool.slots[(start + i) % n])
+                .min_by_key(|s| s.in_flight.load(Ordering::Relaxed));
+            if let Some(slot) = best {
+                let busy = slot.in_flight.load(Ordering::Relaxed) >= self.max_concurrent;
+                if !busy || n + pool.connecting >= self.max_conns {
+                    slot.in_flight.fetch_add(1, Ordering::Relaxed);
+                    return Ok(slot.clone());
+                }
+            }
+            pool.connecting += 1;
+        }
+
+        // don't block the other calls when connecting
+        let ret = (self.connect)();
+        let mut pool = self.pool.lock().unwrap();
+        pool.connecting -= 1;
+        let slot = Arc::new(Slot {
+            conn: ret?,
+            in_flight: AtomicUsize::new(1),
+        });
+        info!("pool client: new connection, size={}", pool.slots.len() + 1);
+        pool.slots.push(slot.clone());
+        Ok(slot)
+    }
+
+    // release the connection after the call
+    fn put(&self, slot: Arc<Slot<C>>, err: Option<&Error>) {
+        let idle = slot.in_flight.fetch_sub(1, Ordering::Relaxed) == 1;
+        let broken = err.is_some() && slot.conn.is_broken(err);
+        if !idle && !broken {
+            return;
+        }
+
+        let mut pool = self.pool.lock().unwrap();
+        let idle_count = pool
+            .slots
+            .iter()
+            .filter(|s| s.in_flight.load(Ordering::Relaxed) == 0)
+            .count();
+        // the connection may be picked again before we get the lock
+        let idle = slot.in_flight.load(Ordering::Relaxed) == 0;
+        if broken || (idle && idle_count > self.max_idle) {
+            info!("pool client: drop connection, broken={}", broken);
+            pool.slots.retain(|s| !Arc::ptr_eq(s, &slot));
+        }
+    }
+}
+
+impl<C: PoolConn> Client for PoolClient<C> {
+    fn call_service(&self, req: ReqBuf) -> Result<Frame, Error> {
+        let slot = self.get()?;
+        let ret = slot.conn.call(req);
+        self.put(slot, ret.as_ref().err());
+        ret
+    }
+
+    /// the connection is released once the call is abandoned
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        let slot = self.get()?;
+        let ret = slot.conn.call_cancellable(req, token);
+        self.put(slot, ret.as_ref().err());
+        ret
+    }
+
+    /// the retries are sent over the same connection by `PoolConn::call_with_retry`
+    fn call_with_retry(&self, req: ReqBuf, policy: &RetryPolicy) -> Result<Frame, Error> {
+        let slot = self.get()?;
+        let ret = slot.conn.call_with_retry(req, policy);
+        self.put(slot, ret.as_ref().err());
+        ret
+    }
+}
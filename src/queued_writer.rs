@@ -1,67 +1,14 @@
-use std::io::IoSlice;
 use std::io::Write;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::vec_bufs::VecBufs;
+
 use arrayvec::ArrayVec;
 use crossbeam::queue::SegQueue;
 use may::sync::Mutex;
 
 const MAX_VEC_BUF: usize = 64;
 
-struct VecBufs {
-    block: usize,
-    pos: usize,
-    bufs: ArrayVec<Vec<u8>, MAX_VEC_BUF>,
-}
-
-impl VecBufs {
-    fn new(bufs: ArrayVec<Vec<u8>, MAX_VEC_BUF>) -> Self {
-        VecBufs {
-            block: 0,
-            pos: 0,
-            bufs,
-        }
-    }
-
-    fn get_io_slice(&self) -> ArrayVec<IoSlice<'_>, MAX_VEC_BUF> {
-        let mut ret = ArrayVec::new();
-        let first = IoSlice::new(&self.bufs[self.block][self.pos..]);
-        ret.push(first);
-        for buf in self.bufs.iter().skip(self.block + 1) {
-            ret.push(IoSlice::new(buf))
-        }
-        ret
-    }
-
-    fn advance(&mut self, n: usize) {
-        let mut left = n;
-        for buf in self.bufs[self.block..].iter() {
-            let len = buf.len() - self.pos;
-            if left >= len {
-                left -= len;
-                self.block += 1;
-                self.pos = 0;
-            } else {
-                self.pos += left;
-                break;
-            }
-        }
-    }
-
-    fn is_empty(&self) -> bool {
-        self.block == self.bufs.len()
-    }
-
-    // write all data from the vecs to the writer
-    fn write_all<W: Write>(mut self, writer: &mut W) -> std::io::Result<()> {
-        while !self.is_empty() {
-            let n = writer.write_vectored(&self.get_io_slice())?;
-            self.advance(n);
-        }
-        Ok(())
-    }
-}
-
 #[derive(Debug)]
 pub struct QueuedWriter<W: Write> {
     data_count: AtomicUsize,
@@ -99,8 +46,7 @@ impl<W: Write> QueuedWriter<W> {
                 }
 
                 let cnt = total_data.len();
-                let io_bufs = VecBufs::new(total_data);
-                if let Err(e) = io_bufs.write_all(&mut *writer) {
+                if let Err(e) = VecBufs::new(&total_data).write_all(&mut *writer) {
                     // FIXME: handle the error
                     error!("QueuedWriter failed, err={}", e);
                 }
@@ -0,0 +1,197 @@
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cancel::CancelToken;
+use crate::errors::Error;
+use crate::frame::{Frame, ReqBuf};
+use crate::multiplex_client::MultiplexClient;
+use crate::retry::RetryPolicy;
+use crate::stream_ext::StreamExt;
+use crate::util::jitter;
+use crate::Client;
+
+use may::sync::Mutex;
+use may::{coroutine, go};
+
+/// the connection state of the `ReconnectClient`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    /// the calls are sent over the connection
+    Connected,
+    /// the connection is lost, the calls fail until it's reconnected
+    Reconnecting,
+}
+
+type OnChange = Box<dyn Fn(ConnState, ConnState) + Send + Sync>;
+
+struct Inner<S: StreamExt> {
+    connect: Box<dyn Fn() -> io::Result<S> + Send + Sync>,
+    // None when reconnecting
+    client: Mutex<Option<Arc<MultiplexClient<S>>>>,
+    // the min and max delay between the reconnect attempts
+    backoff: Mutex<(Duration, Duration)>,
+    // the number of the successful reconnects
+    reconnects: AtomicUsize,
+    // called by the supervisor on each state transition
+    on_change: Mutex<Option<Arc<OnChange>>>,
+}
+
+impl<S: StreamExt> Inner<S> {
+    fn current(&self) -> Option<Arc<MultiplexClient<S>>> {
+        self.client.lock().unwrap().clone()
+    }
+
+    fn notify(&self, from: ConnState, to: ConnState) {
+        let on_change = self.on_change.lock().unwrap().clone();
+        if let Some(f) = on_change {
+            f(from, to);
+        }
+    }
+
+    // wait for the connection to close and then reconnect, forever
+    fn supervise(&self) {
+        loop {
+            if let Some(client) = self.current() {
+                client.wait_closed();
+            }
+            *self.client.lock().unwrap() = None;
+            warn!("reconnect client: connection closed, reconnecting");
+            self.notify(ConnState::Connected, ConnState::Reconnecting);
+
+            let (min, max) = *self.backoff.lock().unwrap();
+            let mut delay = min;
+            let client = loop {
+                match (self.connect)().and_then(MultiplexClient::new) {
+                    Ok(client) => break client,
+                    Err(e) => {
+                        error!("reconnect client: connect err = {:?}", e);
+                        coroutine::sleep(jitter(delay));
+                        delay = (delay * 2).min(max);
+                    }
+                }
+            };
+            info!("reconnect client: connection restored");
+            *self.client.lock().unwrap() = Some(Arc::new(client));
+            self.reconnects.fetch_add(1, Ordering::Relaxed);
+            self.notify(ConnState::Reconnecting, ConnState::Connected);
+        }
+    }
+}
+
+/// multiplexed client that reconnects when the connection is lost
+/// the calls that are waiting on the lost connection fail immediately
+pub struct ReconnectClient<S: StreamExt> {
+    inner: Arc<Inner<S>>,
+    // no timeout by default, the calls wait until the connection is closed
+    timeout: Option<Duration>,
+    // the reconnecting coroutine
+    supervisor: Option<coroutine::JoinHandle<()>>,
+}
+
+impl<S: StreamExt> Drop for ReconnectClient<S> {
+    fn drop(&mut self) {
+        if let Some(h) = self.supervisor.take() {
+            unsafe { h.coroutine().cancel() };
+        }
+    }
+}
+
+impl<S: StreamExt> std::fmt::Debug for ReconnectClient<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReconnectClient")
+            .field("state", &self.state())
+            .field("reconnects", &self.reconnects())
+            .finish()
+    }
+}
+
+impl<S: StreamExt> ReconnectClient<S> {
+    /// create the client, the first connection is made before returning
+    /// the connect function is called again each time the connection is lost
+    pub fn new<F>(connect: F) -> io::Result<Self>
+    where
+        F: Fn() -> io::Result<S> + Send + Sync + 'static,
+    {
+        let client = MultiplexClient::new(connect()?)?;
+        let inner = Arc::new(Inner {
+            connect: Box::new(connect),
+            client: Mutex::new(Some(Arc::new(client))),
+            backoff: Mutex::new((Duration::from_millis(100), Duration::from_secs(10))),
+            reconnects: AtomicUsize::new(0),
+            on_change: Mutex::new(None),
+        });
+
+        let supervised = inner.clone();
+        let supervisor = go!(
+            coroutine::Builder::new().name("ReconnectClientSupervisor".to_owned()),
+            move || supervised.supervise()
+        )?;
+
+        Ok(ReconnectClient {
+            inner,
+            timeout: None,
+            supervisor: Some(supervisor),
+        })
+    }
+
+    /// set the default timeout value
+    /// there is no timeout initially, the calls wait until the connection is closed
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// set the delay between the reconnect attempts
+    /// the delay starts from `min` and doubles up to `max` after each failure
+    /// the initial values are 100ms and 10s, `min` is at least 1ms
+    pub fn set_backoff(&mut self, min: Duration, max: Duration) {
+        // a zero delay would never grow, and spin on the failed connects
+        let min = min.max(Duration::from_millis(1));
+        *self.inner.backoff.lock().unwrap() = (min, max.max(min));
+    }
+
+    /// set the callback for the state transitions, called with the old and new state
+    /// the callback runs in the reconnecting coroutine, in the order of the transitions
+    pub fn set_on_state_change<F>(&mut self, f: F)
+    where
+        F: Fn(ConnState, ConnState) + Send + Sync + 'static,
+    {
+        *self.inner.on_change.lock().unwrap() = Some(Arc::new(Box::new(f)));
+    }
+
+    /// the current connection state
+    pub fn state(&self) -> ConnState {
+        match self.inner.current() {
+            Some(ref client) if !client.is_closed() => ConnState::Connected,
+            _ => ConnState::Reconnecting,
+        }
+    }
+
+    /// the number of the times the connection is restored
+    pub fn reconnects(&self) -> usize {
+        self.inner.reconnects.load(Ordering::Relaxed)
+    }
+}
+
+impl<S: StreamExt> Client for ReconnectClient<S> {
+    fn call_service(&self, req: ReqBuf) -> Result<Frame, Error> {
+        match self.inner.current() {
+            Some(client) => client.call_timeout(req, self.timeout),
+            None => Err(Error::Disconnected),
+        }
+    }
+
+    /// each attempt is sent over the current connection, so the retry uses
+    /// the new connection once the client is reconnected
+    fn call_with_retry(&self, req: ReqBuf, policy: &RetryPolicy) -> Result<Frame, Error> {
+        policy.retry(|| self.call_service(req.clone()))
+    }
+
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        match self.inner.current() {
+            Some(client) => client.call_cancellable_timeout(req, self.timeout, token),
+            None => Err(Error::Disconnected),
+        }
+    }
+}
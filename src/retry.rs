@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use crate::cancel::CancelToken;
+use crate::errors::Error;
+use crate::frame::{Frame, ReqBuf};
+use crate::util::jitter;
+use crate::Client;
+
+use may::coroutine;
+use may::sync::Mutex;
+
+/// the default classification of the retryable errors
+/// the io errors, timeouts and connection loss are retryable,
+/// the errors reported by the server are not
+pub fn is_retryable(err: &Error) -> bool {
+    matches!(err, Error::Io(_) | Error::Timeout | Error::Disconnected)
+}
+
+/// when and how often the failed calls are retried
+pub struct RetryPolicy {
+    // the max number of the attempts, including the first one
+    max_attempts: usize,
+    // the min and max delay between the attempts
+    backoff: (Duration, Duration),
+    retryable: fn(&Error) -> bool,
+    // each call deposits `ratio` tokens and each retry withdraws one,
+    // so the retries don't pile up on a failing server
+    ratio: f64,
+    max_tokens: f64,
+    tokens: Mutex<f64>,
+}
+
+impl std::fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("backoff", &self.backoff)
+            .field("ratio", &self.ratio)
+            .field("tokens", &*self.tokens.lock().unwrap())
+            .finish()
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(3)
+    }
+}
+
+impl RetryPolicy {
+    /// create the policy that makes at most `max_attempts` attempts for each call
+    /// the backoff is 10ms doubled up to 1s, the budget is 20% of the calls
+    /// plus 10 spare retries
+    pub fn new(max_attempts: usize) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            backoff: (Duration::from_millis(10), Duration::from_secs(1)),
+            retryable: is_retryable,
+            ratio: 0.2,
+            max_tokens: 10.0,
+            tokens: Mutex::new(10.0),
+        }
+    }
+
+    /// set the delay between the attempts
+    /// the delay starts from `min` and doubles up to `max` after each attempt
+    pub fn set_backoff(&mut self, min: Duration, max: Duration) {
+        self.backoff = (min, max.max(min));
+    }
+
+    /// set the retry budget, each call earns `ratio` retries
+    /// and at most `max_retries` unused retries are kept
+    pub fn set_budget(&mut self, ratio: f64, max_retries: usize) {
+        self.ratio = ratio;
+        self.max_tokens = max_retries as f64;
+        *self.tokens.lock().unwrap() = self.max_tokens;
+    }
+
+    /// set which errors are retried, the default is `is_retryable`
+    pub fn set_retryable(&mut self, retryable: fn(&Error) -> bool) {
+        self.retryable = retryable;
+    }
+
+    fn deposit(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens + self.ratio).min(self.max_tokens);
+    }
+
+    fn withdraw(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        if *tokens < 1.0 {
+            warn!("retry budget exhausted");
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+
+    /// run the call until it succeeds or the error is not retried
+    /// the call must be idempotent, it may be executed more than once by the server
+    pub fn retry<T, F>(&self, mut call: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Result<T, Error>,
+    {
+        self.deposit();
+        let (mut delay, max) = self.backoff;
+        let mut attempt = 1;
+        loop {
+            match call() {
+                Err(ref err)
+                    if attempt < self.max_attempts && (self.retryable)(err) && self.withdraw() =>
+                {
+                    info!("retry the call, attempt={}, err = {:?}", attempt, err);
+                    coroutine::sleep(jitter(delay));
+                    delay = (delay * 2).min(max);
+                    attempt += 1;
+                }
+                ret => return ret,
+            }
+        }
+    }
+}
+
+/// client that retries the idempotent calls by the policy
+/// only the calls made by `Client::call_idempotent` are retried
+/// the calls are retried by `Client::call_with_retry` of the wrapped client,
+/// most clients send each attempt as a new frame, so the service may run
+/// more than once, only the udp clients keep the frame id for the response cache
+#[derive(Debug)]
+pub struct RetryClient<C: Client> {
+    client: C,
+    policy: RetryPolicy,
+}
+
+impl<C: Client> RetryClient<C> {
+    /// wrap the client with the retry policy
+    pub fn new(client: C, policy: RetryPolicy) -> Self {
+        RetryClient { client, policy }
+    }
+
+    /// get the wrapped client
+    pub fn get_ref(&self) -> &C {
+        &self.client
+    }
+}
+
+impl<C: Client> Client for RetryClient<C> {
+    /// the call is not retried, it may not be idempotent
+    fn call_service(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.client.call_service(req)
+    }
+
+    /// call the server, and retry on the retryable errors
+    fn call_idempotent(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.client.call_with_retry(req, &self.policy)
+    }
+
+    /// the call is retried by the given policy instead of the client's own
+    fn call_with_retry(&self, req: ReqBuf, policy: &RetryPolicy) -> Result<Frame, Error> {
+        self.client.call_with_retry(req, policy)
+    }
+
+    /// the call is not retried, the token is forwarded to the wrapped client
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        self.client.call_cancellable(req, token)
+    }
+}
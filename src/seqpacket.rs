@@ -0,0 +1,152 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::mem::MaybeUninit;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::frame::FRAME_MAX_LEN;
+
+use may::coroutine;
+use may::io::{CoIo, WaitIo};
+use socket2::{Domain, SockAddr, Socket, Type};
+
+/// the max packet size, the max frame plus the frame head
+/// the packet can't exceed the socket send buffer, which is capped by the
+/// `net.core.wmem_max` sysctl, so the bigger packets fail with `InvalidInput`
+pub(crate) const MAX_PACKET_LEN: usize = FRAME_MAX_LEN as usize + 16;
+
+// the connected socket, the read returns the real packet len with `MSG_TRUNC`
+// so that the truncated packets are detected by the caller
+#[derive(Debug)]
+struct PacketSocket(Socket);
+
+impl Read for PacketSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // safety: the initialized bytes are never read as uninitialized
+        let buf = unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        self.0.recv_with_flags(buf, libc::MSG_TRUNC)
+    }
+}
+
+impl Write for PacketSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for PacketSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+/// unix seqpacket connection, each send/recv is exactly one packet
+#[derive(Debug)]
+pub(crate) struct SeqPacket(CoIo<PacketSocket>);
+
+impl SeqPacket {
+    fn from_socket(sock: Socket) -> io::Result<Self> {
+        // the kernel may cap it, see `MAX_PACKET_LEN`
+        sock.set_send_buffer_size(MAX_PACKET_LEN)?;
+        sock.set_nonblocking(true)?;
+        Ok(SeqPacket(CoIo::new(PacketSocket(sock))?))
+    }
+
+    /// connect to the seqpacket server path
+    /// retry in the coroutine while the backlog of the listener is full
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let sock = Socket::new(Domain::UNIX, Type::SEQPACKET, None)?;
+        sock.set_nonblocking(true)?;
+        let addr = SockAddr::unix(path)?;
+        // the blocking connect would stall the worker thread when the backlog is full,
+        // and the socket is not notified when there is room, so poll it by retrying
+        let mut delay = Duration::from_millis(1);
+        loop {
+            match sock.connect(&addr) {
+                Ok(()) => break,
+                Err(e) => match e.raw_os_error() {
+                    Some(libc::EISCONN) => break,
+                    Some(libc::EAGAIN) | Some(libc::EINPROGRESS) | Some(libc::EALREADY) => {
+                        coroutine::sleep(delay);
+                        delay = (delay * 2).min(Duration::from_millis(100));
+                    }
+                    Some(libc::EINTR) => {}
+                    _ => return Err(e),
+                },
+            }
+        }
+        SeqPacket::from_socket(sock)
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        SeqPacket::from_socket(self.0.inner().0.try_clone()?)
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.set_read_timeout(timeout)
+    }
+
+    /// send the data as one packet
+    pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let n = self.0.write(data).map_err(|e| {
+            if e.raw_os_error() == Some(libc::EMSGSIZE) {
+                let s = format!(
+                    "seqpacket packet exceeds the socket buffer. len={}",
+                    data.len()
+                );
+                io::Error::new(ErrorKind::InvalidInput, s)
+            } else {
+                e
+            }
+        })?;
+        if n != data.len() {
+            let s = format!("seqpacket partial send. len={} sent={}", data.len(), n);
+            return Err(io::Error::new(ErrorKind::WriteZero, s));
+        }
+        Ok(())
+    }
+
+    /// receive one packet, the packet bigger than the buf is an `InvalidData` error
+    /// the rest of the truncated packet is lost, so the connection should be closed
+    pub fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf)? {
+            0 => Err(ErrorKind::UnexpectedEof.into()),
+            n if n > buf.len() => {
+                let s = format!("seqpacket packet truncated. len={} buf={}", n, buf.len());
+                Err(io::Error::new(ErrorKind::InvalidData, s))
+            }
+            n => Ok(n),
+        }
+    }
+}
+
+/// unix seqpacket listener
+#[derive(Debug)]
+pub(crate) struct SeqPacketListener(CoIo<Socket>);
+
+impl SeqPacketListener {
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let sock = Socket::new(Domain::UNIX, Type::SEQPACKET, None)?;
+        sock.bind(&SockAddr::unix(path)?)?;
+        sock.listen(128)?;
+        sock.set_nonblocking(true)?;
+        Ok(SeqPacketListener(CoIo::new(sock)?))
+    }
+
+    /// accept a new connection, would block the coroutine until ready
+    pub fn accept(&self) -> io::Result<SeqPacket> {
+        loop {
+            self.0.reset_io();
+            match self.0.inner().accept() {
+                Ok((sock, _)) => return SeqPacket::from_socket(sock),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => self.0.wait_io(),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::io::{self, Cursor};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::compress::{Compression, Compressor};
+use crate::errors::Error;
+use crate::frame::{Frame, ReqBuf};
+use crate::seqpacket::{SeqPacket, MAX_PACKET_LEN};
+
+#[derive(Debug)]
+pub struct SeqPacketClient {
+    // each request would have a unique id
+    id: u64,
+    // the connection
+    sock: SeqPacket,
+    // recv buf, big enough for the max packet
+    buf: Vec<u8>,
+    // compress the requests if negotiated
+    compressor: Compressor,
+}
+
+impl SeqPacketClient {
+    /// connect to the server path
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<SeqPacketClient> {
+        Ok(SeqPacketClient {
+            id: 0,
+            sock: SeqPacket::connect(path)?,
+            buf: vec![0; MAX_PACKET_LEN],
+            compressor: Compressor::default(),
+        })
+    }
+
+    /// set timeout
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), io::Error> {
+        self.sock.set_read_timeout(Some(timeout))
+    }
+
+    /// set the compression settings, the compression is disabled by default
+    /// the requests are compressed once the server accepts one of the codecs
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compressor.set_config(compression);
+    }
+}
+
+impl SeqPacketClient {
+    /// call the server
+    /// the request must be encoded into the ReqBuf
+    /// the response is the raw frame, you should parsing it into final response
+    pub fn call_service(&mut self, req: ReqBuf) -> Result<Frame, Error> {
+        let id = self.id;
+        self.id += 1;
+        info!("request id = {}", id);
+
+        // each frame is sent as one packet
+        self.sock.send(&self.compressor.encode(req.finish(id)))?;
+
+        // read the response
+        loop {
+            let len = self.sock.recv(&mut self.buf)?;
+
+            // deserialize the rsp
+            let rsp_frame = Frame::decode_from(&mut Cursor::new(&self.buf[..len]))
+                .map_err(|e| Error::ClientDeserialize(e.to_string()))?;
+
+            // discard the rsp that does not belong to us
+            if rsp_frame.id == id {
+                info!("get response id = {}", id);
+                self.compressor.observe(&rsp_frame);
+                return Ok(rsp_frame);
+            }
+        }
+    }
+}
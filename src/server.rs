@@ -1,76 +1,269 @@
+use std::borrow::Cow;
 use std::io::{self, BufReader, Cursor};
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
 #[cfg(unix)]
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use crate::compress::Compression;
 use crate::frame::{Frame, RspBuf};
+use crate::loopback::LoopbackStream;
+#[cfg(feature = "noise")]
+use crate::noise::{NoiseConfig, NoiseStream};
 use crate::queued_writer::QueuedWriter;
-use crate::Server;
+#[cfg(target_os = "linux")]
+use crate::seqpacket::{SeqPacketListener, MAX_PACKET_LEN};
+use crate::stream_ext::StreamExt;
+#[cfg(feature = "tls")]
+use crate::tls::{PeerIdentity, TlsAcceptor};
+use crate::udp_batch::{QueuedSender, RecvBatch};
+use crate::udp_cache::{Lookup, RspCache};
+use crate::udp_frag::{self, Reassembler, UdpConfig};
+use crate::udp_seal::Sealer;
+#[cfg(feature = "websocket")]
+use crate::websocket::WsStream;
+use crate::{Server, WireError};
 
+use byteorder::{BigEndian, ReadBytesExt};
 use co_managed::Manager;
 use may::net::{TcpListener, UdpSocket};
 #[cfg(unix)]
-use may::os::unix::net::UnixListener;
+use may::os::unix::net::{UnixDatagram, UnixListener};
 use may::sync::Mutex;
 use may::{coroutine, go};
 
 /// service instance
-pub struct ServerInstance(Option<coroutine::JoinHandle<()>>);
+pub struct ServerInstance {
+    handle: Option<coroutine::JoinHandle<()>>,
+    // the bound address of the tcp and udp services
+    addr: Option<SocketAddr>,
+}
+
+impl ServerInstance {
+    fn new(handle: coroutine::JoinHandle<()>, addr: Option<SocketAddr>) -> Self {
+        ServerInstance {
+            handle: Some(handle),
+            addr,
+        }
+    }
+
+    /// the local address that the tcp or udp service is bound to
+    /// useful when binding to port 0, None for the other services
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+}
 
 impl Drop for ServerInstance {
     fn drop(&mut self) {
-        if let Some(s) = self.0.take() {
+        if let Some(s) = self.handle.take() {
             unsafe { s.coroutine().cancel() };
             s.join().ok();
         }
     }
 }
 
+// process the requests from the stream until the connection is closed
+fn serve_stream<F, S>(service: F, compression: Arc<Compression>, stream: S, tag: &str)
+where
+    F: Fn(&[u8], &mut RspBuf) -> Result<(), WireError> + Clone + Send + 'static,
+    S: StreamExt,
+{
+    let (rs, ws) = match stream.split() {
+        Ok(halves) => halves,
+        Err(e) => {
+            error!("{} server split stream: err = {:?}", tag, e);
+            return;
+        }
+    };
+    // the read half of the stream
+    let mut rs = BufReader::new(rs);
+    // the write half of the stream
+    let ws = Arc::new(QueuedWriter::new(ws));
+
+    loop {
+        let req = match Frame::decode_from(&mut rs) {
+            Ok(r) => r,
+            Err(ref e) => {
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    info!("{} server decode req: connection closed", tag);
+                } else {
+                    error!("{} server decode req: err = {:?}", tag, e);
+                }
+                break;
+            }
+        };
+
+        info!("get request: id={:?}", req.id);
+        let w_stream = ws.clone();
+        let service = service.clone();
+        let compression = compression.clone();
+        go!(move || {
+            let mut rsp = RspBuf::new();
+            let ret = service(req.decode_req(), &mut rsp);
+            let data = compression.respond(&req, rsp.finish(req.id, ret));
+
+            info!("send rsp: id={}", req.id);
+            // send the result back to client
+            w_stream.write(data);
+        });
+    }
+}
+
+/// Provides a function for serving an established stream connection.
+pub trait StreamServer: Server {
+    /// Spawns the service on the stream, e.g. a pipe or a shared memory stream
+    /// return a coroutine that you can cancel it when need to stop the service
+    fn serve<S: StreamExt>(self, stream: S) -> io::Result<ServerInstance> {
+        let instance = go!(
+            coroutine::Builder::new().name("StreamServer".to_owned()),
+            move || {
+                let compression = Arc::new(self.compression());
+                let server = Arc::new(self);
+                let service = move |req: &[u8], rsp: &mut RspBuf| server.service(req, rsp);
+                serve_stream(service, compression, stream, "stream")
+            }
+        )?;
+        Ok(ServerInstance::new(instance, None))
+    }
+
+    /// Spawns the service on an in-process loopback stream
+    /// return the service instance and the client side of the stream
+    fn serve_loopback(self) -> io::Result<(ServerInstance, LoopbackStream)> {
+        let (client, server) = LoopbackStream::pair();
+        let instance = self.serve(server)?;
+        Ok((instance, client))
+    }
+}
+
 /// Provides a function for starting the service.
 pub trait UdpServer: Server {
     /// Spawns the service, binding to the given address
     /// return a coroutine that you can cancel it when need to stop the service
     fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<ServerInstance> {
+        UdpServer::start_with_config(self, addr, UdpConfig::default())
+    }
+
+    /// Spawns the service with the given udp config
+    /// return a coroutine that you can cancel it when need to stop the service
+    fn start_with_config<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        config: UdpConfig,
+    ) -> io::Result<ServerInstance> {
+        config.check()?;
         let sock = UdpSocket::bind(addr)?; // the write half
+        let local_addr = sock.local_addr()?;
         let sock1 = sock.try_clone()?; // the read half
         let instance = go!(
             coroutine::Builder::new().name("UdpServer".to_owned()),
             move || {
+                let compression = Arc::new(self.compression());
                 let server = Arc::new(self);
-                let mut buf = vec![0u8; 1024];
-                // the write half need to be protected by mutex
-                // for that coroutine io obj can't shared safely
-                let sock = Arc::new(Mutex::new(sock));
+                let mut batch = RecvBatch::new(config.recv_buf_size);
+                let mut reassembler = Reassembler::new(&config);
+                let sealer = Sealer::new(&config);
+                let cache = match config.rsp_cache_size {
+                    0 => None,
+                    n => Some(Arc::new(Mutex::new(RspCache::new(
+                        n,
+                        config.rsp_cache_bytes,
+                        config.rsp_cache_ttl,
+                    )))),
+                };
+                // the responses are queued and sent in batch
+                let sender = Arc::new(QueuedSender::new(sock, config.mtu));
                 loop {
-                    // each udp packet should be less than 1024 bytes
-                    let (len, addr) = t!(sock1.recv_from(&mut buf));
-                    info!("recv_from: len={:?} addr={:?}", len, addr);
+                    // each udp packet should be less than the recv buf size
+                    let n = t!(batch.recv(&sock1));
+                    for i in 0..n {
+                        let (buf, addr) = batch.packet(i);
+                        info!("recv_from: len={:?} addr={:?}", buf.len(), addr);
 
-                    // if we failed to deserialize the request frame, just continue
-                    let req = t!(Frame::decode_from(&mut Cursor::new(&buf)));
-                    let sock = sock.clone();
-                    let server = server.clone();
-                    // let mutex = mutex.clone();
-                    go!(move || {
-                        let mut rsp = RspBuf::new();
-                        let ret = server.service(req.decode_req(), &mut rsp);
-                        let data = rsp.finish(req.id, ret);
+                        // if we failed to deserialize the request frame, just continue
+                        let data = if udp_frag::is_fragment(buf) {
+                            let id = t!((&buf[0..8]).read_u64::<BigEndian>());
+                            match t!(reassembler.push((addr, id), buf)) {
+                                Some(data) => Cow::Owned(data),
+                                None => continue,
+                            }
+                        } else {
+                            Cow::Borrowed(buf)
+                        };
+                        // the response is sealed to the sender of the request
+                        let opened = t!(sealer.open(data));
+                        let req = t!(Frame::decode_from(&mut Cursor::new(&*opened.data)));
+                        let session = opened.session;
 
-                        info!("send_to: len={:?} addr={:?}", data.len(), addr);
+                        // the sealed frame received before never runs the service again,
+                        // it's only answered by the cached response, e.g. retransmitted
+                        if !opened.fresh {
+                            if let Some(ref cache) = cache {
+                                // the lock is released before sending
+                                let data = cache
+                                    .lock()
+                                    .unwrap()
+                                    .cached(addr, req.id)
+                                    .map(<[u8]>::to_vec);
+                                if let Some(data) = data {
+                                    info!("resend cached rsp: id={}", req.id);
+                                    sender.send(data, addr);
+                                    continue;
+                                }
+                            }
+                            info!("drop replayed request: id={}", req.id);
+                            continue;
+                        }
 
-                        // send the result back to client
-                        // udp no need to protect by a mutex, each send would be one frame
-                        let s = sock.lock().unwrap();
-                        match s.send_to(&data, addr) {
-                            Ok(_) => {}
-                            Err(err) => error!("udp send_to failed, err={:?}", err),
+                        // the retransmitted request would not run the service again
+                        if let Some(ref cache) = cache {
+                            // the lock is released before sending
+                            let data = match cache.lock().unwrap().lookup(addr, req.id) {
+                                Lookup::Miss => None,
+                                Lookup::Pending => {
+                                    info!("drop duplicated request: id={}", req.id);
+                                    continue;
+                                }
+                                Lookup::Hit(data) => Some(data.to_vec()),
+                            };
+                            if let Some(data) = data {
+                                info!("resend cached rsp: id={}", req.id);
+                                sender.send(data, addr);
+                                continue;
+                            }
                         }
-                    });
+
+                        let sender = sender.clone();
+                        let sealer = sealer.clone();
+                        let server = server.clone();
+                        let compression = compression.clone();
+                        let cache = cache.clone();
+                        go!(move || {
+                            let mut rsp = RspBuf::new();
+                            let ret = server.service(req.decode_req(), &mut rsp);
+                            let data = compression.respond(&req, rsp.finish(req.id, ret));
+                            let data = match sealer.seal(data, session.as_deref()) {
+                                Ok(data) => data,
+                                Err(e) => {
+                                    error!("udp server seal rsp: err = {:?}", e);
+                                    return;
+                                }
+                            };
+
+                            if let Some(cache) = cache {
+                                cache.lock().unwrap().complete(addr, req.id, &data);
+                            }
+
+                            info!("send_to: len={:?} addr={:?}", data.len(), addr);
+
+                            // send the result back to client
+                            sender.send(data, addr);
+                        });
+                    }
                 }
             }
         )?;
-        Ok(ServerInstance(Some(instance)))
+        Ok(ServerInstance::new(instance, Some(local_addr)))
     }
 }
 
@@ -80,52 +273,187 @@ pub trait TcpServer: Server {
     /// return a coroutine that you can cancel it when need to stop the service
     fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<ServerInstance> {
         let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
         let instance = go!(
             coroutine::Builder::new().name("TcpServer".to_owned()),
             move || {
+                let compression = Arc::new(self.compression());
                 let server = Arc::new(self);
                 let manager = Manager::new();
                 for stream in listener.incoming() {
                     let stream = t!(stream);
                     let server = server.clone();
+                    let compression = compression.clone();
                     manager.add(move |_| {
-                        let rs = stream.try_clone().expect("failed to clone stream");
-                        // the read half of the stream
-                        let mut rs = BufReader::new(rs);
-                        // the write half of the stream
-                        let ws = Arc::new(QueuedWriter::new(stream));
+                        let service = move |req: &[u8], rsp: &mut RspBuf| server.service(req, rsp);
+                        serve_stream(service, compression, stream, "tcp")
+                    });
+                }
+            }
+        )?;
+        Ok(ServerInstance::new(instance, Some(local_addr)))
+    }
 
-                        loop {
-                            let req = match Frame::decode_from(&mut rs) {
-                                Ok(r) => r,
-                                Err(ref e) => {
-                                    if e.kind() == io::ErrorKind::UnexpectedEof {
-                                        info!("tcp server decode req: connection closed");
-                                    } else {
-                                        error!("tcp server decode req: err = {:?}", e);
-                                    }
-                                    break;
-                                }
-                            };
+    /// Spawns the noise encrypted service, binding to the given address
+    /// the handshake is done in the connection coroutine, the requests go to `NoiseServer::noise_service`
+    /// return a coroutine that you can cancel it when need to stop the service
+    #[cfg(feature = "noise")]
+    fn start_noise<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        config: NoiseConfig,
+    ) -> io::Result<ServerInstance>
+    where
+        Self: NoiseServer,
+    {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let instance = go!(
+            coroutine::Builder::new().name("TcpNoiseServer".to_owned()),
+            move || {
+                let compression = Arc::new(self.compression());
+                let server = Arc::new(self);
+                let config = Arc::new(config);
+                let manager = Manager::new();
+                for stream in listener.incoming() {
+                    let stream = t!(stream);
+                    let server = server.clone();
+                    let compression = compression.clone();
+                    let config = config.clone();
+                    manager.add(move |_| {
+                        let stream = match NoiseStream::accept(stream, &config) {
+                            Ok(s) => s,
+                            Err(e) => {
+                                error!("tcp noise server handshake: err = {:?}", e);
+                                return;
+                            }
+                        };
+                        let peer: Arc<[u8]> = stream.peer_key().into();
+                        let service = move |req: &[u8], rsp: &mut RspBuf| {
+                            server.noise_service(&peer, req, rsp)
+                        };
+                        serve_stream(service, compression, stream, "tcp")
+                    });
+                }
+            }
+        )?;
+        Ok(ServerInstance::new(instance, Some(local_addr)))
+    }
 
-                            info!("get request: id={:?}", req.id);
-                            let w_stream = ws.clone();
-                            let server = server.clone();
-                            go!(move || {
-                                let mut rsp = RspBuf::new();
-                                let ret = server.service(req.decode_req(), &mut rsp);
-                                let data = rsp.finish(req.id, ret);
+    /// Spawns the websocket service, binding to the given address
+    /// each binary message carries one frame, the handshake is done in the connection coroutine
+    /// return a coroutine that you can cancel it when need to stop the service
+    #[cfg(feature = "websocket")]
+    fn start_ws<L: ToSocketAddrs>(self, addr: L) -> io::Result<ServerInstance> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let instance = go!(
+            coroutine::Builder::new().name("WsServer".to_owned()),
+            move || {
+                let compression = Arc::new(self.compression());
+                let server = Arc::new(self);
+                let manager = Manager::new();
+                for stream in listener.incoming() {
+                    let stream = t!(stream);
+                    let server = server.clone();
+                    let compression = compression.clone();
+                    manager.add(move |_| {
+                        let stream = match WsStream::accept(stream) {
+                            Ok(s) => s,
+                            Err(e) => {
+                                error!("ws server handshake: err = {:?}", e);
+                                return;
+                            }
+                        };
+                        let service = move |req: &[u8], rsp: &mut RspBuf| server.service(req, rsp);
+                        serve_stream(service, compression, stream, "ws")
+                    });
+                }
+            }
+        )?;
+        Ok(ServerInstance::new(instance, Some(local_addr)))
+    }
+}
 
-                                info!("send rsp: id={}", req.id);
-                                // send the result back to client
-                                w_stream.write(data);
-                            });
-                        }
+/// Provides the noise service that knows the authenticated peer key
+/// implement it for your server to start the noise service, e.g. `impl NoiseServer for MyServer {}`
+#[cfg(feature = "noise")]
+pub trait NoiseServer: Server {
+    /// the service for the noise connections, the peer is the authenticated public key of the client
+    /// the default implementation just ignores the peer and calls `service`
+    fn noise_service(&self, peer: &[u8], req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        let _ = peer;
+        self.service(req, rsp)
+    }
+}
+
+/// Provides the tls service that knows the verified client identity
+/// implement it for your server to start the tls service, e.g. `impl TlsServer for MyServer {}`
+#[cfg(feature = "tls")]
+pub trait TlsServer: Server {
+    /// the service for the tls connections, the peer is the verified client identity
+    /// it's None if the server doesn't require client certificates
+    /// the default implementation just ignores the peer and calls `service`
+    fn tls_service(
+        &self,
+        peer: Option<&PeerIdentity>,
+        req: &[u8],
+        rsp: &mut RspBuf,
+    ) -> Result<(), WireError> {
+        let _ = peer;
+        self.service(req, rsp)
+    }
+
+    /// Spawns the tls service, binding to the given address
+    /// the handshake is done in the connection coroutine
+    /// return a coroutine that you can cancel it when need to stop the service
+    fn start_tls<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        acceptor: TlsAcceptor,
+    ) -> io::Result<ServerInstance> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let instance = go!(
+            coroutine::Builder::new().name("TlsServer".to_owned()),
+            move || {
+                let compression = Arc::new(self.compression());
+                let server = Arc::new(self);
+                let manager = Manager::new();
+                for stream in listener.incoming() {
+                    let stream = t!(stream);
+                    let server = server.clone();
+                    let compression = compression.clone();
+                    let acceptor = acceptor.clone();
+                    manager.add(move |_| {
+                        let stream = match acceptor.accept(stream) {
+                            Ok(s) => s,
+                            Err(e) => {
+                                error!("tls server handshake: err = {:?}", e);
+                                return;
+                            }
+                        };
+                        let peer = stream.peer_identity().map(Arc::new);
+                        let service = move |req: &[u8], rsp: &mut RspBuf| {
+                            server.tls_service(peer.as_deref(), req, rsp)
+                        };
+                        serve_stream(service, compression, stream, "tls")
                     });
                 }
             }
         )?;
-        Ok(ServerInstance(Some(instance)))
+        Ok(ServerInstance::new(instance, Some(local_addr)))
+    }
+}
+
+// the socket bound to the path, unlink the path when the server is dropped
+#[cfg(unix)]
+struct UnlinkOnDrop<T>(T, PathBuf);
+
+#[cfg(unix)]
+impl<T> Drop for UnlinkOnDrop<T> {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.1).ok();
     }
 }
 
@@ -135,66 +463,215 @@ pub trait UdsServer: Server {
     /// Spawns the service, binding to the given address
     /// return a coroutine that you can cancel it when need to stop the service
     fn start<P: AsRef<Path>>(self, path: P) -> io::Result<ServerInstance> {
-        struct AutoDrop(UnixListener, PathBuf);
-        impl Drop for AutoDrop {
-            fn drop(&mut self) {
-                std::fs::remove_file(&self.1).ok();
+        std::fs::remove_file(&path).ok();
+        let listener = UnlinkOnDrop(UnixListener::bind(&path)?, path.as_ref().to_owned());
+        let instance = go!(
+            coroutine::Builder::new().name("Unix Socket Server".to_owned()),
+            move || {
+                let compression = Arc::new(self.compression());
+                let server = Arc::new(self);
+                let manager = Manager::new();
+                for stream in listener.0.incoming() {
+                    let stream = t!(stream);
+                    let server = server.clone();
+                    let compression = compression.clone();
+                    manager.add(move |_| {
+                        let service = move |req: &[u8], rsp: &mut RspBuf| server.service(req, rsp);
+                        serve_stream(service, compression, stream, "uds")
+                    });
+                }
             }
-        }
+        )?;
+        Ok(ServerInstance::new(instance, None))
+    }
 
+    /// Spawns the noise encrypted service, binding to the given address
+    /// the handshake is done in the connection coroutine, the requests go to `NoiseServer::noise_service`
+    /// return a coroutine that you can cancel it when need to stop the service
+    #[cfg(feature = "noise")]
+    fn start_noise<P: AsRef<Path>>(self, path: P, config: NoiseConfig) -> io::Result<ServerInstance>
+    where
+        Self: NoiseServer,
+    {
         std::fs::remove_file(&path).ok();
-        let listener = AutoDrop(UnixListener::bind(&path)?, path.as_ref().to_owned());
+        let listener = UnlinkOnDrop(UnixListener::bind(&path)?, path.as_ref().to_owned());
         let instance = go!(
-            coroutine::Builder::new().name("Unix Socket Server".to_owned()),
+            coroutine::Builder::new().name("Unix Socket Noise Server".to_owned()),
             move || {
+                let compression = Arc::new(self.compression());
                 let server = Arc::new(self);
+                let config = Arc::new(config);
                 let manager = Manager::new();
                 for stream in listener.0.incoming() {
                     let stream = t!(stream);
                     let server = server.clone();
+                    let compression = compression.clone();
+                    let config = config.clone();
+                    manager.add(move |_| {
+                        let stream = match NoiseStream::accept(stream, &config) {
+                            Ok(s) => s,
+                            Err(e) => {
+                                error!("uds noise server handshake: err = {:?}", e);
+                                return;
+                            }
+                        };
+                        let peer: Arc<[u8]> = stream.peer_key().into();
+                        let service = move |req: &[u8], rsp: &mut RspBuf| {
+                            server.noise_service(&peer, req, rsp)
+                        };
+                        serve_stream(service, compression, stream, "uds")
+                    });
+                }
+            }
+        )?;
+        Ok(ServerInstance::new(instance, None))
+    }
+}
+
+/// the default buffer size for the unix datagram socket
+#[cfg(unix)]
+pub const UDS_DGRAM_BUF_SIZE: usize = 64 * 1024;
+
+/// Provides a function for starting the unix datagram socket service.
+#[cfg(unix)]
+pub trait UdsDatagramServer: Server {
+    /// Spawns the service, binding to the given path
+    /// return a coroutine that you can cancel it when need to stop the service
+    fn start<P: AsRef<Path>>(self, path: P) -> io::Result<ServerInstance> {
+        UdsDatagramServer::start_with_buf_size(self, path, UDS_DGRAM_BUF_SIZE)
+    }
+
+    /// Spawns the service with the given receive buffer size
+    /// each request frame must fit in one datagram
+    fn start_with_buf_size<P: AsRef<Path>>(
+        self,
+        path: P,
+        buf_size: usize,
+    ) -> io::Result<ServerInstance> {
+        std::fs::remove_file(&path).ok();
+        let sock = UnlinkOnDrop(UnixDatagram::bind(&path)?, path.as_ref().to_owned());
+        let sock1 = sock.0.try_clone()?; // the write half
+        let instance = go!(
+            coroutine::Builder::new().name("UdsDatagramServer".to_owned()),
+            move || {
+                let compression = Arc::new(self.compression());
+                let server = Arc::new(self);
+                let mut buf = vec![0u8; buf_size];
+                // the write half need to be protected by mutex
+                // for that coroutine io obj can't shared safely
+                let w_sock = Arc::new(Mutex::new(sock1));
+                loop {
+                    let (len, addr) = t!(sock.0.recv_from(&mut buf));
+                    info!("recv_from: len={:?} addr={:?}", len, addr);
+
+                    // the client must bind to a path to get the response
+                    let peer = match addr.as_pathname() {
+                        Some(p) => p.to_owned(),
+                        None => {
+                            error!("uds datagram server: unbound peer, drop the request");
+                            continue;
+                        }
+                    };
+
+                    // if we failed to deserialize the request frame, just continue
+                    let req = t!(Frame::decode_from(&mut Cursor::new(&buf[..len])));
+                    let w_sock = w_sock.clone();
+                    let server = server.clone();
+                    let compression = compression.clone();
+                    go!(move || {
+                        let mut rsp = RspBuf::new();
+                        let ret = server.service(req.decode_req(), &mut rsp);
+                        let data = compression.respond(&req, rsp.finish(req.id, ret));
+
+                        info!("send_to: len={:?} addr={:?}", data.len(), peer);
+
+                        // send the result back to client
+                        let s = w_sock.lock().unwrap();
+                        if let Err(err) = s.send_to(&data, &peer) {
+                            error!("uds datagram send_to failed, err={:?}", err);
+                        }
+                    });
+                }
+            }
+        )?;
+        Ok(ServerInstance::new(instance, None))
+    }
+}
+
+/// Provides a function for starting the unix seqpacket socket service.
+/// each frame is carried by exactly one packet, the frames bigger than the
+/// socket buffer, which is capped by the `net.core.wmem_max` sysctl, can't be sent
+#[cfg(target_os = "linux")]
+pub trait UdsSeqPacketServer: Server {
+    /// Spawns the service, binding to the given path
+    /// return a coroutine that you can cancel it when need to stop the service
+    fn start<P: AsRef<Path>>(self, path: P) -> io::Result<ServerInstance> {
+        std::fs::remove_file(&path).ok();
+        let listener = UnlinkOnDrop(SeqPacketListener::bind(&path)?, path.as_ref().to_owned());
+        let instance = go!(
+            coroutine::Builder::new().name("SeqPacket Server".to_owned()),
+            move || {
+                let compression = Arc::new(self.compression());
+                let server = Arc::new(self);
+                let manager = Manager::new();
+                loop {
+                    let mut rs = t!(listener.0.accept());
+                    let server = server.clone();
+                    let compression = compression.clone();
                     manager.add(move |_| {
-                        let rs = stream.try_clone().expect("failed to clone stream");
-                        // the read half of the stream
-                        let mut rs = BufReader::new(rs);
                         // the write half need to be protected by mutex
-                        // for that coroutine io obj can't shared safely
-                        let ws = Arc::new(QueuedWriter::new(stream));
+                        // each frame must be written as one packet
+                        let ws = Arc::new(Mutex::new(rs.try_clone().expect("failed to clone")));
+                        // each connection holds a buffer for the max frame plus the head
+                        // the truncated packet is an error that closes the connection
+                        let mut buf = vec![0u8; MAX_PACKET_LEN];
 
                         loop {
-                            let req = match Frame::decode_from(&mut rs) {
+                            let req = match rs
+                                .recv(&mut buf)
+                                .and_then(|len| Frame::decode_from(&mut Cursor::new(&buf[..len])))
+                            {
                                 Ok(r) => r,
                                 Err(ref e) => {
                                     if e.kind() == io::ErrorKind::UnexpectedEof {
-                                        info!("uds server decode req: connection closed");
+                                        info!("seqpacket server decode req: connection closed");
                                     } else {
-                                        error!("uds server decode req: err = {:?}", e);
+                                        error!("seqpacket server decode req: err = {:?}", e);
                                     }
                                     break;
                                 }
                             };
 
                             info!("get request: id={:?}", req.id);
-                            let w_stream = ws.clone();
+                            let ws = ws.clone();
                             let server = server.clone();
+                            let compression = compression.clone();
                             go!(move || {
                                 let mut rsp = RspBuf::new();
                                 let ret = server.service(req.decode_req(), &mut rsp);
-                                let data = rsp.finish(req.id, ret);
+                                let data = compression.respond(&req, rsp.finish(req.id, ret));
 
                                 info!("send rsp: id={}", req.id);
                                 // send the result back to client
-                                w_stream.write(data);
+                                if let Err(e) = ws.lock().unwrap().send(&data) {
+                                    error!("seqpacket server send rsp: err = {:?}", e);
+                                }
                             });
                         }
                     });
                 }
             }
         )?;
-        Ok(ServerInstance(Some(instance)))
+        Ok(ServerInstance::new(instance, None))
     }
 }
 
+impl<T: Server> StreamServer for T {}
 impl<T: Server> UdpServer for T {}
 impl<T: Server> TcpServer for T {}
 #[cfg(unix)]
 impl<T: Server> UdsServer for T {}
+#[cfg(unix)]
+impl<T: Server> UdsDatagramServer for T {}
+#[cfg(target_os = "linux")]
+impl<T: Server> UdsSeqPacketServer for T {}
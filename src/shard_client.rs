@@ -0,0 +1,170 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cancel::CancelToken;
+use crate::errors::Error;
+use crate::frame::{Frame, ReqBuf};
+use crate::multiplex_client::LazyClient;
+use crate::retry::RetryPolicy;
+use crate::stream_ext::StreamExt;
+use crate::Client;
+
+use may::sync::RwLock;
+
+// FNV-1a, the hash must be stable across the processes that share the nodes
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// the splitmix64 finalizer, spread the combined hash over all the bits
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+struct Node<S: StreamExt> {
+    name: String,
+    // the hash of the name
+    seed: u64,
+    client: LazyClient<S>,
+}
+
+impl<S: StreamExt> Node<S> {
+    // the node with the highest score owns the key
+    fn score(&self, key_hash: u64) -> u64 {
+        mix(self.seed ^ key_hash)
+    }
+}
+
+/// client that routes each call to one of the nodes by the key
+/// the nodes are picked by rendezvous hashing, so adding or removing
+/// a node only moves the keys that it owns
+pub struct ShardClient<S: StreamExt> {
+    nodes: RwLock<Vec<Arc<Node<S>>>>,
+    // no timeout by default, the calls wait until the connection is closed
+    timeout: Option<Duration>,
+}
+
+impl<S: StreamExt> std::fmt::Debug for ShardClient<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShardClient")
+            .field("nodes", &self.nodes())
+            .finish()
+    }
+}
+
+impl<S: StreamExt> Default for ShardClient<S> {
+    fn default() -> Self {
+        ShardClient::new()
+    }
+}
+
+impl<S: StreamExt> ShardClient<S> {
+    /// create the client without any node
+    pub fn new() -> Self {
+        ShardClient {
+            nodes: RwLock::new(Vec::new()),
+            timeout: None,
+        }
+    }
+
+    /// set the default timeout value
+    /// there is no timeout initially, the calls wait until the connection is closed
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// add the node, the name identifies the node and decides the keys it owns
+    /// the node with the same name is replaced
+    /// no connection is created until the first call to the node
+    pub fn add_node<F>(&self, name: &str, connect: F)
+    where
+        F: Fn() -> io::Result<S> + Send + Sync + 'static,
+    {
+        let node = Arc::new(Node {
+            name: name.to_owned(),
+            seed: fnv1a(name.as_bytes()),
+            client: LazyClient::new(connect),
+        });
+        let mut nodes = self.nodes.write().unwrap();
+        nodes.retain(|n| n.name != name);
+        nodes.push(node);
+    }
+
+    /// remove the node, return false if there is no such node
+    pub fn remove_node(&self, name: &str) -> bool {
+        let mut nodes = self.nodes.write().unwrap();
+        let len = nodes.len();
+        nodes.retain(|n| n.name != name);
+        nodes.len() != len
+    }
+
+    /// the names of the nodes
+    pub fn nodes(&self) -> Vec<String> {
+        let nodes = self.nodes.read().unwrap();
+        nodes.iter().map(|n| n.name.clone()).collect()
+    }
+
+    fn pick(&self, key: &[u8]) -> Option<Arc<Node<S>>> {
+        let key_hash = fnv1a(key);
+        let nodes = self.nodes.read().unwrap();
+        nodes.iter().max_by_key(|n| n.score(key_hash)).cloned()
+    }
+
+    /// the name of the node that owns the key
+    pub fn node_for(&self, key: &[u8]) -> Option<String> {
+        self.pick(key).map(|n| n.name.clone())
+    }
+
+    /// call the node that owns the key
+    pub fn call_service(&self, key: &[u8], req: ReqBuf) -> Result<Frame, Error> {
+        self.call_with(key, req, None)
+    }
+
+    // call the node that owns the key, the call is abandoned once the token is cancelled
+    fn call_with(
+        &self,
+        key: &[u8],
+        req: ReqBuf,
+        token: Option<&CancelToken>,
+    ) -> Result<Frame, Error> {
+        let node = self.pick(key).ok_or(Error::NoNodes)?;
+        let client = node.client.get()?;
+        match token {
+            Some(token) => client.call_cancellable_timeout(req, self.timeout, token),
+            None => client.call_timeout(req, self.timeout),
+        }
+    }
+
+    /// the client that calls the node that owns the key
+    /// the node is picked for each call, so it follows the node changes
+    pub fn for_key<'a>(&'a self, key: &'a [u8]) -> ShardKey<'a, S> {
+        ShardKey { shard: self, key }
+    }
+}
+
+/// the client of one key that is got by `ShardClient::for_key`
+#[derive(Debug)]
+pub struct ShardKey<'a, S: StreamExt> {
+    shard: &'a ShardClient<S>,
+    key: &'a [u8],
+}
+
+impl<S: StreamExt> Client for ShardKey<'_, S> {
+    fn call_service(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.shard.call_service(self.key, req)
+    }
+
+    /// each attempt picks the node again, so the retry follows the node changes
+    fn call_with_retry(&self, req: ReqBuf, policy: &RetryPolicy) -> Result<Frame, Error> {
+        policy.retry(|| self.shard.call_with(self.key, req.clone(), None))
+    }
+
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        self.shard.call_with(self.key, req, Some(token))
+    }
+}
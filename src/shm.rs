@@ -0,0 +1,464 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::fd::Fd;
+use crate::stream_ext::StreamExt;
+
+use may::io::CoIo;
+use may::{coroutine, go};
+
+// Shared memory layout
+// ring0 head(u64) at 0, ring0 tail(u64) at 64
+// ring1 head(u64) at 128, ring1 tail(u64) at 192
+// write shut flags([u32; 2]) at 256, read shut flags([u32; 2]) at 264
+// ring0 data at PAGE_SIZE, ring1 data at PAGE_SIZE + RING_SIZE
+//
+// side 0 writes ring0 and reads ring1, side 1 is the opposite
+
+const PAGE_SIZE: usize = 4096;
+const RING_SIZE: usize = 256 * 1024;
+const SHM_SIZE: usize = PAGE_SIZE + 2 * RING_SIZE;
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+type EventFd = Fd;
+
+fn eventfd() -> io::Result<EventFd> {
+    let fd = cvt(unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) })?;
+    Ok(Fd(fd))
+}
+
+// the connected unix sockets, each side holds one end
+// the end is closed by the kernel when the process exits, even if it crashed
+fn socketpair() -> io::Result<(Fd, Fd)> {
+    let mut fds = [0; 2];
+    let ty = libc::SOCK_STREAM | libc::SOCK_CLOEXEC;
+    cvt(unsafe { libc::socketpair(libc::AF_UNIX, ty, 0, fds.as_mut_ptr()) })?;
+    Ok((Fd(fds[0]), Fd(fds[1])))
+}
+
+// the bytes in the ring, the positions can be written by a broken peer,
+// so they are checked before the data is touched
+fn ring_len(head: u64, tail: u64) -> io::Result<usize> {
+    let len = head.wrapping_sub(tail);
+    if len > RING_SIZE as u64 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "shm stream: corrupted ring positions",
+        ));
+    }
+    Ok(len as usize)
+}
+
+// wake up the waiter, the count is kept until the waiter reads it
+fn notify(bell: &EventFd) {
+    let one = 1u64;
+    unsafe { libc::write(bell.0, &one as *const u64 as *const libc::c_void, 8) };
+}
+
+/// the raw fds of one side of the shared memory stream
+/// they can be passed to another process by `SCM_RIGHTS`, they are created
+/// with `FD_CLOEXEC`, so clear the flag first to inherit them across `exec`
+///
+/// the peer is found gone once all the copies of its `link` are closed,
+/// so don't keep the passed fds open in the sending process
+#[derive(Debug, Clone, Copy)]
+pub struct ShmFds {
+    /// the memfd that holds the rings
+    pub memfd: RawFd,
+    /// 0 or 1, the two sides must be different
+    pub side: u8,
+    /// notified when data is written to our read ring
+    pub data_bell: RawFd,
+    /// notified when space is freed in our write ring
+    pub space_bell: RawFd,
+    /// the peer's data bell
+    pub peer_data_bell: RawFd,
+    /// the peer's space bell
+    pub peer_space_bell: RawFd,
+    /// our end of the socket pair that tells if the peer process is alive
+    pub link: RawFd,
+}
+
+// wait for the peer end of the socket pair to close, nothing is sent on it,
+// so it's only readable once the peer is gone, even if it crashed
+// then mark the peer gone and wake up our waiting reader and writer
+fn watch_peer(mut link: CoIo<Fd>, peer_gone: Arc<AtomicBool>, bells: [EventFd; 2]) {
+    let mut buf = [0u8; 8];
+    loop {
+        match link.read(&mut buf) {
+            Ok(0) => break,
+            Ok(_) => continue,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                error!("shm stream: watch peer err = {:?}", e);
+                break;
+            }
+        }
+    }
+    peer_gone.store(true, Ordering::Release);
+    for bell in bells.iter() {
+        notify(bell);
+    }
+}
+
+// the mapped memory that shared by the halves of one side
+#[derive(Debug)]
+struct Region {
+    ptr: *mut u8,
+    memfd: Fd,
+    side: usize,
+    peer_data_bell: EventFd,
+    peer_space_bell: EventFd,
+    // our end of the socket pair, owned by the watcher
+    link: RawFd,
+    // set by the watcher, the peer can't set the shut flags if it crashed
+    peer_gone: Arc<AtomicBool>,
+    watcher: Option<coroutine::JoinHandle<()>>,
+}
+
+unsafe impl Send for Region {}
+unsafe impl Sync for Region {}
+
+impl Region {
+    fn head(&self, ring: usize) -> &AtomicU64 {
+        unsafe { &*(self.ptr.add(ring * 128) as *const AtomicU64) }
+    }
+
+    fn tail(&self, ring: usize) -> &AtomicU64 {
+        unsafe { &*(self.ptr.add(ring * 128 + 64) as *const AtomicU64) }
+    }
+
+    // the side writes nothing more, its peer reads EOF after the ring is drained
+    fn write_shut(&self, side: usize) -> &AtomicU32 {
+        unsafe { &*(self.ptr.add(256 + side * 4) as *const AtomicU32) }
+    }
+
+    // the side reads nothing more, its peer fails to write
+    fn read_shut(&self, side: usize) -> &AtomicU32 {
+        unsafe { &*(self.ptr.add(264 + side * 4) as *const AtomicU32) }
+    }
+
+    fn data(&self, ring: usize) -> *mut u8 {
+        unsafe { self.ptr.add(PAGE_SIZE + ring * RING_SIZE) }
+    }
+
+    fn peer_gone(&self) -> bool {
+        self.peer_gone.load(Ordering::Acquire)
+    }
+
+    fn peer_write_shut(&self) -> bool {
+        self.write_shut(1 - self.side).load(Ordering::Acquire) != 0 || self.peer_gone()
+    }
+
+    fn peer_read_shut(&self) -> bool {
+        self.read_shut(1 - self.side).load(Ordering::Acquire) != 0 || self.peer_gone()
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        // the link is closed once the watcher exits, so the peer finds us gone
+        if let Some(h) = self.watcher.take() {
+            unsafe { h.coroutine().cancel() };
+        }
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, SHM_SIZE) };
+    }
+}
+
+// the read direction of one side, shut once the reader is dropped
+#[derive(Debug)]
+struct ReadEnd(Arc<Region>);
+
+impl Drop for ReadEnd {
+    fn drop(&mut self) {
+        let region = &self.0;
+        region.read_shut(region.side).store(1, Ordering::Release);
+        notify(&region.peer_space_bell);
+    }
+}
+
+// the write direction of one side, shut once the writer is dropped
+#[derive(Debug)]
+struct WriteEnd(Arc<Region>);
+
+impl Drop for WriteEnd {
+    fn drop(&mut self) {
+        let region = &self.0;
+        region.write_shut(region.side).store(1, Ordering::Release);
+        notify(&region.peer_data_bell);
+    }
+}
+
+/// shared memory stream for the same host ipc
+/// the data is transferred by a pair of rings in a memfd
+/// and the wakeup is done by eventfd
+#[derive(Debug)]
+pub struct ShmStream {
+    reader: ShmReader,
+    writer: ShmWriter,
+}
+
+impl ShmStream {
+    /// create a connected pair of shared memory streams
+    pub fn pair() -> io::Result<(ShmStream, ShmStream)> {
+        // each fd is owned by `Fd` once created, so it's closed on the errors
+        let (link0, link1) = socketpair()?;
+        let name = b"conetty-shm\0";
+        let memfd = Fd(cvt(unsafe {
+            libc::memfd_create(name.as_ptr() as *const libc::c_char, libc::MFD_CLOEXEC)
+        })?);
+        cvt(unsafe { libc::ftruncate(memfd.0, SHM_SIZE as libc::off_t) })?;
+        let memfd1 = memfd.try_clone()?;
+
+        let (data0, space0) = (eventfd()?, eventfd()?);
+        let (data1, space1) = (eventfd()?, eventfd()?);
+        let s0 = ShmStream::new(
+            memfd,
+            0,
+            [data0.try_clone()?, space0.try_clone()?],
+            [data1.try_clone()?, space1.try_clone()?],
+            link0,
+        )?;
+        let s1 = ShmStream::new(memfd1, 1, [data1, space1], [data0, space0], link1)?;
+        Ok((s0, s1))
+    }
+
+    /// create the stream from the raw fds, the fds are owned by the stream
+    ///
+    /// # Safety
+    ///
+    /// the fds must be valid and come from the `raw_fds` of a stream created by `pair`
+    pub unsafe fn from_raw_fds(fds: ShmFds) -> io::Result<Self> {
+        ShmStream::new(
+            Fd(fds.memfd),
+            fds.side as usize,
+            [Fd(fds.data_bell), Fd(fds.space_bell)],
+            [Fd(fds.peer_data_bell), Fd(fds.peer_space_bell)],
+            Fd(fds.link),
+        )
+    }
+
+    /// get the raw fds of the stream, they are still owned by the stream
+    pub fn raw_fds(&self) -> ShmFds {
+        let region = &self.reader.end.0;
+        ShmFds {
+            memfd: region.memfd.0,
+            side: region.side as u8,
+            data_bell: self.reader.data_bell.as_raw_fd(),
+            space_bell: self.writer.space_bell.as_raw_fd(),
+            peer_data_bell: region.peer_data_bell.0,
+            peer_space_bell: region.peer_space_bell.0,
+            link: region.link,
+        }
+    }
+
+    // map the memfd, wrap the bells and watch the peer
+    fn new(
+        memfd: Fd,
+        side: usize,
+        bells: [EventFd; 2],
+        peer: [EventFd; 2],
+        link: Fd,
+    ) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                SHM_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                memfd.0,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        let [peer_data_bell, peer_space_bell] = peer;
+        let mut region = Region {
+            ptr: ptr as *mut u8,
+            memfd,
+            side: side & 1,
+            peer_data_bell,
+            peer_space_bell,
+            link: link.0,
+            peer_gone: Arc::new(AtomicBool::new(false)),
+            watcher: None,
+        };
+        let [data_bell, space_bell] = bells;
+
+        // the watcher wakes up our reader and writer by their own bells
+        let link = CoIo::new(link)?;
+        let peer_gone = region.peer_gone.clone();
+        let wake = [data_bell.try_clone()?, space_bell.try_clone()?];
+        let watcher = go!(
+            coroutine::Builder::new().name("ShmPeerWatcher".to_owned()),
+            move || watch_peer(link, peer_gone, wake)
+        )?;
+        region.watcher = Some(watcher);
+
+        let region = Arc::new(region);
+        let reader = ShmReader {
+            end: ReadEnd(region.clone()),
+            data_bell: CoIo::new(data_bell)?,
+            read_timeout: None,
+        };
+        let writer = ShmWriter {
+            end: WriteEnd(region),
+            space_bell: CoIo::new(space_bell)?,
+        };
+        Ok(ShmStream { reader, writer })
+    }
+
+    // wait for the bell, return the timeout error if any
+    // the bell is also rung by the watcher once the peer is gone,
+    // so the caller checks the shut flags again after it returns
+    fn wait(bell: &mut CoIo<EventFd>, timeout: Option<Duration>) -> io::Result<()> {
+        bell.set_read_timeout(timeout)?;
+        let mut buf = [0u8; 8];
+        match bell.read_exact(&mut buf) {
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Err(ErrorKind::TimedOut.into()),
+            ret => ret,
+        }
+    }
+}
+
+impl Read for ShmStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for ShmStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl StreamExt for ShmStream {
+    type Reader = ShmReader;
+    type Writer = ShmWriter;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        Ok((self.reader, self.writer))
+    }
+
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.reader.read_timeout = Some(timeout);
+        Ok(())
+    }
+}
+
+/// the read half of the `ShmStream`
+#[derive(Debug)]
+pub struct ShmReader {
+    end: ReadEnd,
+    data_bell: CoIo<EventFd>,
+    // the timeout of waiting for the data
+    read_timeout: Option<Duration>,
+}
+
+/// the write half of the `ShmStream`
+/// the peer reads EOF once it is dropped
+#[derive(Debug)]
+pub struct ShmWriter {
+    end: WriteEnd,
+    space_bell: CoIo<EventFd>,
+}
+
+impl Read for ShmReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let region = &self.end.0;
+        let ring = 1 - region.side;
+        loop {
+            let tail = region.tail(ring).load(Ordering::Relaxed);
+            let head = region.head(ring).load(Ordering::Acquire);
+            let avail = ring_len(head, tail)?;
+            if avail == 0 {
+                if region.peer_write_shut() {
+                    // check again, the peer may write data before shut
+                    if region.head(ring).load(Ordering::Acquire) == tail {
+                        return Ok(0);
+                    }
+                    continue;
+                }
+                ShmStream::wait(&mut self.data_bell, self.read_timeout)?;
+                continue;
+            }
+
+            let n = avail.min(buf.len());
+            let pos = tail as usize % RING_SIZE;
+            let first = n.min(RING_SIZE - pos);
+            unsafe {
+                let data = region.data(ring);
+                std::ptr::copy_nonoverlapping(data.add(pos), buf.as_mut_ptr(), first);
+                std::ptr::copy_nonoverlapping(data, buf.as_mut_ptr().add(first), n - first);
+            }
+            region
+                .tail(ring)
+                .store(tail.wrapping_add(n as u64), Ordering::Release);
+            notify(&region.peer_space_bell);
+            return Ok(n);
+        }
+    }
+}
+
+impl Write for ShmWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let region = &self.end.0;
+        let ring = region.side;
+        loop {
+            if region.peer_read_shut() {
+                return Err(ErrorKind::BrokenPipe.into());
+            }
+
+            let head = region.head(ring).load(Ordering::Relaxed);
+            let tail = region.tail(ring).load(Ordering::Acquire);
+            let free = RING_SIZE - ring_len(head, tail)?;
+            if free == 0 {
+                ShmStream::wait(&mut self.space_bell, None)?;
+                continue;
+            }
+
+            let n = free.min(buf.len());
+            let pos = head as usize % RING_SIZE;
+            let first = n.min(RING_SIZE - pos);
+            unsafe {
+                let data = region.data(ring);
+                std::ptr::copy_nonoverlapping(buf.as_ptr(), data.add(pos), first);
+                std::ptr::copy_nonoverlapping(buf.as_ptr().add(first), data, n - first);
+            }
+            region
+                .head(ring)
+                .store(head.wrapping_add(n as u64), Ordering::Release);
+            notify(&region.peer_data_bell);
+            return Ok(n);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
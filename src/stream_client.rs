@@ -1,15 +1,24 @@
-use std::io::{self, BufReader};
+use std::io::{self, BufReader, Write};
 use std::time::Duration;
 
+use crate::compress::{Compression, Compressor};
 use crate::errors::Error;
 use crate::frame::{Frame, ReqBuf};
 use crate::stream_ext::StreamExt;
+use crate::vec_bufs::VecBufs;
+
+// the batch is written in windows of at most this size, the responses of
+// a window are read before the next is written, so that the server is not
+// blocked on writing the responses while the client is still writing
+const MAX_BATCH_BYTES: usize = 64 * 1024;
 
 pub struct StreamClient<S: StreamExt> {
     // each request would have a unique id
     id: u64,
     // the connection
     stream: BufReader<S>,
+    // compress the requests if negotiated
+    compressor: Compressor,
 }
 
 impl<S: StreamExt> StreamClient<S> {
@@ -18,6 +27,7 @@ impl<S: StreamExt> StreamClient<S> {
         StreamClient {
             id: 0,
             stream: BufReader::with_capacity(1024, stream),
+            compressor: Compressor::default(),
         }
     }
 }
@@ -27,6 +37,12 @@ impl<S: StreamExt> StreamClient<S> {
     pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), io::Error> {
         self.stream.get_mut().set_read_timeout(timeout)
     }
+
+    /// set the compression settings, the compression is disabled by default
+    /// the requests are compressed once the server accepts one of the codecs
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compressor.set_config(compression);
+    }
 }
 
 impl<S: StreamExt> StreamClient<S> {
@@ -39,7 +55,8 @@ impl<S: StreamExt> StreamClient<S> {
         info!("request id = {}", id);
 
         // encode the request
-        self.stream.get_mut().write_all(&(req.finish(id)))?;
+        let buf = self.compressor.encode(req.finish(id));
+        self.stream.get_mut().write_all(&buf)?;
 
         // read the response
         loop {
@@ -47,11 +64,68 @@ impl<S: StreamExt> StreamClient<S> {
             let rsp_frame = Frame::decode_from(&mut self.stream)
                 .map_err(|e| Error::ClientDeserialize(e.to_string()))?;
 
-            // discard the rsp that is is not belong to us
+            // discard the rsp that does not belong to us
             if rsp_frame.id == id {
                 info!("get response id = {}", id);
+                self.compressor.observe(&rsp_frame);
                 return Ok(rsp_frame);
             }
         }
     }
+
+    /// send the requests in as few writes as possible and collect the responses
+    /// the responses are in the order of the requests, even if the server
+    /// answers them out of order
+    pub fn call_batch(&mut self, reqs: Vec<ReqBuf>) -> Result<Vec<Frame>, Error> {
+        let first = self.id;
+        self.id += reqs.len() as u64;
+        info!("request ids = {}..{}", first, self.id);
+
+        // encode the requests
+        let bufs: Vec<_> = reqs
+            .into_iter()
+            .zip(first..)
+            .map(|(req, id)| self.compressor.encode(req.finish(id)))
+            .collect();
+
+        let mut rsps = Vec::with_capacity(bufs.len());
+        let mut start = 0;
+        while start < bufs.len() {
+            // at least one request in each window
+            let mut end = start + 1;
+            let mut size = bufs[start].len();
+            while end < bufs.len() && size + bufs[end].len() <= MAX_BATCH_BYTES {
+                size += bufs[end].len();
+                end += 1;
+            }
+            VecBufs::new(&bufs[start..end]).write_all(self.stream.get_mut())?;
+            rsps.extend(self.read_batch(first + start as u64, end - start)?);
+            start = end;
+        }
+        Ok(rsps)
+    }
+
+    // read the responses of the requests with the ids in first..first+n
+    fn read_batch(&mut self, first: u64, n: usize) -> Result<Vec<Frame>, Error> {
+        let mut rsps: Vec<Option<Frame>> = Vec::new();
+        rsps.resize_with(n, || None);
+        let mut left = n;
+        while left > 0 {
+            // deserialize the rsp
+            let rsp_frame = Frame::decode_from(&mut self.stream)
+                .map_err(|e| Error::ClientDeserialize(e.to_string()))?;
+
+            // discard the rsp that does not belong to us
+            let i = rsp_frame.id.wrapping_sub(first) as usize;
+            match rsps.get_mut(i) {
+                Some(rsp @ None) => {
+                    self.compressor.observe(&rsp_frame);
+                    *rsp = Some(rsp_frame);
+                    left -= 1;
+                }
+                _ => continue,
+            }
+        }
+        Ok(rsps.into_iter().flatten().collect())
+    }
 }
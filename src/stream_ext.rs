@@ -1,16 +1,78 @@
 use std::io::{self, Read, Write};
+use std::net::Shutdown;
 use std::time::Duration;
 
+/// stream that can carry the conetty frames
+///
+/// the multiplexed clients and the servers read and write the stream
+/// concurrently, so the stream must be split into the read and write halves.
+/// wrappers like tls or compression can split the inner stream first
+/// and then wrap each half
 pub trait StreamExt: Sized + Read + Write + Send + 'static {
-    fn try_clone(&self) -> io::Result<Self>;
+    /// the read half of the stream
+    type Reader: Read + Send + 'static;
+    /// the write half of the stream
+    type Writer: Write + Send + 'static;
+
+    /// split the stream into the read half and the write half
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)>;
     fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()>;
 }
 
+/// the read half of a socket that is split by cloning the handle
+#[derive(Debug)]
+pub struct SocketReader<S>(S);
+
+impl<S: Read> Read for SocketReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+/// the write half of a socket that is split by cloning the handle
+/// the write side is shut down when it's dropped, so the peer reads EOF
+/// even if the read half is still alive
+#[derive(Debug)]
+pub struct SocketWriter<S> {
+    sock: S,
+    shutdown: fn(&S) -> io::Result<()>,
+}
+
+impl<S> Drop for SocketWriter<S> {
+    fn drop(&mut self) {
+        // the socket may be closed by the peer already
+        (self.shutdown)(&self.sock).ok();
+    }
+}
+
+impl<S: Write> Write for SocketWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sock.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        self.sock.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sock.flush()
+    }
+}
+
+// split the stream by cloning the underlying handle
 macro_rules! impl_stream_ext {
     ($name: ty) => {
         impl StreamExt for $name {
-            fn try_clone(&self) -> io::Result<Self> {
-                (*self).try_clone()
+            type Reader = SocketReader<$name>;
+            type Writer = SocketWriter<$name>;
+
+            fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+                let reader = SocketReader(self.try_clone()?);
+                let writer = SocketWriter {
+                    sock: self,
+                    shutdown: |s: &$name| s.shutdown(Shutdown::Write),
+                };
+                Ok((reader, writer))
             }
             fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
                 (*self).set_read_timeout(Some(timeout))
@@ -0,0 +1,399 @@
+use std::convert::TryFrom;
+use std::io::{self, Cursor, ErrorKind, Read, Write};
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::stream_ext::StreamExt;
+
+use may::net::TcpStream;
+use may::sync::Mutex;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{
+    Certificate, ClientConfig, ClientConnection, Connection, PrivateKey, RootCertStore,
+    ServerConfig, ServerConnection, ServerName,
+};
+
+// the buffer size for the tls records that read from the stream
+const TLS_BUF_SIZE: usize = 16 * 1024 + 512;
+
+fn tls_err<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(ErrorKind::InvalidData, e)
+}
+
+fn load_certs(pem: &[u8]) -> io::Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut Cursor::new(pem))?;
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "no certificate found",
+        ));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(pem: &[u8]) -> io::Result<PrivateKey> {
+    for item in rustls_pemfile::read_all(&mut Cursor::new(pem))? {
+        match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    Err(io::Error::new(
+        ErrorKind::InvalidInput,
+        "no private key found",
+    ))
+}
+
+fn load_roots(pem: &[u8]) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(pem)? {
+        roots.add(&cert).map_err(tls_err)?;
+    }
+    Ok(roots)
+}
+
+/// the verified certificate chain of the tls peer
+#[derive(Debug, Clone)]
+pub struct PeerIdentity {
+    certs: Vec<Vec<u8>>,
+}
+
+impl PeerIdentity {
+    /// the der encoded certificate chain, the end entity comes first
+    pub fn certs(&self) -> &[Vec<u8>] {
+        &self.certs
+    }
+
+    /// the der encoded end entity certificate
+    pub fn end_entity(&self) -> &[u8] {
+        &self.certs[0]
+    }
+}
+
+/// the server side tls config
+#[derive(Clone)]
+pub struct TlsAcceptor(Arc<ServerConfig>);
+
+impl TlsAcceptor {
+    /// create the acceptor from the pem encoded certificate chain and private key
+    pub fn new(cert_pem: &[u8], key_pem: &[u8]) -> io::Result<Self> {
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(load_certs(cert_pem)?, load_key(key_pem)?)
+            .map_err(tls_err)?;
+        Ok(TlsAcceptor(Arc::new(config)))
+    }
+
+    /// the same as `new`, but the clients must present a certificate signed by the ca
+    /// the verified client identity is passed to `TlsServer::tls_service`
+    pub fn with_client_auth(cert_pem: &[u8], key_pem: &[u8], ca_pem: &[u8]) -> io::Result<Self> {
+        let verifier = AllowAnyAuthenticatedClient::new(load_roots(ca_pem)?).boxed();
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(load_certs(cert_pem)?, load_key(key_pem)?)
+            .map_err(tls_err)?;
+        Ok(TlsAcceptor(Arc::new(config)))
+    }
+
+    /// create the acceptor from a custom rustls config
+    pub fn from_config(config: Arc<ServerConfig>) -> Self {
+        TlsAcceptor(config)
+    }
+
+    /// perform the server side handshake on the stream
+    pub fn accept<S: StreamExt>(&self, stream: S) -> io::Result<TlsStream<S>> {
+        let conn = ServerConnection::new(self.0.clone()).map_err(tls_err)?;
+        TlsStream::handshake(conn.into(), stream)
+    }
+}
+
+impl std::fmt::Debug for TlsAcceptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("TlsAcceptor")
+    }
+}
+
+/// the client side tls config
+#[derive(Clone)]
+pub struct TlsConnector(Arc<ClientConfig>);
+
+impl TlsConnector {
+    /// create the connector that trust the pem encoded ca bundle
+    pub fn new(ca_pem: &[u8]) -> io::Result<Self> {
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(load_roots(ca_pem)?)
+            .with_no_client_auth();
+        Ok(TlsConnector(Arc::new(config)))
+    }
+
+    /// the same as `new`, and present the client certificate to the server
+    pub fn with_client_cert(ca_pem: &[u8], cert_pem: &[u8], key_pem: &[u8]) -> io::Result<Self> {
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(load_roots(ca_pem)?)
+            .with_client_auth_cert(load_certs(cert_pem)?, load_key(key_pem)?)
+            .map_err(tls_err)?;
+        Ok(TlsConnector(Arc::new(config)))
+    }
+
+    /// create the connector from a custom rustls config
+    pub fn from_config(config: Arc<ClientConfig>) -> Self {
+        TlsConnector(config)
+    }
+
+    /// connect to the tcp server address
+    /// the server certificate is verified against the server name, which is also sent as SNI
+    pub fn connect<L: ToSocketAddrs>(
+        &self,
+        addr: L,
+        server_name: &str,
+    ) -> io::Result<TlsStream<TcpStream>> {
+        let stream = TcpStream::connect(addr)?;
+        self.connect_stream(stream, server_name)
+    }
+
+    /// perform the client side handshake on the stream
+    pub fn connect_stream<S: StreamExt>(
+        &self,
+        stream: S,
+        server_name: &str,
+    ) -> io::Result<TlsStream<S>> {
+        let name = ServerName::try_from(server_name)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        let conn = ClientConnection::new(self.0.clone(), name).map_err(tls_err)?;
+        TlsStream::handshake(conn.into(), stream)
+    }
+}
+
+impl std::fmt::Debug for TlsConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("TlsConnector")
+    }
+}
+
+// the tls records that read from the stream but not processed yet
+struct RecordBuf {
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+}
+
+// read the plain data, the tls connection is shared by the read and write halves
+// so the lock must not be held when blocking on the stream
+fn read_plain<R: Read>(
+    conn: &Mutex<Connection>,
+    io: &mut R,
+    rb: &mut RecordBuf,
+    buf: &mut [u8],
+) -> io::Result<usize> {
+    loop {
+        {
+            let mut conn = conn.lock().unwrap();
+            let mut err = None;
+            while rb.pos < rb.len {
+                match conn.read_tls(&mut &rb.buf[rb.pos..rb.len]) {
+                    Ok(n) => rb.pos += n,
+                    // the plain data buffer is full
+                    Err(e) => {
+                        err = Some(e);
+                        break;
+                    }
+                }
+                conn.process_new_packets().map_err(tls_err)?;
+            }
+
+            match conn.reader().read(buf) {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    if let Some(e) = err {
+                        return Err(e);
+                    }
+                }
+                ret => return ret,
+            }
+        }
+
+        // wait for more tls records
+        let n = io.read(&mut rb.buf)?;
+        rb.pos = 0;
+        rb.len = n;
+        if n == 0 {
+            // let the connection know the eof
+            let mut conn = conn.lock().unwrap();
+            conn.read_tls(&mut &[][..])?;
+        }
+    }
+}
+
+// take out the pending tls records, so they can be written without the lock
+fn take_tls(conn: &mut Connection) -> io::Result<Vec<u8>> {
+    let mut records = Vec::new();
+    while conn.wants_write() {
+        conn.write_tls(&mut records)?;
+    }
+    Ok(records)
+}
+
+// write the plain data and flush all the pending tls records
+// the lock is released before blocking on the stream, or the reader could
+// not process the incoming records while the peer waits for us to read
+fn write_plain<W: Write>(conn: &Mutex<Connection>, io: &mut W, buf: &[u8]) -> io::Result<usize> {
+    let (n, records) = {
+        let mut conn = conn.lock().unwrap();
+        let n = conn.writer().write(buf)?;
+        (n, take_tls(&mut conn)?)
+    };
+    io.write_all(&records)?;
+    Ok(n)
+}
+
+/// tls stream over the underlying stream
+/// the handshake is done when the stream is created
+pub struct TlsStream<S: StreamExt = TcpStream> {
+    conn: Arc<Mutex<Connection>>,
+    stream: S,
+    rb: RecordBuf,
+}
+
+impl<S: StreamExt> TlsStream<S> {
+    fn handshake(mut conn: Connection, mut stream: S) -> io::Result<Self> {
+        while conn.is_handshaking() {
+            conn.complete_io(&mut stream)?;
+        }
+        Ok(TlsStream {
+            conn: Arc::new(Mutex::new(conn)),
+            stream,
+            rb: RecordBuf {
+                buf: vec![0; TLS_BUF_SIZE],
+                pos: 0,
+                len: 0,
+            },
+        })
+    }
+
+    /// the verified identity of the peer
+    /// return None if the peer doesn't present a certificate
+    pub fn peer_identity(&self) -> Option<PeerIdentity> {
+        let conn = self.conn.lock().unwrap();
+        let certs = conn.peer_certificates()?;
+        if certs.is_empty() {
+            return None;
+        }
+        Some(PeerIdentity {
+            certs: certs.iter().map(|c| c.0.clone()).collect(),
+        })
+    }
+
+    /// get the underlying stream
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<S: StreamExt> std::fmt::Debug for TlsStream<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("TlsStream")
+    }
+}
+
+impl<S: StreamExt> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_plain(&self.conn, &mut self.stream, &mut self.rb, buf)
+    }
+}
+
+impl<S: StreamExt> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_plain(&self.conn, &mut self.stream, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: StreamExt> StreamExt for TlsStream<S> {
+    type Reader = TlsReader<S::Reader>;
+    type Writer = TlsWriter<S::Writer>;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        let (r, w) = self.stream.split()?;
+        let reader = TlsReader {
+            conn: self.conn.clone(),
+            io: r,
+            rb: self.rb,
+        };
+        let writer = TlsWriter {
+            conn: self.conn,
+            io: w,
+        };
+        Ok((reader, writer))
+    }
+
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
+
+/// the read half of the tls stream
+pub struct TlsReader<R> {
+    conn: Arc<Mutex<Connection>>,
+    io: R,
+    rb: RecordBuf,
+}
+
+impl<R> std::fmt::Debug for TlsReader<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("TlsReader")
+    }
+}
+
+impl<R: Read> Read for TlsReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_plain(&self.conn, &mut self.io, &mut self.rb, buf)
+    }
+}
+
+/// the write half of the tls stream
+/// the close notify is sent when dropped
+pub struct TlsWriter<W: Write> {
+    conn: Arc<Mutex<Connection>>,
+    io: W,
+}
+
+impl<W: Write> Drop for TlsWriter<W> {
+    fn drop(&mut self) {
+        let records = {
+            let mut conn = self.conn.lock().unwrap();
+            conn.send_close_notify();
+            take_tls(&mut conn)
+        };
+        if let Ok(records) = records {
+            self.io.write_all(&records).ok();
+        }
+    }
+}
+
+impl<W: Write> std::fmt::Debug for TlsWriter<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("TlsWriter")
+    }
+}
+
+impl<W: Write> Write for TlsWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_plain(&self.conn, &mut self.io, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
@@ -0,0 +1,300 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::udp_frag;
+
+use arrayvec::ArrayVec;
+use crossbeam::queue::SegQueue;
+use may::net::UdpSocket;
+use may::sync::Mutex;
+
+// max datagrams that would be received/sent by one syscall
+const MAX_BATCH: usize = 16;
+
+/// buffers that receive a batch of datagrams at once
+pub(crate) struct RecvBatch {
+    bufs: Vec<Vec<u8>>,
+    lens: Vec<usize>,
+    addrs: Vec<SocketAddr>,
+}
+
+impl RecvBatch {
+    pub fn new(buf_size: usize) -> Self {
+        let any = SocketAddr::from(([0, 0, 0, 0], 0));
+        RecvBatch {
+            bufs: vec![vec![0; buf_size]; MAX_BATCH],
+            lens: vec![0; MAX_BATCH],
+            addrs: vec![any; MAX_BATCH],
+        }
+    }
+
+    /// get the received datagram and the peer address
+    pub fn packet(&self, i: usize) -> (&[u8], SocketAddr) {
+        (&self.bufs[i][..self.lens[i]], self.addrs[i])
+    }
+
+    /// receive at least one datagram, return the number of the valid datagrams
+    /// the truncated datagrams and the ones from unknown addresses are dropped
+    #[cfg(target_os = "linux")]
+    pub fn recv(&mut self, sock: &UdpSocket) -> io::Result<usize> {
+        use may::io::WaitIo;
+        use std::os::unix::io::AsRawFd;
+
+        let mut names: [libc::sockaddr_storage; MAX_BATCH] = unsafe { std::mem::zeroed() };
+        let mut iovs: ArrayVec<libc::iovec, MAX_BATCH> = self
+            .bufs
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            })
+            .collect();
+        let mut msgs: [libc::mmsghdr; MAX_BATCH] = unsafe { std::mem::zeroed() };
+        for (i, msg) in msgs.iter_mut().enumerate() {
+            msg.msg_hdr.msg_name = &mut names[i] as *mut _ as *mut libc::c_void;
+            msg.msg_hdr.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as _;
+            msg.msg_hdr.msg_iov = &mut iovs[i];
+            msg.msg_hdr.msg_iovlen = 1;
+        }
+
+        let n = loop {
+            sock.reset_io();
+            let ret = unsafe {
+                libc::recvmmsg(
+                    sock.as_raw_fd(),
+                    msgs.as_mut_ptr(),
+                    MAX_BATCH as _,
+                    libc::MSG_DONTWAIT as _,
+                    std::ptr::null_mut(),
+                )
+            };
+            if ret >= 0 {
+                break ret as usize;
+            }
+            let err = io::Error::last_os_error();
+            match err.kind() {
+                io::ErrorKind::WouldBlock => sock.wait_io(),
+                io::ErrorKind::Interrupted => {}
+                _ => return Err(err),
+            }
+        };
+
+        // move the valid datagrams to the front
+        let mut valid = 0;
+        for i in 0..n {
+            if msgs[i].msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+                warn!("drop truncated datagram, len={}", msgs[i].msg_len);
+                continue;
+            }
+            let addr = match sys::from_sockaddr(&names[i]) {
+                Ok(addr) => addr,
+                Err(err) => {
+                    warn!("drop datagram, err={:?}", err);
+                    continue;
+                }
+            };
+            self.bufs.swap(valid, i);
+            self.lens[valid] = msgs[i].msg_len as usize;
+            self.addrs[valid] = addr;
+            valid += 1;
+        }
+        Ok(valid)
+    }
+
+    /// receive at least one datagram, return the number of datagrams
+    #[cfg(not(target_os = "linux"))]
+    pub fn recv(&mut self, sock: &UdpSocket) -> io::Result<usize> {
+        let (len, addr) = sock.recv_from(&mut self.bufs[0])?;
+        self.lens[0] = len;
+        self.addrs[0] = addr;
+        Ok(1)
+    }
+}
+
+/// queued datagram sender, it's safe to call `send` concurrently
+/// only one of the callers would perform the batched write
+pub(crate) struct QueuedSender {
+    mtu: usize,
+    data_count: AtomicUsize,
+    data_queue: SegQueue<(Vec<u8>, SocketAddr)>,
+    sock: Mutex<UdpSocket>,
+}
+
+impl QueuedSender {
+    pub fn new(sock: UdpSocket, mtu: usize) -> Self {
+        QueuedSender {
+            mtu,
+            data_count: AtomicUsize::new(0),
+            data_queue: SegQueue::new(),
+            sock: Mutex::new(sock),
+        }
+    }
+
+    /// send the frame to the peer, would split it into fragments if necessary
+    pub fn send(&self, data: Vec<u8>, addr: SocketAddr) {
+        let frags = match udp_frag::split(data, self.mtu) {
+            Ok(frags) => frags,
+            Err(err) => {
+                error!("udp split frame failed, err={:?}", err);
+                return;
+            }
+        };
+        let cnt = frags.len();
+        for frag in frags {
+            self.data_queue.push((frag, addr));
+        }
+
+        // only allow the first sender perform the send operation
+        // other concurrent senders would just push the data
+        if self.data_count.fetch_add(cnt, Ordering::AcqRel) == 0 {
+            // the lock is taken only once for all the queued datagrams
+            let sock = self.sock.lock().unwrap();
+            loop {
+                let mut packets = ArrayVec::<_, MAX_BATCH>::new();
+                while let Some(packet) = self.data_queue.pop() {
+                    packets.push(packet);
+                    if packets.is_full() {
+                        break;
+                    }
+                }
+
+                let cnt = packets.len();
+                send_batch(&sock, &packets);
+
+                // detect if there are more packet need to deal with
+                if self.data_count.fetch_sub(cnt, Ordering::AcqRel) == cnt {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_batch(sock: &UdpSocket, packets: &[(Vec<u8>, SocketAddr)]) {
+    use std::os::unix::io::AsRawFd;
+
+    let mut names: ArrayVec<_, MAX_BATCH> = packets
+        .iter()
+        .map(|(_, addr)| sys::to_sockaddr(addr))
+        .collect();
+    let mut iovs: ArrayVec<libc::iovec, MAX_BATCH> = packets
+        .iter()
+        .map(|(data, _)| libc::iovec {
+            iov_base: data.as_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        })
+        .collect();
+    let mut msgs: [libc::mmsghdr; MAX_BATCH] = unsafe { std::mem::zeroed() };
+    for (i, msg) in msgs.iter_mut().take(packets.len()).enumerate() {
+        msg.msg_hdr.msg_name = &mut names[i].0 as *mut _ as *mut libc::c_void;
+        msg.msg_hdr.msg_namelen = names[i].1;
+        msg.msg_hdr.msg_iov = &mut iovs[i];
+        msg.msg_hdr.msg_iovlen = 1;
+    }
+
+    let mut sent = 0;
+    while sent < packets.len() {
+        let ret = unsafe {
+            libc::sendmmsg(
+                sock.as_raw_fd(),
+                msgs[sent..].as_mut_ptr(),
+                (packets.len() - sent) as _,
+                libc::MSG_DONTWAIT as _,
+            )
+        };
+        if ret > 0 {
+            sent += ret as usize;
+            continue;
+        }
+
+        let err = io::Error::last_os_error();
+        match err.kind() {
+            io::ErrorKind::Interrupted => {}
+            // the socket buffer is full, send the left ones one by one
+            io::ErrorKind::WouldBlock => {
+                for (data, addr) in &packets[sent..] {
+                    if let Err(err) = sock.send_to(data, addr) {
+                        error!("udp send_to failed, err={:?}", err);
+                    }
+                }
+                return;
+            }
+            // skip the bad one
+            _ => {
+                error!("udp sendmmsg failed, err={:?}", err);
+                sent += 1;
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_batch(sock: &UdpSocket, packets: &[(Vec<u8>, SocketAddr)]) {
+    for (data, addr) in packets {
+        if let Err(err) = sock.send_to(data, addr) {
+            error!("udp send_to failed, err={:?}", err);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+    pub fn to_sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let len = match addr {
+            SocketAddr::V4(a) => {
+                let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = a.port().to_be();
+                sin.sin_addr = libc::in_addr {
+                    s_addr: u32::from_ne_bytes(a.ip().octets()),
+                };
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(a) => {
+                let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = a.port().to_be();
+                sin6.sin6_flowinfo = a.flowinfo();
+                sin6.sin6_addr = libc::in6_addr {
+                    s6_addr: a.ip().octets(),
+                };
+                sin6.sin6_scope_id = a.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+        (storage, len as libc::socklen_t)
+    }
+
+    pub fn from_sockaddr(storage: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let sin = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(sin.sin_addr.s_addr.to_ne_bytes());
+                let port = u16::from_be(sin.sin_port);
+                Ok(SocketAddr::V4(SocketAddrV4::new(ip, port)))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+                let port = u16::from_be(sin6.sin6_port);
+                Ok(SocketAddr::V6(SocketAddrV6::new(
+                    ip,
+                    port,
+                    sin6.sin6_flowinfo,
+                    sin6.sin6_scope_id,
+                )))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid socket address family",
+            )),
+        }
+    }
+}
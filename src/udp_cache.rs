@@ -0,0 +1,153 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+type Key = (SocketAddr, u64);
+
+enum Entry {
+    // the request is still being processed
+    Pending,
+    // the encoded response frame
+    Done(Vec<u8>),
+}
+
+/// the result of looking up a request in the cache
+pub(crate) enum Lookup<'a> {
+    /// a new request, the caller should process it
+    Miss,
+    /// a duplicated request that is still being processed
+    Pending,
+    /// a duplicated request that is already processed
+    Hit(&'a [u8]),
+}
+
+/// cache the udp responses by (peer, id)
+/// so that the retransmitted requests would not run the service again
+pub(crate) struct RspCache {
+    capacity: usize,
+    // the max and current bytes of the cached responses
+    max_bytes: usize,
+    bytes: usize,
+    ttl: Duration,
+    map: HashMap<Key, (Instant, Entry)>,
+    // the insertion order, used for eviction
+    order: VecDeque<(Key, Instant)>,
+}
+
+impl RspCache {
+    pub fn new(capacity: usize, max_bytes: usize, ttl: Duration) -> Self {
+        RspCache {
+            capacity,
+            max_bytes,
+            bytes: 0,
+            ttl,
+            map: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    // remove the entry and count the freed bytes
+    fn remove(&mut self, key: &Key) {
+        if let Some((_, Entry::Done(data))) = self.map.remove(key) {
+            self.bytes -= data.len();
+        }
+    }
+
+    // evict the expired entries and keep the cache within the capacity,
+    // with the room for `room` more entries
+    fn evict(&mut self, room: usize) {
+        let now = Instant::now();
+        while let Some(&(key, t)) = self.order.front() {
+            if now.duration_since(t) < self.ttl
+                && self.map.len() + room <= self.capacity
+                && self.bytes <= self.max_bytes
+            {
+                break;
+            }
+            self.order.pop_front();
+            // the key may be re-inserted later, only remove the same one
+            if matches!(self.map.get(&key), Some((inserted, _)) if *inserted == t) {
+                self.remove(&key);
+            }
+        }
+    }
+
+    /// look up the request, a pending entry is inserted if not found
+    pub fn lookup(&mut self, peer: SocketAddr, id: u64) -> Lookup<'_> {
+        let key = (peer, id);
+        let now = Instant::now();
+        let fresh = match self.map.get(&key) {
+            Some((t, _)) => now.duration_since(*t) < self.ttl,
+            None => false,
+        };
+
+        if !fresh {
+            self.remove(&key);
+            self.evict(1);
+            self.map.insert(key, (now, Entry::Pending));
+            self.order.push_back((key, now));
+            return Lookup::Miss;
+        }
+
+        match self.map.get(&key) {
+            Some((_, Entry::Done(data))) => Lookup::Hit(data),
+            _ => Lookup::Pending,
+        }
+    }
+
+    /// get the cached response without starting the request
+    pub fn cached(&self, peer: SocketAddr, id: u64) -> Option<&[u8]> {
+        match self.map.get(&(peer, id)) {
+            Some((t, Entry::Done(data))) if t.elapsed() < self.ttl => Some(data),
+            _ => None,
+        }
+    }
+
+    /// save the response for the request
+    pub fn complete(&mut self, peer: SocketAddr, id: u64, data: &[u8]) {
+        let key = (peer, id);
+        // the response that is too big is not cached, the request would run again
+        if data.len() > self.max_bytes {
+            self.map.remove(&key);
+            return;
+        }
+        // the entry may be already evicted
+        if let Some((_, entry)) = self.map.get_mut(&key) {
+            if let Entry::Done(ref old) = *entry {
+                self.bytes -= old.len();
+            }
+            *entry = Entry::Done(data.to_vec());
+            self.bytes += data.len();
+            self.evict(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(cache: &mut RspCache, peer: SocketAddr, ids: std::ops::Range<u64>) {
+        for id in ids {
+            assert!(matches!(cache.lookup(peer, id), Lookup::Miss));
+            cache.complete(peer, id, b"rsp");
+        }
+    }
+
+    #[test]
+    fn keep_capacity_entries() {
+        let peer = "127.0.0.1:2000".parse().unwrap();
+        let mut cache = RspCache::new(3, 1024, Duration::from_secs(10));
+        fill(&mut cache, peer, 0..5);
+        // the oldest ones are evicted
+        assert!(cache.cached(peer, 1).is_none());
+        for id in 2..5 {
+            assert_eq!(cache.cached(peer, id), Some(&b"rsp"[..]));
+        }
+
+        let mut cache = RspCache::new(1, 1024, Duration::from_secs(10));
+        fill(&mut cache, peer, 0..2);
+        assert!(cache.cached(peer, 0).is_none());
+        assert_eq!(cache.cached(peer, 1), Some(&b"rsp"[..]));
+    }
+}
@@ -1,13 +1,28 @@
+use std::borrow::Cow;
 use std::io::{self, Cursor};
 use std::net::ToSocketAddrs;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use crate::compress::{Compression, Compressor};
 use crate::errors::Error;
-use crate::frame::{Frame, ReqBuf};
+use crate::frame::{self, Frame, ReqBuf};
+use crate::retry::RetryPolicy;
+use crate::udp_frag::{self, Reassembler, UdpConfig};
+use crate::udp_seal::Sealer;
 
+use byteorder::{BigEndian, ReadBytesExt};
 use may::net::UdpSocket;
 
-#[derive(Debug)]
+// the socket read timeout
+fn is_timeout(err: &Error) -> bool {
+    match err {
+        Error::Io(e) => {
+            e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock
+        }
+        _ => false,
+    }
+}
+
 pub struct UdpClient {
     // each request would have a unique id
     id: u64,
@@ -15,11 +30,44 @@ pub struct UdpClient {
     sock: UdpSocket,
     // send/recv buf
     buf: Vec<u8>,
+    // frames bigger than mtu would be fragmented
+    mtu: usize,
+    // collect the fragmented responses
+    reassembler: Reassembler<u64>,
+    // seal the frames if configured
+    sealer: Sealer,
+    // compress the requests if negotiated
+    compressor: Compressor,
+    // the total time to wait for a response
+    timeout: Duration,
+    // the first retransmission interval, doubled after each resend
+    retransmit: Option<Duration>,
+}
+
+impl std::fmt::Debug for UdpClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UdpClient")
+            .field("id", &self.id)
+            .field("sock", &self.sock)
+            .field("mtu", &self.mtu)
+            .field("timeout", &self.timeout)
+            .field("retransmit", &self.retransmit)
+            .finish()
+    }
 }
 
 impl UdpClient {
     /// connect to the server address
     pub fn connect<L: ToSocketAddrs>(addr: L) -> io::Result<UdpClient> {
+        UdpClient::connect_with_config(addr, UdpConfig::default())
+    }
+
+    /// connect to the server address with the given udp config
+    pub fn connect_with_config<L: ToSocketAddrs>(
+        addr: L,
+        config: UdpConfig,
+    ) -> io::Result<UdpClient> {
+        config.check()?;
         // this would bind a random port by the system
         let sock = UdpSocket::bind("0.0.0.0:0")?;
         sock.connect(addr)?;
@@ -28,15 +76,38 @@ impl UdpClient {
         Ok(UdpClient {
             sock,
             id: 0,
-            buf: vec![0; 1024],
+            buf: vec![0; config.recv_buf_size],
+            mtu: config.mtu,
+            reassembler: Reassembler::new(&config),
+            sealer: Sealer::new(&config),
+            compressor: Compressor::default(),
+            timeout: Duration::from_secs(1),
+            retransmit: None,
         })
     }
 
     /// set the default timeout value
     /// the initial timeout is 1 seconds
     pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
         self.sock.set_read_timeout(Some(timeout)).unwrap();
     }
+
+    /// enable the request retransmission
+    /// the request is resent with the same id if no response within the interval
+    /// the interval is doubled after each resend until the timeout is reached
+    /// enable the response cache of the server, or the resent requests run again
+    /// the interval is at least 1ms
+    pub fn set_retransmit(&mut self, interval: Duration) {
+        // the zero read timeout is rejected by the socket
+        self.retransmit = Some(interval.max(Duration::from_millis(1)));
+    }
+
+    /// set the compression settings, the compression is disabled by default
+    /// the requests are compressed once the server accepts one of the codecs
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compressor.set_config(compression);
+    }
 }
 
 impl UdpClient {
@@ -44,24 +115,107 @@ impl UdpClient {
     /// the request must be encoded into the ReqBuf
     /// the response is the raw frame, you should parsing it into final response
     pub fn call_service(&mut self, req: ReqBuf) -> Result<Frame, Error> {
+        let (id, frags) = self.encode(req)?;
+        self.send_recv(id, &frags)
+    }
+
+    /// call the server, and retry on the retryable errors by the policy
+    /// the retried requests keep the frame id, so the server with the
+    /// response cache enabled doesn't run the service again
+    pub fn call_idempotent(&mut self, req: ReqBuf, policy: &RetryPolicy) -> Result<Frame, Error> {
+        let (id, frags) = self.encode(req)?;
+        policy.retry(|| self.send_recv(id, &frags))
+    }
+
+    // encode the request into the fragments with a new id
+    fn encode(&mut self, req: ReqBuf) -> Result<(u64, Vec<Vec<u8>>), Error> {
         let id = self.id;
         self.id += 1;
         info!("request id = {}", id);
 
+        let frame = self.compressor.encode(req.finish(id));
+        let frame = self.sealer.seal(frame, None)?;
+        Ok((id, udp_frag::split(frame, self.mtu)?))
+    }
+
+    // send the request and wait for the response
+    fn send_recv(&mut self, id: u64, frags: &[Vec<u8>]) -> Result<Frame, Error> {
         // send the data to server
-        self.sock.send(&(req.finish(id))).map_err(Error::from)?;
+        self.send_frags(frags)?;
+
+        let mut interval = match self.retransmit {
+            Some(interval) => interval,
+            None => return self.recv_rsp(id),
+        };
 
-        // read the response
+        // the same error as the socket read timeout without retransmission
+        let deadline = Instant::now() + self.timeout;
         loop {
-            self.sock.recv(&mut self.buf).map_err(Error::from)?;
+            let now = Instant::now();
+            if now >= deadline {
+                self.sock.set_read_timeout(Some(self.timeout))?;
+                return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+            }
+            self.sock
+                .set_read_timeout(Some(interval.min(deadline - now)))?;
+            match self.recv_rsp(id) {
+                Err(ref e) if is_timeout(e) => {
+                    info!("retransmit request id = {}", id);
+                    self.send_frags(frags)?;
+                    interval *= 2;
+                }
+                ret => {
+                    self.sock.set_read_timeout(Some(self.timeout))?;
+                    return ret;
+                }
+            }
+        }
+    }
+
+    fn send_frags(&self, frags: &[Vec<u8>]) -> Result<(), Error> {
+        for data in frags {
+            self.sock.send(data)?;
+        }
+        Ok(())
+    }
+
+    // read the response with the given id
+    fn recv_rsp(&mut self, id: u64) -> Result<Frame, Error> {
+        loop {
+            let len = self.sock.recv(&mut self.buf).map_err(Error::from)?;
+            let buf = &self.buf[..len];
 
             // deserialize the rsp
-            let rsp_frame = Frame::decode_from(&mut Cursor::new(&self.buf))
+            let data = if udp_frag::is_fragment(buf) {
+                // the old servers echo the id with the compression offer
+                let rsp_id = frame::strip_offer((&buf[0..8]).read_u64::<BigEndian>()?);
+                // discard the fragments that is not belong to us
+                if rsp_id != id {
+                    continue;
+                }
+                // the malformed fragment is discarded, the response may still come
+                match t!(self.reassembler.push(rsp_id, buf)) {
+                    Some(data) => Cow::Owned(data),
+                    None => continue,
+                }
+            } else {
+                Cow::Borrowed(buf)
+            };
+            let opened = self
+                .sealer
+                .open(data)
+                .map_err(|e| Error::ClientDeserialize(e.to_string()))?;
+            // the duplicated response, e.g. of the retransmitted request
+            if !opened.fresh {
+                continue;
+            }
+            let rsp_frame = Frame::decode_from(&mut Cursor::new(&*opened.data))
                 .map_err(|e| Error::ClientDeserialize(e.to_string()))?;
 
             // discard the rsp that is is not belong to us
             if rsp_frame.id == id {
                 info!("get response id = {}", id);
+                self.compressor.observe(&rsp_frame);
                 return Ok(rsp_frame);
             }
         }
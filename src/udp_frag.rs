@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{self, Cursor, ErrorKind};
+use std::time::{Duration, Instant};
+
+use crate::frame::FRAME_MAX_LEN;
+#[cfg(feature = "noise")]
+use crate::noise::NoiseConfig;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+// Fragment layout
+// id(u64) + mark(u64) + total_len(u32) + index(u16) + count(u16) + chunk([u8])
+//
+// a normal frame never has a len field equal to `FRAG_MARK`, so the
+// receiver can tell the fragments apart from whole frames
+
+const FRAG_MARK: u64 = u64::MAX;
+const FRAG_HEAD_LEN: usize = 24;
+// the frame head and the sealing overhead are far less than 64k
+const MAX_TOTAL_LEN: usize = FRAME_MAX_LEN as usize + 64 * 1024;
+
+/// the max payload size of an udp datagram
+pub const MAX_DATAGRAM_SIZE: usize = 65507;
+
+/// udp transport config
+#[derive(Debug, Clone)]
+pub struct UdpConfig {
+    /// the receive buffer size, can't exceed `MAX_DATAGRAM_SIZE`
+    pub recv_buf_size: usize,
+    /// frames bigger than this would be split into fragments
+    pub mtu: usize,
+    /// partial frames older than this would be dropped
+    pub reassembly_timeout: Duration,
+    /// max bytes that could be held by the partial frames
+    pub max_reassembly_bytes: usize,
+    /// max responses cached by the server for the retransmitted requests
+    /// the cache is disabled by default, set it to enable the cache
+    pub rsp_cache_size: usize,
+    /// max bytes of the cached responses, the oldest are evicted first
+    pub rsp_cache_bytes: usize,
+    /// how long a response is cached
+    pub rsp_cache_ttl: Duration,
+    /// seal each frame by noise, the peers must use the same setting
+    /// the clients must allow the server public key
+    #[cfg(feature = "noise")]
+    pub noise: Option<NoiseConfig>,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        UdpConfig {
+            recv_buf_size: MAX_DATAGRAM_SIZE,
+            // ethernet mtu - ip header - udp header
+            mtu: 1472,
+            reassembly_timeout: Duration::from_secs(5),
+            max_reassembly_bytes: 16 * 1024 * 1024,
+            rsp_cache_size: 0,
+            rsp_cache_bytes: 4 * 1024 * 1024,
+            rsp_cache_ttl: Duration::from_secs(5),
+            #[cfg(feature = "noise")]
+            noise: None,
+        }
+    }
+}
+
+impl UdpConfig {
+    pub(crate) fn check(&self) -> io::Result<()> {
+        if self.recv_buf_size > MAX_DATAGRAM_SIZE || self.recv_buf_size < self.mtu {
+            let s = format!("invalid udp recv_buf_size={}", self.recv_buf_size);
+            return Err(io::Error::new(ErrorKind::InvalidInput, s));
+        }
+        // the biggest frame must fit in the max number of the fragments
+        if self.mtu <= FRAG_HEAD_LEN
+            || self.mtu > MAX_DATAGRAM_SIZE
+            || (self.mtu - FRAG_HEAD_LEN) * (u16::MAX as usize) < MAX_TOTAL_LEN
+        {
+            let s = format!("invalid udp mtu={}", self.mtu);
+            return Err(io::Error::new(ErrorKind::InvalidInput, s));
+        }
+        Ok(())
+    }
+}
+
+/// check if the datagram is a fragment of a bigger frame
+pub(crate) fn is_fragment(buf: &[u8]) -> bool {
+    buf.len() >= FRAG_HEAD_LEN && buf[8..16] == FRAG_MARK.to_be_bytes()
+}
+
+/// split the encoded frame into datagrams that fit in the mtu
+pub(crate) fn split(frame: Vec<u8>, mtu: usize) -> io::Result<Vec<Vec<u8>>> {
+    if frame.len() <= mtu {
+        return Ok(vec![frame]);
+    }
+
+    let id = (&frame[0..8]).read_u64::<BigEndian>().unwrap();
+    let chunk_size = mtu - FRAG_HEAD_LEN;
+    let count = (frame.len() + chunk_size - 1) / chunk_size;
+    if frame.len() > MAX_TOTAL_LEN || count > u16::MAX as usize {
+        let s = format!("udp frame too big to split, len={}", frame.len());
+        return Err(io::Error::new(ErrorKind::InvalidInput, s));
+    }
+
+    let frags = frame
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut buf = Vec::with_capacity(FRAG_HEAD_LEN + chunk.len());
+            buf.write_u64::<BigEndian>(id).unwrap();
+            buf.write_u64::<BigEndian>(FRAG_MARK).unwrap();
+            buf.write_u32::<BigEndian>(frame.len() as u32).unwrap();
+            buf.write_u16::<BigEndian>(index as u16).unwrap();
+            buf.write_u16::<BigEndian>(count as u16).unwrap();
+            buf.extend_from_slice(chunk);
+            buf
+        })
+        .collect();
+    Ok(frags)
+}
+
+struct Partial {
+    start: Instant,
+    count: u16,
+    received: u16,
+    got: Vec<bool>,
+    data: Vec<u8>,
+}
+
+/// collect fragments and rebuild the original frame
+pub(crate) struct Reassembler<K> {
+    timeout: Duration,
+    max_bytes: usize,
+    used: usize,
+    pending: HashMap<K, Partial>,
+    // the time that the oldest partial frame is timeout
+    deadline: Option<Instant>,
+}
+
+impl<K: Hash + Eq + Copy> Reassembler<K> {
+    pub fn new(config: &UdpConfig) -> Self {
+        Reassembler {
+            timeout: config.reassembly_timeout,
+            max_bytes: config.max_reassembly_bytes,
+            used: 0,
+            pending: HashMap::new(),
+            deadline: None,
+        }
+    }
+
+    // drop all the partial frames that are timeout
+    // nothing is scanned before the oldest partial frame is timeout
+    fn expire(&mut self) {
+        let now = Instant::now();
+        match self.deadline {
+            Some(deadline) if now >= deadline => {}
+            _ => return,
+        }
+        let timeout = self.timeout;
+        let mut freed = 0;
+        self.pending.retain(|_, p| {
+            let alive = now.duration_since(p.start) < timeout;
+            if !alive {
+                freed += p.data.len();
+            }
+            alive
+        });
+        self.used -= freed;
+        self.deadline = self.pending.values().map(|p| p.start + timeout).min();
+    }
+
+    /// push a fragment, return the whole frame once all fragments are received
+    /// the key should be unique for each frame, e.g. the peer address and frame id
+    pub fn push(&mut self, key: K, buf: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let mut r = Cursor::new(&buf[16..FRAG_HEAD_LEN]);
+        let total_len = r.read_u32::<BigEndian>()? as usize;
+        let index = r.read_u16::<BigEndian>()?;
+        let count = r.read_u16::<BigEndian>()?;
+        let chunk = &buf[FRAG_HEAD_LEN..];
+        if total_len > MAX_TOTAL_LEN {
+            let s = format!("udp frame too big. len={}", total_len);
+            return Err(io::Error::new(ErrorKind::InvalidData, s));
+        }
+        if index >= count || chunk.len() > total_len {
+            let s = format!("invalid udp fragment. index={} count={}", index, count);
+            return Err(io::Error::new(ErrorKind::InvalidData, s));
+        }
+
+        self.expire();
+        if !self.pending.contains_key(&key) {
+            if self.used + total_len > self.max_bytes {
+                let s = format!("udp reassembly buffer full, drop frame. len={}", total_len);
+                return Err(io::Error::new(ErrorKind::Other, s));
+            }
+            self.used += total_len;
+            let start = Instant::now();
+            // the older partial frames are timeout earlier
+            if self.deadline.is_none() {
+                self.deadline = Some(start + self.timeout);
+            }
+            let partial = Partial {
+                start,
+                count,
+                received: 0,
+                got: vec![false; count as usize],
+                data: vec![0; total_len],
+            };
+            self.pending.insert(key, partial);
+        }
+
+        let partial = self.pending.get_mut(&key).unwrap();
+        // the last chunk may be shorter, so compute the offset from the first ones
+        let offset = if index + 1 == count {
+            total_len - chunk.len()
+        } else {
+            index as usize * chunk.len()
+        };
+        if count != partial.count
+            || total_len != partial.data.len()
+            || offset + chunk.len() > total_len
+        {
+            let s = format!("invalid udp fragment. index={} count={}", index, count);
+            return Err(io::Error::new(ErrorKind::InvalidData, s));
+        }
+
+        if !partial.got[index as usize] {
+            partial.got[index as usize] = true;
+            partial.received += 1;
+            partial.data[offset..offset + chunk.len()].copy_from_slice(chunk);
+        }
+
+        if partial.received < partial.count {
+            return Ok(None);
+        }
+
+        let partial = self.pending.remove(&key).unwrap();
+        self.used -= partial.data.len();
+        Ok(Some(partial.data))
+    }
+}
@@ -0,0 +1,181 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, Cursor};
+use std::net::ToSocketAddrs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::compress::{Compression, Compressor};
+use crate::errors::Error;
+use crate::frame::{Frame, ReqBuf};
+use crate::retry::RetryPolicy;
+use crate::udp_frag::{self, Reassembler, UdpConfig};
+use crate::udp_seal::Sealer;
+use crate::Client;
+
+use byteorder::{BigEndian, ReadBytesExt};
+use may::net::UdpSocket;
+use may::sync::Mutex;
+use may::{coroutine, go};
+use may_waiter::TokenWaiter;
+
+#[derive(Debug)]
+pub struct UdpMultiplexClient {
+    // default timeout is 1s, the lost datagram would block the call forever without it
+    timeout: Option<Duration>,
+    // frames bigger than mtu would be fragmented
+    mtu: usize,
+    // seal the frames if configured
+    sealer: Sealer,
+    // compress the requests if negotiated
+    compressor: Compressor,
+    // the frame id of the next request, never reused by the client
+    // the server caches the responses by the frame id
+    next_id: AtomicU64,
+    // the frame id on the wire to the waiter id of the pending calls
+    ids: Arc<Mutex<HashMap<u64, usize>>>,
+    // the write half need to be protected by mutex
+    // for that coroutine io obj can't shared safely
+    sock: Mutex<UdpSocket>,
+    // the listening coroutine
+    listener: Option<coroutine::JoinHandle<()>>,
+}
+
+impl Drop for UdpMultiplexClient {
+    fn drop(&mut self) {
+        if let Some(h) = self.listener.take() {
+            unsafe { h.coroutine().cancel() };
+            // FIXME: join here when bug fix in thread context in may
+            // h.join().ok();
+        }
+    }
+}
+
+impl UdpMultiplexClient {
+    /// connect to the server address
+    pub fn connect<L: ToSocketAddrs>(addr: L) -> io::Result<Self> {
+        UdpMultiplexClient::connect_with_config(addr, UdpConfig::default())
+    }
+
+    /// connect to the server address with the given udp config
+    pub fn connect_with_config<L: ToSocketAddrs>(addr: L, config: UdpConfig) -> io::Result<Self> {
+        config.check()?;
+        // this would bind a random port by the system
+        let sock = UdpSocket::bind("0.0.0.0:0")?;
+        sock.connect(addr)?;
+        // the read half
+        let r_sock = sock.try_clone()?;
+        let mut buf = vec![0u8; config.recv_buf_size];
+        let mut reassembler = Reassembler::new(&config);
+        let sealer = Sealer::new(&config);
+        let r_sealer = sealer.clone();
+        let ids = Arc::new(Mutex::new(HashMap::new()));
+        let r_ids = ids.clone();
+        let listener = go!(
+            coroutine::Builder::new().name("UdpMultiplexClientListener".to_owned()),
+            move || loop {
+                let len = t!(r_sock.recv(&mut buf));
+                let packet = &buf[..len];
+
+                // if we failed to deserialize the rsp frame, just continue
+                let data = if udp_frag::is_fragment(packet) {
+                    let id = t!((&packet[0..8]).read_u64::<BigEndian>());
+                    match t!(reassembler.push(id, packet)) {
+                        Some(data) => Cow::Owned(data),
+                        None => continue,
+                    }
+                } else {
+                    Cow::Borrowed(packet)
+                };
+                let opened = t!(r_sealer.open(data));
+                // the duplicated response
+                if !opened.fresh {
+                    continue;
+                }
+                let rsp_frame = t!(Frame::decode_from(&mut Cursor::new(&*opened.data)));
+                info!("receive rsp, id={}", rsp_frame.id);
+
+                // set the wait req while locked, so the waiter is not dropped
+                let mut ids = r_ids.lock().unwrap();
+                match ids.remove(&rsp_frame.id) {
+                    Some(id) => {
+                        let id = unsafe { may_waiter::ID::from_usize(id) };
+                        TokenWaiter::set_rsp(id, rsp_frame);
+                    }
+                    None => warn!("unknown rsp id={}", rsp_frame.id),
+                }
+            }
+        )?;
+
+        Ok(UdpMultiplexClient {
+            timeout: Some(Duration::from_secs(1)),
+            mtu: config.mtu,
+            sealer,
+            compressor: Compressor::default(),
+            next_id: AtomicU64::new(0),
+            ids,
+            sock: Mutex::new(sock),
+            listener: Some(listener),
+        })
+    }
+
+    /// set the default timeout value
+    /// the initial timeout is 1 second
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// set the compression settings, the compression is disabled by default
+    /// the requests are compressed once the server accepts one of the codecs
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compressor.set_config(compression);
+    }
+
+    // encode the request with a new id
+    fn encode(&self, req: ReqBuf) -> (u64, Vec<u8>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        info!("request id = {}", id);
+        (id, self.compressor.encode(req.finish(id)))
+    }
+
+    // send the encoded request and wait for the response
+    // the frame is sealed for each attempt, so the sealed copy is never replayed
+    fn send_recv(&self, id: u64, frame: &[u8]) -> Result<Frame, Error> {
+        let waiter = TokenWaiter::new();
+        let buf = self.sealer.seal(frame.to_vec(), None)?;
+
+        self.ids
+            .lock()
+            .unwrap()
+            .insert(id, waiter.id().unwrap().into());
+        let ret = {
+            // the fragments of one frame are sent together under the lock
+            let sock = self.sock.lock().unwrap();
+            udp_frag::split(buf, self.mtu)
+                .and_then(|frags| frags.iter().try_for_each(|data| sock.send(data).map(drop)))
+        };
+
+        // wait for the rsp
+        let rsp = ret.and_then(|_| waiter.wait_rsp(self.timeout));
+        // the late response is dropped by the listener
+        self.ids.lock().unwrap().remove(&id);
+        let rsp_frame: Frame = rsp?;
+        self.compressor.observe(&rsp_frame);
+        Ok(rsp_frame)
+    }
+}
+
+impl Client for UdpMultiplexClient {
+    fn call_service(&self, req: ReqBuf) -> Result<Frame, Error> {
+        let (id, frame) = self.encode(req);
+        self.send_recv(id, &frame)
+    }
+
+    /// the retried requests keep the frame id, so the server with the
+    /// response cache enabled doesn't run the service again
+    fn call_with_retry(&self, req: ReqBuf, policy: &RetryPolicy) -> Result<Frame, Error> {
+        let (id, frame) = self.encode(req);
+        policy.retry(|| self.send_recv(id, &frame))
+    }
+}
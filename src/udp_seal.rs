@@ -0,0 +1,95 @@
+use std::borrow::Cow;
+use std::io;
+#[cfg(feature = "noise")]
+use std::io::ErrorKind;
+use std::sync::Arc;
+
+#[cfg(feature = "noise")]
+pub(crate) use crate::noise::Session;
+#[cfg(feature = "noise")]
+use crate::noise::{self, UdpNoise};
+use crate::udp_frag::UdpConfig;
+
+// the frames are never sealed without the noise feature
+#[cfg(not(feature = "noise"))]
+pub(crate) type Session = ();
+
+/// the opened udp frame
+pub(crate) struct Opened<'a> {
+    pub data: Cow<'a, [u8]>,
+    /// the noise session if sealed, the response is sealed to its sender
+    pub session: Option<Arc<Session>>,
+    /// false if the sealed frame is received before, e.g. retransmitted or replayed
+    pub fresh: bool,
+}
+
+/// seal the udp frames by noise if it's configured
+/// it does nothing if the noise feature is not enabled
+#[derive(Debug, Clone)]
+pub(crate) struct Sealer {
+    #[cfg(feature = "noise")]
+    noise: Option<Arc<UdpNoise>>,
+}
+
+impl Sealer {
+    #[cfg(feature = "noise")]
+    pub fn new(config: &UdpConfig) -> Self {
+        Sealer {
+            noise: config.noise.clone().map(|c| Arc::new(UdpNoise::new(c))),
+        }
+    }
+
+    #[cfg(not(feature = "noise"))]
+    pub fn new(_config: &UdpConfig) -> Self {
+        Sealer {}
+    }
+
+    /// seal the encoded frame to the sender of the request
+    /// the clients pass None to seal for the configured server key
+    #[cfg(feature = "noise")]
+    pub fn seal(&self, frame: Vec<u8>, session: Option<&Session>) -> io::Result<Vec<u8>> {
+        match self.noise {
+            None => Ok(frame),
+            Some(ref noise) => noise.seal(&frame, session),
+        }
+    }
+
+    #[cfg(not(feature = "noise"))]
+    pub fn seal(&self, frame: Vec<u8>, _session: Option<&Session>) -> io::Result<Vec<u8>> {
+        Ok(frame)
+    }
+
+    /// open the received frame
+    /// the plain frames are rejected if noise is configured
+    #[cfg(feature = "noise")]
+    pub fn open<'a>(&self, data: Cow<'a, [u8]>) -> io::Result<Opened<'a>> {
+        match self.noise {
+            None if noise::is_sealed(&data) => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "sealed frame but noise is not configured",
+            )),
+            None => Ok(Opened {
+                data,
+                session: None,
+                fresh: true,
+            }),
+            Some(ref noise) => {
+                let (frame, session, fresh) = noise.open(&data)?;
+                Ok(Opened {
+                    data: Cow::Owned(frame),
+                    session: Some(session),
+                    fresh,
+                })
+            }
+        }
+    }
+
+    #[cfg(not(feature = "noise"))]
+    pub fn open<'a>(&self, data: Cow<'a, [u8]>) -> io::Result<Opened<'a>> {
+        Ok(Opened {
+            data,
+            session: None,
+            fresh: true,
+        })
+    }
+}
@@ -0,0 +1,114 @@
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::compress::{Compression, Compressor};
+use crate::errors::Error;
+use crate::frame::{Frame, ReqBuf};
+use crate::server::UDS_DGRAM_BUF_SIZE;
+
+use may::os::unix::net::UnixDatagram;
+
+#[derive(Debug)]
+pub struct UdsDatagramClient {
+    // each request would have a unique id
+    id: u64,
+    // the connection
+    sock: UnixDatagram,
+    // the local path that the server would reply to
+    path: PathBuf,
+    // send/recv buf
+    buf: Vec<u8>,
+    // compress the requests if negotiated
+    compressor: Compressor,
+}
+
+impl Drop for UdsDatagramClient {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+impl UdsDatagramClient {
+    /// connect to the server path
+    /// the client would bind to a temp path to receive the responses
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<UdsDatagramClient> {
+        static SEQ: AtomicUsize = AtomicUsize::new(0);
+        let local = std::env::temp_dir().join(format!(
+            "conetty-dgram-{}-{}",
+            std::process::id(),
+            SEQ.fetch_add(1, Ordering::Relaxed)
+        ));
+        UdsDatagramClient::connect_from(local, path)
+    }
+
+    /// connect to the server path, binding the client to the local path
+    pub fn connect_from<L: AsRef<Path>, P: AsRef<Path>>(
+        local: L,
+        path: P,
+    ) -> io::Result<UdsDatagramClient> {
+        std::fs::remove_file(&local).ok();
+        let sock = UnixDatagram::bind(&local)?;
+        let client = UdsDatagramClient {
+            id: 0,
+            sock,
+            path: local.as_ref().to_owned(),
+            buf: vec![0; UDS_DGRAM_BUF_SIZE],
+            compressor: Compressor::default(),
+        };
+        client.sock.connect(path)?;
+        client.sock.set_read_timeout(Some(Duration::from_secs(1)))?;
+        Ok(client)
+    }
+
+    /// set the default timeout value
+    /// the initial timeout is 1 seconds
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.sock.set_read_timeout(Some(timeout)).unwrap();
+    }
+
+    /// set the receive buffer size
+    /// each response frame must fit in one datagram
+    pub fn set_buf_size(&mut self, size: usize) {
+        self.buf.resize(size, 0);
+    }
+
+    /// set the compression settings, the compression is disabled by default
+    /// the requests are compressed once the server accepts one of the codecs
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compressor.set_config(compression);
+    }
+}
+
+impl UdsDatagramClient {
+    /// call the server
+    /// the request must be encoded into the ReqBuf
+    /// the response is the raw frame, you should parsing it into final response
+    pub fn call_service(&mut self, req: ReqBuf) -> Result<Frame, Error> {
+        let id = self.id;
+        self.id += 1;
+        info!("request id = {}", id);
+
+        // send the data to server
+        self.sock
+            .send(&self.compressor.encode(req.finish(id)))
+            .map_err(Error::from)?;
+
+        // read the response
+        loop {
+            let len = self.sock.recv(&mut self.buf).map_err(Error::from)?;
+
+            // deserialize the rsp
+            let rsp_frame = Frame::decode_from(&mut Cursor::new(&self.buf[..len]))
+                .map_err(|e| Error::ClientDeserialize(e.to_string()))?;
+
+            // discard the rsp that is is not belong to us
+            if rsp_frame.id == id {
+                info!("get response id = {}", id);
+                self.compressor.observe(&rsp_frame);
+                return Ok(rsp_frame);
+            }
+        }
+    }
+}
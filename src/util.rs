@@ -0,0 +1,17 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+// a random number, each RandomState is seeded with different random keys
+pub(crate) fn random() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    hasher.finish()
+}
+
+// pick a random delay in [delay/2, delay], so the clients don't retry at once
+pub(crate) fn jitter(delay: Duration) -> Duration {
+    let half = delay / 2;
+    let nanos = half.as_nanos() as u64;
+    half + Duration::from_nanos(random() % (nanos + 1))
+}
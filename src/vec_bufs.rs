@@ -0,0 +1,67 @@
+use std::io::{self, IoSlice, Write};
+
+// the max number of the slices in one vectored write, the IOV_MAX of linux and macos
+// the writes with more slices may fail with EINVAL
+const MAX_IOV: usize = 1024;
+
+/// the buffers that are written by the vectored writes
+pub(crate) struct VecBufs<'a> {
+    bufs: &'a [Vec<u8>],
+    block: usize,
+    pos: usize,
+}
+
+impl<'a> VecBufs<'a> {
+    pub fn new(bufs: &'a [Vec<u8>]) -> Self {
+        let mut vec_bufs = VecBufs {
+            bufs,
+            block: 0,
+            pos: 0,
+        };
+        // skip the leading empty buffers
+        vec_bufs.advance(0);
+        vec_bufs
+    }
+
+    // the slices of the data left, at most `MAX_IOV` of them
+    fn get_io_slice(&self) -> Vec<IoSlice<'_>> {
+        let n = (self.bufs.len() - self.block).min(MAX_IOV);
+        let mut ret = Vec::with_capacity(n);
+        ret.push(IoSlice::new(&self.bufs[self.block][self.pos..]));
+        for buf in self.bufs[self.block + 1..].iter().take(n - 1) {
+            ret.push(IoSlice::new(buf))
+        }
+        ret
+    }
+
+    fn advance(&mut self, n: usize) {
+        let mut left = n;
+        for buf in self.bufs[self.block..].iter() {
+            let len = buf.len() - self.pos;
+            if left >= len {
+                left -= len;
+                self.block += 1;
+                self.pos = 0;
+            } else {
+                self.pos += left;
+                break;
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.block == self.bufs.len()
+    }
+
+    /// write all data from the vecs to the writer
+    pub fn write_all<W: Write>(mut self, writer: &mut W) -> io::Result<()> {
+        while !self.is_empty() {
+            let n = writer.write_vectored(&self.get_io_slice())?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.advance(n);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,378 @@
+use std::io::{self, ErrorKind, IoSlice, Read, Write};
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::frame::{Frame, FRAME_MAX_LEN};
+use crate::stream_ext::StreamExt;
+
+use may::net::TcpStream;
+use may::sync::Mutex;
+use tungstenite::protocol::frame::coding::{CloseCode, Control, Data, OpCode};
+use tungstenite::protocol::frame::{CloseFrame, Frame as WsFrame, FrameSocket};
+use tungstenite::protocol::Role;
+
+fn ws_err(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::new(ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+// the client must mask the frames with a key that the proxies can't predict
+fn mask_key() -> io::Result<[u8; 4]> {
+    let mut key = [0; 4];
+    getrandom::getrandom(&mut key)?;
+    Ok(key)
+}
+
+// the payloads of the received binary messages
+#[derive(Debug, Default)]
+struct Inbox {
+    buf: Vec<u8>,
+    pos: usize,
+    // the opcode of the fragmented message that is not finished yet
+    message: Option<Data>,
+}
+
+impl Inbox {
+    fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    fn read(&mut self, out: &mut [u8]) -> usize {
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        n
+    }
+
+    // read one frame, the binary payload is put into the inbox
+    fn recv<R: Read>(&mut self, socket: &mut FrameSocket<R>, role: Role) -> io::Result<Recv> {
+        let mut frame = match socket.read(Some(FRAME_MAX_LEN as usize)).map_err(ws_err)? {
+            Some(frame) => frame,
+            None => return Ok(Recv::Closed(None)),
+        };
+        // only the frames from the client are masked
+        match (frame.header_mut().mask.take(), role) {
+            (Some(mask), Role::Server) => {
+                for (i, b) in frame.payload_mut().iter_mut().enumerate() {
+                    *b ^= mask[i & 3];
+                }
+            }
+            (None, Role::Client) => {}
+            (Some(_), Role::Client) => return Ok(Recv::violated("masked server frame")),
+            (None, Role::Server) => return Ok(Recv::violated("unmasked client frame")),
+        }
+
+        match frame.header().opcode {
+            OpCode::Data(data) => {
+                let opcode = match (data, self.message) {
+                    (Data::Continue, Some(opcode)) => opcode,
+                    (Data::Continue, None) => {
+                        return Ok(Recv::violated("continuation without a message"))
+                    }
+                    (_, Some(_)) => return Ok(Recv::violated("message interrupted")),
+                    (opcode, None) => opcode,
+                };
+                // the continuations of the other messages are rejected too
+                if opcode != Data::Binary {
+                    let s = format!(
+                        "unsupported websocket message. opcode={}",
+                        OpCode::Data(opcode)
+                    );
+                    return Err(io::Error::new(ErrorKind::InvalidData, s));
+                }
+                if frame.header().is_final {
+                    self.message = None;
+                } else {
+                    self.message = Some(opcode);
+                }
+                self.buf = frame.into_data();
+                self.pos = 0;
+                Ok(Recv::Done)
+            }
+            OpCode::Control(Control::Ping) => Ok(Recv::Reply(WsFrame::pong(frame.into_data()))),
+            OpCode::Control(Control::Pong) => Ok(Recv::Done),
+            OpCode::Control(Control::Close) => Ok(Recv::Closed(Some(WsFrame::close(None)))),
+            opcode => {
+                let s = format!("unsupported websocket message. opcode={}", opcode);
+                Err(io::Error::new(ErrorKind::InvalidData, s))
+            }
+        }
+    }
+}
+
+// what to do after receiving a websocket frame
+enum Recv {
+    // nothing to answer
+    Done,
+    // answer the control frame
+    Reply(WsFrame),
+    // the stream is closed, answer the close frame if any
+    Closed(Option<WsFrame>),
+    // the peer breaks the protocol, answer the close frame and fail
+    Violated(WsFrame, io::Error),
+}
+
+impl Recv {
+    fn violated(reason: &'static str) -> Self {
+        let close = CloseFrame {
+            code: CloseCode::Protocol,
+            reason: reason.into(),
+        };
+        let s = format!("websocket protocol error: {}", reason);
+        Recv::Violated(
+            WsFrame::close(Some(close)),
+            io::Error::new(ErrorKind::InvalidData, s),
+        )
+    }
+}
+
+// send the frame, the client side frames must be masked
+fn send_frame<W: Write>(
+    socket: &mut FrameSocket<W>,
+    role: Role,
+    mut frame: WsFrame,
+) -> io::Result<()> {
+    if let Role::Client = role {
+        frame.header_mut().mask = Some(mask_key()?);
+    }
+    socket.send(frame).map_err(ws_err)
+}
+
+// send each complete conetty frame in the pending data as one binary message
+fn send_pending<W: Write>(
+    socket: &mut FrameSocket<W>,
+    role: Role,
+    pending: &mut Vec<u8>,
+) -> io::Result<()> {
+    let mut start = 0;
+    while let Some(len) = Frame::encoded_len(&pending[start..]) {
+        // the common case is that exactly one frame is written
+        if start == 0 && len == pending.len() {
+            let data = std::mem::take(pending);
+            let frame = WsFrame::message(data, OpCode::Data(Data::Binary), true);
+            return send_frame(socket, role, frame);
+        }
+        let data = pending[start..start + len].to_vec();
+        let frame = WsFrame::message(data, OpCode::Data(Data::Binary), true);
+        send_frame(socket, role, frame)?;
+        start += len;
+    }
+    pending.drain(..start);
+    Ok(())
+}
+
+fn pending_write(pending: &mut Vec<u8>, bufs: &[IoSlice<'_>]) -> usize {
+    let mut n = 0;
+    for buf in bufs {
+        pending.extend_from_slice(buf);
+        n += buf.len();
+    }
+    n
+}
+
+/// websocket stream that carries one conetty frame in each binary message
+pub struct WsStream<S: StreamExt = TcpStream> {
+    socket: FrameSocket<S>,
+    role: Role,
+    inbox: Inbox,
+    // the written data that is not a complete frame yet
+    pending: Vec<u8>,
+}
+
+impl WsStream<TcpStream> {
+    /// connect to the websocket server, the uri is like `ws://localhost:8080/`
+    pub fn connect<L: ToSocketAddrs>(addr: L, uri: &str) -> io::Result<Self> {
+        WsStream::connect_stream(TcpStream::connect(addr)?, uri)
+    }
+}
+
+impl<S: StreamExt> WsStream<S> {
+    fn new(stream: S, role: Role) -> Self {
+        WsStream {
+            socket: FrameSocket::new(stream),
+            role,
+            inbox: Inbox::default(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// do the client handshake on the established stream
+    pub fn connect_stream(mut stream: S, uri: &str) -> io::Result<Self> {
+        // the server doesn't send anything before the first request
+        // so nothing is left in the handshake buffer
+        tungstenite::client(uri, &mut stream)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        Ok(WsStream::new(stream, Role::Client))
+    }
+
+    /// do the server handshake on the accepted stream
+    pub fn accept(mut stream: S) -> io::Result<Self> {
+        // the client must wait for the handshake response before sending frames
+        tungstenite::accept(&mut stream)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        Ok(WsStream::new(stream, Role::Server))
+    }
+
+    /// get the underlying stream
+    pub fn get_ref(&self) -> &S {
+        self.socket.get_ref()
+    }
+}
+
+impl<S: StreamExt> std::fmt::Debug for WsStream<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WsStream")
+            .field("role", &self.role)
+            .finish()
+    }
+}
+
+impl<S: StreamExt> Read for WsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.inbox.is_empty() {
+            match self.inbox.recv(&mut self.socket, self.role)? {
+                Recv::Done => {}
+                Recv::Reply(frame) => send_frame(&mut self.socket, self.role, frame)?,
+                Recv::Closed(reply) => {
+                    if let Some(frame) = reply {
+                        send_frame(&mut self.socket, self.role, frame).ok();
+                    }
+                    return Ok(0);
+                }
+                Recv::Violated(frame, e) => {
+                    send_frame(&mut self.socket, self.role, frame).ok();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(self.inbox.read(buf))
+    }
+}
+
+impl<S: StreamExt> Write for WsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_vectored(&[IoSlice::new(buf)])
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let n = pending_write(&mut self.pending, bufs);
+        send_pending(&mut self.socket, self.role, &mut self.pending)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush().map_err(ws_err)
+    }
+}
+
+impl<S: StreamExt> StreamExt for WsStream<S> {
+    type Reader = WsReader<S::Reader, S::Writer>;
+    type Writer = WsWriter<S::Writer>;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        let (stream, part) = self.socket.into_inner();
+        let (reader, writer) = stream.split()?;
+        let writer = Arc::new(Mutex::new(FrameSocket::new(writer)));
+        let reader = WsReader {
+            socket: FrameSocket::from_partially_read(reader, part),
+            writer: writer.clone(),
+            role: self.role,
+            inbox: self.inbox,
+        };
+        let writer = WsWriter {
+            socket: writer,
+            role: self.role,
+            pending: self.pending,
+        };
+        Ok((reader, writer))
+    }
+
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.socket.get_mut().set_read_timeout(timeout)
+    }
+}
+
+/// the read half of the websocket stream
+pub struct WsReader<R, W> {
+    socket: FrameSocket<R>,
+    // answer the ping and close
+    writer: Arc<Mutex<FrameSocket<W>>>,
+    role: Role,
+    inbox: Inbox,
+}
+
+impl<R, W> std::fmt::Debug for WsReader<R, W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WsReader")
+            .field("role", &self.role)
+            .finish()
+    }
+}
+
+impl<R: Read, W: Write> Read for WsReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.inbox.is_empty() {
+            match self.inbox.recv(&mut self.socket, self.role)? {
+                Recv::Done => {}
+                Recv::Reply(frame) => {
+                    send_frame(&mut self.writer.lock().unwrap(), self.role, frame)?
+                }
+                Recv::Closed(reply) => {
+                    if let Some(frame) = reply {
+                        send_frame(&mut self.writer.lock().unwrap(), self.role, frame).ok();
+                    }
+                    return Ok(0);
+                }
+                Recv::Violated(frame, e) => {
+                    send_frame(&mut self.writer.lock().unwrap(), self.role, frame).ok();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(self.inbox.read(buf))
+    }
+}
+
+/// the write half of the websocket stream
+/// each conetty frame is sent as one binary message
+pub struct WsWriter<W: Write> {
+    socket: Arc<Mutex<FrameSocket<W>>>,
+    role: Role,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Drop for WsWriter<W> {
+    fn drop(&mut self) {
+        // notify the peer that no more data would be sent
+        let mut socket = self.socket.lock().unwrap();
+        send_frame(&mut socket, self.role, WsFrame::close(None)).ok();
+    }
+}
+
+impl<W: Write> std::fmt::Debug for WsWriter<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WsWriter")
+            .field("role", &self.role)
+            .finish()
+    }
+}
+
+impl<W: Write> Write for WsWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_vectored(&[IoSlice::new(buf)])
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let n = pending_write(&mut self.pending, bufs);
+        let mut socket = self.socket.lock().unwrap();
+        send_pending(&mut socket, self.role, &mut self.pending)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.lock().unwrap().flush().map_err(ws_err)
+    }
+}
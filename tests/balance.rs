@@ -0,0 +1,88 @@
+mod common;
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::start_tcp;
+use conetty::{Balance, BalanceClient, Client, ReqBuf, RspBuf, Server, TcpServer, WireError};
+use may::net::TcpStream;
+use may::{coroutine, go};
+
+// reply with the replica tag
+struct Replica(u8);
+
+impl Server for Replica {
+    fn service(&self, _req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(&[self.0])
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+fn client(balance: Balance, addrs: &[SocketAddr]) -> BalanceClient<TcpStream> {
+    let mut client = common::balance(balance, addrs);
+    client.set_timeout(Duration::from_secs(5));
+    client
+}
+
+fn call(client: &impl Client) -> Result<u8, conetty::Error> {
+    let rsp_frame = client.call_service(ReqBuf::new())?;
+    Ok(rsp_frame.decode_rsp()?[0])
+}
+
+#[test]
+fn balance_round_robin() {
+    let (_s0, a0) = start_tcp(Replica(0));
+    let (_s1, a1) = start_tcp(Replica(1));
+    let (_s2, a2) = start_tcp(Replica(2));
+    let client = client(Balance::RoundRobin, &[a0, a1, a2]);
+
+    let mut count = [0; 3];
+    for _ in 0..9 {
+        count[call(&client).unwrap() as usize] += 1;
+    }
+    assert_eq!(count, [3, 3, 3]);
+}
+
+#[test]
+fn balance_concurrent() {
+    let (_s0, a0) = start_tcp(Replica(0));
+    let (_s1, a1) = start_tcp(Replica(1));
+
+    for &balance in &[Balance::LeastOutstanding, Balance::PowerOfTwo] {
+        let client = Arc::new(client(balance, &[a0, a1]));
+        let mut vec = vec![];
+        for _ in 0..8 {
+            let client = client.clone();
+            vec.push(go!(move || {
+                for _ in 0..10 {
+                    assert!(call(&*client).unwrap() < 2);
+                }
+            }));
+        }
+        for h in vec {
+            h.join().unwrap();
+        }
+    }
+}
+
+#[test]
+fn balance_eject_failing_endpoint() {
+    let (_s0, a0) = start_tcp(Replica(0));
+    // nothing is listening on the second endpoint yet
+    let a1 = common::free_addr();
+    let mut client = client(Balance::RoundRobin, &[a0, a1]);
+    client.set_ejection(2, Duration::from_millis(200));
+
+    let errors = (0..10).filter(|_| call(&client).is_err()).count();
+    assert_eq!(errors, 2);
+    assert_eq!(client.healthy(), 1);
+    assert!((0..10).all(|_| matches!(call(&client), Ok(0))));
+
+    // the endpoint is tried again after the ejection time
+    let _s1 = Replica(1).start(a1).unwrap();
+    coroutine::sleep(Duration::from_millis(300));
+    assert_eq!(client.healthy(), 2);
+    assert!((0..10).any(|_| matches!(call(&client), Ok(1))));
+}
@@ -0,0 +1,177 @@
+mod common;
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::start_tcp;
+use conetty::{
+    CircuitBreaker, CircuitState, Client, Error, Frame, MultiplexClient, ReqBuf, RspBuf, Server,
+    WireError,
+};
+use may::net::TcpStream;
+use may::{coroutine, go};
+
+// sleep for the milliseconds in the request if any before the echo
+struct Echo;
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        if let Some(&ms) = req.first() {
+            coroutine::sleep(Duration::from_millis(ms as u64));
+        }
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+// time out the calls when told to fail
+struct Flaky {
+    client: MultiplexClient<TcpStream>,
+    fail: Arc<AtomicBool>,
+    calls: Arc<AtomicUsize>,
+}
+
+impl Client for Flaky {
+    fn call_service(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        if self.fail.load(Ordering::SeqCst) {
+            return Err(Error::Timeout);
+        }
+        self.client.call_service(req)
+    }
+}
+
+type Transitions = Arc<Mutex<Vec<(CircuitState, CircuitState)>>>;
+
+fn breaker(
+    addr: SocketAddr,
+) -> (
+    CircuitBreaker<Flaky>,
+    Arc<AtomicBool>,
+    Arc<AtomicUsize>,
+    Transitions,
+) {
+    let fail = Arc::new(AtomicBool::new(false));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let flaky = Flaky {
+        client: common::client(addr),
+        fail: fail.clone(),
+        calls: calls.clone(),
+    };
+    let transitions = Transitions::default();
+    let mut breaker = CircuitBreaker::new(flaky);
+    let changes = transitions.clone();
+    breaker.set_on_state_change(move |from, to| changes.lock().unwrap().push((from, to)));
+    breaker.set_open_time(Duration::from_millis(100));
+    (breaker, fail, calls, transitions)
+}
+
+#[test]
+fn circuit_consecutive_failures() {
+    use CircuitState::*;
+
+    let (_server, addr) = start_tcp(Echo);
+    let (mut breaker, fail, calls, transitions) = breaker(addr);
+    breaker.set_max_failures(3);
+
+    fail.store(true, Ordering::SeqCst);
+    for _ in 0..3 {
+        assert!(matches!(
+            breaker.call_service(ReqBuf::new()),
+            Err(Error::Timeout)
+        ));
+    }
+    assert_eq!(breaker.state(), Open);
+    // fail fast without calling the server
+    assert!(matches!(
+        breaker.call_service(ReqBuf::new()),
+        Err(Error::CircuitOpen)
+    ));
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    // the failed trial call opens the circuit again
+    coroutine::sleep(Duration::from_millis(150));
+    assert!(matches!(
+        breaker.call_service(ReqBuf::new()),
+        Err(Error::Timeout)
+    ));
+    assert_eq!(breaker.state(), Open);
+
+    // the successful trial call closes the circuit
+    fail.store(false, Ordering::SeqCst);
+    coroutine::sleep(Duration::from_millis(150));
+    breaker.call_service(ReqBuf::new()).unwrap();
+    assert_eq!(breaker.state(), Closed);
+
+    assert_eq!(
+        *transitions.lock().unwrap(),
+        vec![
+            (Closed, Open),
+            (Open, HalfOpen),
+            (HalfOpen, Open),
+            (Open, HalfOpen),
+            (HalfOpen, Closed)
+        ]
+    );
+}
+
+#[test]
+fn circuit_error_rate() {
+    let (_server, addr) = start_tcp(Echo);
+    let (mut breaker, fail, _calls, _) = breaker(addr);
+    breaker.set_max_failures(100);
+    breaker.set_error_rate(0.5, 4, Duration::from_secs(10));
+
+    // every other call fails, the circuit opens once there are enough calls
+    for i in 0..4 {
+        fail.store(i % 2 == 1, Ordering::SeqCst);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.call_service(ReqBuf::new()).ok();
+    }
+    assert_eq!(breaker.state(), CircuitState::Open);
+}
+
+#[test]
+fn circuit_stale_call() {
+    let (_server, addr) = start_tcp(Echo);
+    let (mut breaker, fail, _calls, _) = breaker(addr);
+    breaker.set_max_failures(2);
+    let breaker = Arc::new(breaker);
+
+    let slow_call = |breaker: &Arc<CircuitBreaker<Flaky>>| {
+        let breaker = breaker.clone();
+        go!(move || {
+            let mut req = ReqBuf::new();
+            req.write_all(&[250]).unwrap();
+            breaker.call_service(req).is_ok()
+        })
+    };
+
+    // the call is started while closed, and finishes after the circuit is half open
+    let stale = slow_call(&breaker);
+    coroutine::sleep(Duration::from_millis(10));
+    fail.store(true, Ordering::SeqCst);
+    breaker.call_service(ReqBuf::new()).ok();
+    breaker.call_service(ReqBuf::new()).ok();
+    assert_eq!(breaker.state(), CircuitState::Open);
+    fail.store(false, Ordering::SeqCst);
+
+    coroutine::sleep(Duration::from_millis(120));
+    let trial = slow_call(&breaker);
+    coroutine::sleep(Duration::from_millis(20));
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    // only one trial call at a time
+    assert!(matches!(
+        breaker.call_service(ReqBuf::new()),
+        Err(Error::CircuitOpen)
+    ));
+
+    // the stale call doesn't close the circuit, only the trial call does
+    assert!(stale.join().unwrap());
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    assert!(trial.join().unwrap());
+    assert_eq!(breaker.state(), CircuitState::Closed);
+}
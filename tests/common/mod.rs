@@ -0,0 +1,54 @@
+// the fixtures shared by the integration tests, each test crate uses a part of them
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use conetty::{
+    Balance, BalanceClient, MultiplexClient, Server, ServerInstance, TcpServer, UdpConfig,
+    UdpServer,
+};
+use may::net::TcpStream;
+
+/// start the tcp server on a free port, return the instance and the bound address
+pub fn start_tcp<S: Server>(server: S) -> (ServerInstance, SocketAddr) {
+    let instance = TcpServer::start(server, "127.0.0.1:0").unwrap();
+    let addr = instance.local_addr().unwrap();
+    (instance, addr)
+}
+
+/// start the udp server on a free port, return the instance and the bound address
+pub fn start_udp<S: Server>(server: S, config: UdpConfig) -> (ServerInstance, SocketAddr) {
+    let instance = UdpServer::start_with_config(server, "127.0.0.1:0", config).unwrap();
+    let addr = instance.local_addr().unwrap();
+    (instance, addr)
+}
+
+/// a free address for the server that is started later
+pub fn free_addr() -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+/// a unique socket path in the temp dir, the servers remove it when dropped
+pub fn temp_path(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    let file = format!("conetty_{}_{}_{}", name, std::process::id(), n);
+    std::env::temp_dir().join(file)
+}
+
+/// the multiplexed client connected to the tcp server
+pub fn client(addr: SocketAddr) -> MultiplexClient<TcpStream> {
+    MultiplexClient::new(TcpStream::connect(addr).unwrap()).unwrap()
+}
+
+/// the balance client over the tcp servers
+pub fn balance(balance: Balance, addrs: &[SocketAddr]) -> BalanceClient<TcpStream> {
+    let endpoints = addrs
+        .iter()
+        .map(|&addr| move || TcpStream::connect(addr))
+        .collect();
+    BalanceClient::new(balance, endpoints)
+}
@@ -0,0 +1,207 @@
+#![cfg(any(feature = "lz4", feature = "zstd"))]
+
+mod common;
+
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::start_udp;
+use conetty::{
+    Client, Codec, Compression, LoopbackStream, MultiplexClient, ReqBuf, RspBuf, Server,
+    StreamClient, StreamServer, UdpClient, UdpConfig, WireError,
+};
+use may::go;
+
+struct Echo(Compression);
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+
+    fn compression(&self) -> Compression {
+        self.0.clone()
+    }
+}
+
+// the json like payload that compresses well
+fn payload(n: usize) -> Vec<u8> {
+    (0..n)
+        .flat_map(|i| format!("{{\"id\":{},\"name\":\"item\"}},", i % 100).into_bytes())
+        .collect()
+}
+
+fn echo(client: &mut StreamClient<LoopbackStream>, data: &[u8]) {
+    let mut req = ReqBuf::new();
+    req.write_all(data).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, data);
+}
+
+#[test]
+fn compress_negotiated() {
+    let (_server, stream) = Echo(Compression::default()).serve_loopback().unwrap();
+    let mut client = StreamClient::new(stream);
+    client.set_compression(Compression::default());
+
+    // the first request is sent as is, the rest are compressed
+    let data = payload(10_000);
+    for _ in 0..3 {
+        echo(&mut client, &data);
+    }
+    // the small payloads are not compressed
+    echo(&mut client, b"hello");
+    echo(&mut client, b"");
+}
+
+#[test]
+fn compress_mixed_peers() {
+    let data = payload(10_000);
+
+    // the server doesn't compress
+    let (_server, stream) = Echo(Compression::none()).serve_loopback().unwrap();
+    let mut client = StreamClient::new(stream);
+    client.set_compression(Compression::default());
+    echo(&mut client, &data);
+    echo(&mut client, &data);
+
+    // the client doesn't negotiate
+    let (_server, stream) = Echo(Compression::default()).serve_loopback().unwrap();
+    let mut client = StreamClient::new(stream);
+    echo(&mut client, &data);
+    echo(&mut client, &data);
+}
+
+#[cfg(all(feature = "lz4", feature = "zstd"))]
+#[test]
+fn compress_no_common_codec() {
+    let data = payload(10_000);
+    let server = Compression {
+        codecs: vec![Codec::Zstd],
+        ..Default::default()
+    };
+    let (_server, stream) = Echo(server).serve_loopback().unwrap();
+    let mut client = StreamClient::new(stream);
+    client.set_compression(Compression {
+        codecs: vec![Codec::Lz4],
+        ..Default::default()
+    });
+    echo(&mut client, &data);
+    echo(&mut client, &data);
+}
+
+#[test]
+fn compress_multiplex() {
+    let (_server, stream) = Echo(Compression::default()).serve_loopback().unwrap();
+    let mut client = MultiplexClient::new(stream).unwrap();
+    client.set_timeout(Duration::from_secs(5));
+    client.set_compression(Compression::default());
+    let client = Arc::new(client);
+
+    let mut vec = vec![];
+    for i in 0..8 {
+        let client = client.clone();
+        let h = go!(move || {
+            let data = payload(1000 * (i + 1));
+            for _ in 0..10 {
+                let mut req = ReqBuf::new();
+                req.write_all(&data).unwrap();
+                let rsp_frame = client.call_service(req).unwrap();
+                let rsp = rsp_frame.decode_rsp().unwrap();
+                assert_eq!(rsp, data.as_slice());
+            }
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+}
+
+#[test]
+fn compress_udp() {
+    let (_server, addr) = start_udp(Echo(Compression::default()), UdpConfig::default());
+    let mut client = UdpClient::connect(addr).unwrap();
+    client.set_compression(Compression::default());
+
+    // the compressed frame fits in one datagram
+    let data = payload(10_000);
+    for _ in 0..2 {
+        let mut req = ReqBuf::new();
+        req.write_all(&data).unwrap();
+        let rsp_frame = client.call_service(req).unwrap();
+        let rsp = rsp_frame.decode_rsp().unwrap();
+        assert_eq!(rsp, data.as_slice());
+    }
+}
+
+#[test]
+fn compress_legacy_server() {
+    use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+    use std::io::Read;
+
+    // the server that decodes the frames without knowing the compression flags
+    let listener = may::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let _server = go!(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        while let Ok(id) = stream.read_u64::<BigEndian>() {
+            let len = stream.read_u64::<BigEndian>().unwrap();
+            // any flag bits make the frame too big, the old peers drop the connection
+            assert!(len <= 1024 * 1024, "len = {:#x}", len);
+            let mut req = vec![0; len as usize];
+            stream.read_exact(&mut req).unwrap();
+
+            // echo the id as is
+            let mut rsp = vec![];
+            rsp.write_u64::<BigEndian>(id).unwrap();
+            rsp.write_u64::<BigEndian>(len + 9).unwrap();
+            rsp.write_u8(0).unwrap();
+            rsp.write_u64::<BigEndian>(len).unwrap();
+            rsp.extend_from_slice(&req);
+            stream.write_all(&rsp).unwrap();
+        }
+    });
+
+    let tcp_stream = may::net::TcpStream::connect(addr).unwrap();
+    let mut client = StreamClient::new(tcp_stream);
+    client.set_timeout(Duration::from_secs(5)).unwrap();
+    client.set_compression(Compression::default());
+
+    // the compression is never negotiated, all the frames are sent as is
+    let data = payload(10_000);
+    for _ in 0..3 {
+        let mut req = ReqBuf::new();
+        req.write_all(&data).unwrap();
+        let rsp_frame = client.call_service(req).unwrap();
+        assert_eq!(rsp_frame.decode_rsp().unwrap(), data.as_slice());
+    }
+}
+
+#[test]
+fn compress_echo_ids() {
+    use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+    use std::io::Read;
+
+    let (_server, mut stream) = Echo(Compression::default()).serve_loopback().unwrap();
+    // the server never rewrites the ids, with or without the offer bit
+    for &id in &[0x7fff_ffff_ffff_ffff, 0x40ff_0000_0000_0001, (1 << 63) | 5] {
+        let mut req = vec![];
+        req.write_u64::<BigEndian>(id).unwrap();
+        req.write_u64::<BigEndian>(5).unwrap();
+        req.extend_from_slice(b"hello");
+        stream.write_all(&req).unwrap();
+
+        assert_eq!(stream.read_u64::<BigEndian>().unwrap(), id);
+        // the flags are only sent back for the offer
+        let head = stream.read_u64::<BigEndian>().unwrap();
+        assert_eq!(head >> 48 != 0, id >> 63 != 0);
+        let mut rsp = vec![0; (head & 0xffff_ffff_ffff) as usize];
+        stream.read_exact(&mut rsp).unwrap();
+        assert_eq!(&rsp[9..], b"hello");
+    }
+}
@@ -0,0 +1,99 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::io::{ErrorKind, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::Duration;
+
+use conetty::{
+    Announcer, Client, ReqBuf, Resolver, RspBuf, Server, ServiceInfo, Transport, WireError,
+};
+use may::coroutine;
+
+struct Echo;
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+#[test]
+fn discover_echo() {
+    let (_server, addr) = common::start_tcp(Echo);
+
+    let group = SocketAddrV4::new(Ipv4Addr::new(239, 255, 67, 79), 7668);
+    let lo = Ipv4Addr::LOCALHOST;
+    let resolver = Resolver::start_with(group, lo).unwrap();
+    let info = ServiceInfo {
+        name: "echo".to_owned(),
+        transport: Transport::Tcp,
+        addr: addr.to_string(),
+    };
+    let interval = Duration::from_millis(100);
+    let announcer = Announcer::start_with(group, lo, vec![info.clone()], interval).unwrap();
+
+    let mut found = vec![];
+    for _ in 0..20 {
+        found = resolver.resolve("echo");
+        if !found.is_empty() {
+            break;
+        }
+        coroutine::sleep(interval);
+    }
+    assert_eq!(found, vec![info]);
+    assert!(resolver.resolve("other").is_empty());
+
+    let client = resolver
+        .connect("echo", |info| {
+            may::net::TcpStream::connect(info.socket_addr()?)
+        })
+        .unwrap();
+    let mut req = ReqBuf::new();
+    req.write_all(&[5u8; 16]).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), &[5u8; 16]);
+
+    // the service would expire after the announcer stopped
+    drop(announcer);
+    coroutine::sleep(interval * 4);
+    assert!(resolver.resolve("echo").is_empty());
+}
+
+#[test]
+fn discover_long_name() {
+    let group = SocketAddrV4::new(Ipv4Addr::new(239, 255, 67, 80), 7669);
+    let lo = Ipv4Addr::LOCALHOST;
+    let resolver = Resolver::start_with(group, lo).unwrap();
+    let interval = Duration::from_millis(100);
+    // nothing is connected to the address
+    let addr = common::free_addr().to_string();
+
+    // the name can't be encoded in the announcement
+    let info = ServiceInfo {
+        name: "x".repeat(70000),
+        transport: Transport::Tcp,
+        addr: addr.clone(),
+    };
+    let err = Announcer::start_with(group, lo, vec![info], interval).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    // the announcement is bigger than the small receive buffers
+    let info = ServiceInfo {
+        name: "y".repeat(4000),
+        transport: Transport::Tcp,
+        addr,
+    };
+    let _announcer = Announcer::start_with(group, lo, vec![info.clone()], interval).unwrap();
+    let mut found = vec![];
+    for _ in 0..20 {
+        found = resolver.resolve(&info.name);
+        if !found.is_empty() {
+            break;
+        }
+        coroutine::sleep(interval);
+    }
+    assert_eq!(found, vec![info]);
+}
@@ -0,0 +1,204 @@
+mod common;
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use common::start_tcp;
+use conetty::{
+    Balance, BalanceClient, CancelToken, Client, Error, Frame, HedgeClient, HedgeStats,
+    MultiplexClient, PoolClient, PoolConn, ReqBuf, RetryClient, RetryPolicy, RspBuf, Server,
+    WireError,
+};
+use may::coroutine;
+use may::net::TcpStream;
+
+// reply with the replica tag after the delay
+struct Replica(u8, Duration);
+
+impl Server for Replica {
+    fn service(&self, _req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        coroutine::sleep(self.1);
+        rsp.write_all(&[self.0])
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+#[test]
+fn hedge_slow_replica() {
+    let slow = Duration::from_millis(1000);
+    let (_s0, a0) = start_tcp(Replica(0, slow));
+    let (_s1, a1) = start_tcp(Replica(1, Duration::from_millis(0)));
+    let client = hedge(balance(&[a0, a1]));
+
+    // the first copy goes to the slow replica, the hedge to the fast one
+    let start = Instant::now();
+    let rsp_frame = client.call_hedged(ReqBuf::new()).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), &[1]);
+    assert!(start.elapsed() < slow / 2);
+    let stats = client.stats();
+    assert_eq!(
+        stats,
+        HedgeStats {
+            calls: 1,
+            fired: 1,
+            won: 1
+        }
+    );
+    assert!(client.delay() >= Duration::from_millis(50));
+}
+
+#[test]
+fn hedge_fast_replica() {
+    let (_s0, a0) = start_tcp(Replica(0, Duration::from_millis(0)));
+    let client = hedge(balance(&[a0]));
+
+    for _ in 0..10 {
+        let rsp_frame = client.call_hedged(ReqBuf::new()).unwrap();
+        assert_eq!(rsp_frame.decode_rsp().unwrap(), &[0]);
+    }
+    // the calls that are not hedged are recorded too
+    client.call_service(ReqBuf::new()).unwrap();
+    let stats = client.stats();
+    assert_eq!((stats.calls, stats.fired, stats.won), (10, 0, 0));
+}
+
+// record how the cancellable calls end and when
+#[derive(Clone)]
+struct Ends(Arc<Mutex<Vec<(bool, Duration)>>>);
+
+impl Ends {
+    fn new() -> Self {
+        Ends(Arc::new(Mutex::new(Vec::new())))
+    }
+
+    fn record(&self, start: Instant, ret: &Result<Frame, Error>) {
+        let cancelled = matches!(ret, Err(Error::Cancelled));
+        self.0.lock().unwrap().push((cancelled, start.elapsed()));
+    }
+
+    // the copy on the slow replica is abandoned without waiting for its response
+    fn assert_cancelled(&self, slow: Duration) {
+        let ends = self.0.lock().unwrap();
+        assert_eq!(ends.len(), 2);
+        let (cancelled, elapsed) = ends.iter().find(|end| end.0).copied().unwrap();
+        assert!(cancelled);
+        assert!(elapsed < slow / 2);
+    }
+}
+
+struct Recorder<C> {
+    client: C,
+    ends: Ends,
+}
+
+impl<C: Client> Client for Recorder<C> {
+    fn call_service(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.client.call_service(req)
+    }
+
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        let start = Instant::now();
+        let ret = self.client.call_cancellable(req, token);
+        self.ends.record(start, &ret);
+        ret
+    }
+}
+
+impl<C: Client + Send + Sync + 'static> PoolConn for Recorder<C> {
+    fn call(&self, req: ReqBuf) -> Result<Frame, Error> {
+        self.client.call_service(req)
+    }
+
+    fn call_cancellable(&self, req: ReqBuf, token: &CancelToken) -> Result<Frame, Error> {
+        Client::call_cancellable(self, req, token)
+    }
+
+    fn is_broken(&self, _err: Option<&Error>) -> bool {
+        false
+    }
+}
+
+fn balance(addrs: &[SocketAddr]) -> BalanceClient<TcpStream> {
+    common::balance(Balance::RoundRobin, addrs)
+}
+
+fn hedge<C: Client + Send + Sync + 'static>(client: C) -> HedgeClient<C> {
+    let mut client = HedgeClient::new(client);
+    client.set_delay(0.95, Duration::from_millis(50));
+    client
+}
+
+#[test]
+fn hedge_cancel_slow_copy() {
+    let slow = Duration::from_millis(1000);
+    let (_s0, a0) = start_tcp(Replica(0, slow));
+    let (_s1, a1) = start_tcp(Replica(1, Duration::from_millis(0)));
+    let ends = Ends::new();
+    let client = hedge(Recorder {
+        client: balance(&[a0, a1]),
+        ends: ends.clone(),
+    });
+
+    let rsp_frame = client.call_hedged(ReqBuf::new()).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), &[1]);
+    coroutine::sleep(Duration::from_millis(100));
+    ends.assert_cancelled(slow);
+}
+
+#[test]
+fn hedge_cancel_through_retry() {
+    let slow = Duration::from_millis(1000);
+    let (_s0, a0) = start_tcp(Replica(0, slow));
+    let (_s1, a1) = start_tcp(Replica(1, Duration::from_millis(0)));
+    let ends = Ends::new();
+    let recorder = Recorder {
+        client: balance(&[a0, a1]),
+        ends: ends.clone(),
+    };
+    let client = hedge(RetryClient::new(recorder, RetryPolicy::new(3)));
+
+    let rsp_frame = client.call_hedged(ReqBuf::new()).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), &[1]);
+    coroutine::sleep(Duration::from_millis(100));
+    // the cancelled copy is not retried
+    ends.assert_cancelled(slow);
+}
+
+// the first call is slow, the others are answered at once
+struct SlowFirst(AtomicUsize, Duration);
+
+impl Server for SlowFirst {
+    fn service(&self, _req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        let i = self.0.fetch_add(1, Ordering::SeqCst);
+        if i == 0 {
+            coroutine::sleep(self.1);
+        }
+        rsp.write_all(&[i as u8])
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+#[test]
+fn hedge_cancel_through_pool() {
+    let slow = Duration::from_millis(1000);
+    let (_server, addr) = start_tcp(SlowFirst(AtomicUsize::new(0), slow));
+    let ends = Ends::new();
+    let recorder = ends.clone();
+    // each copy takes its own connection
+    let pool = PoolClient::new(2, move || {
+        let client = MultiplexClient::new(TcpStream::connect(addr)?)?;
+        Ok(Recorder {
+            client,
+            ends: recorder.clone(),
+        })
+    });
+    let client = hedge(pool);
+
+    let rsp_frame = client.call_hedged(ReqBuf::new()).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), &[1]);
+    coroutine::sleep(Duration::from_millis(100));
+    ends.assert_cancelled(slow);
+}
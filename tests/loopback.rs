@@ -0,0 +1,148 @@
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use conetty::{
+    Client, LoopbackStream, MultiplexClient, ReqBuf, RspBuf, Server, StreamClient, StreamExt,
+    StreamServer, WireError,
+};
+use may::go;
+
+struct Echo;
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+#[test]
+fn echo() {
+    let (_server, stream) = Echo.serve_loopback().unwrap();
+    let mut client = StreamClient::new(stream);
+
+    let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+    let mut req = ReqBuf::new();
+    req.write_all(&data).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, data.as_slice());
+}
+
+#[test]
+fn multiplex_client() {
+    let (_server, stream) = Echo.serve_loopback().unwrap();
+    let mut client = MultiplexClient::new(stream).unwrap();
+    client.set_timeout(Duration::from_secs(5));
+    let client = Arc::new(client);
+
+    let mut vec = vec![];
+    for i in 0..8 {
+        let client = client.clone();
+        let h = go!(move || {
+            for j in 0..100 {
+                let mut req = ReqBuf::new();
+                write!(req, "Hello World! id={}, j={}", i, j).unwrap();
+                let rsp_frame = client.call_service(req).unwrap();
+                let rsp = rsp_frame.decode_rsp().unwrap();
+                assert_eq!(rsp, format!("Hello World! id={}, j={}", i, j).as_bytes());
+            }
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+}
+
+// a wrapper that can't be cloned, it must split the inner stream first
+struct Xor<T>(T);
+
+impl<T: Read> Read for Xor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.0.read(buf)?;
+        buf[..n].iter_mut().for_each(|b| *b ^= 0x5a);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Xor<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let data: Vec<u8> = buf.iter().map(|b| b ^ 0x5a).collect();
+        self.0.write(&data)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl StreamExt for Xor<LoopbackStream> {
+    type Reader = Xor<<LoopbackStream as StreamExt>::Reader>;
+    type Writer = Xor<<LoopbackStream as StreamExt>::Writer>;
+
+    fn split(self) -> std::io::Result<(Self::Reader, Self::Writer)> {
+        let (r, w) = self.0.split()?;
+        Ok((Xor(r), Xor(w)))
+    }
+
+    fn set_read_timeout(&mut self, timeout: Duration) -> std::io::Result<()> {
+        self.0.set_read_timeout(timeout)
+    }
+}
+
+#[test]
+fn split_wrapper() {
+    let (client_stream, server_stream) = LoopbackStream::pair();
+    let _server = Echo.serve(Xor(server_stream)).unwrap();
+    let mut client = MultiplexClient::new(Xor(client_stream)).unwrap();
+    client.set_timeout(Duration::from_secs(5));
+
+    for i in 0..10 {
+        let mut req = ReqBuf::new();
+        write!(req, "Hello World! id={}", i).unwrap();
+        let rsp_frame = client.call_service(req).unwrap();
+        let rsp = rsp_frame.decode_rsp().unwrap();
+        assert_eq!(rsp, format!("Hello World! id={}", i).as_bytes());
+    }
+}
+
+#[test]
+fn peer_closed() {
+    let (mut a, b) = LoopbackStream::pair();
+    let c = b.try_clone().unwrap();
+    drop(b);
+    // the clone keeps the peer alive
+    a.write_all(b"hello").unwrap();
+    drop(c);
+    let mut buf = [0u8; 8];
+    assert_eq!(a.read(&mut buf).unwrap(), 0);
+    assert!(a.write(b"hello").is_err());
+}
+
+#[test]
+fn split_half_close() {
+    let (a, mut b) = LoopbackStream::pair();
+    let (mut reader, mut writer) = a.split().unwrap();
+    writer.write_all(b"hello").unwrap();
+    // the peer reads EOF while the read half is still alive
+    drop(writer);
+    let mut buf = vec![];
+    b.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"hello");
+
+    b.write_all(b"world").unwrap();
+    let mut buf = [0u8; 5];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"world");
+}
+
+#[test]
+fn read_timeout() {
+    let (mut a, _b) = LoopbackStream::pair();
+    a.set_read_timeout(Duration::from_millis(100)).unwrap();
+    let mut buf = [0u8; 8];
+    assert!(a.read(&mut buf).is_err());
+}
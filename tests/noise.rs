@@ -0,0 +1,284 @@
+#![cfg(feature = "noise")]
+
+mod common;
+
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::start_udp;
+use conetty::{
+    Client, LoopbackStream, MultiplexClient, NoiseConfig, NoiseServer, NoiseStream, ReqBuf, RspBuf,
+    Server, StreamClient, StreamServer, UdpClient, UdpConfig, UdpMultiplexClient, UdpServer,
+    WireError,
+};
+use may::go;
+
+struct Echo;
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+impl NoiseServer for Echo {}
+
+// the (server, client) configs that trust each other
+fn configs() -> (NoiseConfig, NoiseConfig) {
+    let (server_key, server_pub) = NoiseConfig::generate_keypair().unwrap();
+    let (client_key, client_pub) = NoiseConfig::generate_keypair().unwrap();
+    (
+        NoiseConfig::new(&server_key).allow_peer(&client_pub),
+        NoiseConfig::new(&client_key).allow_peer(&server_pub),
+    )
+}
+
+#[test]
+fn noise_loopback() {
+    let (server_config, client_config) = configs();
+    let (client, server) = LoopbackStream::pair();
+    let h = go!(move || NoiseStream::accept(server, &server_config).unwrap());
+    let stream = NoiseStream::connect(client, &client_config).unwrap();
+    let _server = Echo.serve(h.join().unwrap()).unwrap();
+    let mut client = StreamClient::new(stream);
+
+    // bigger than one noise message
+    let data: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+    let mut req = ReqBuf::new();
+    req.write_all(&data).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, data.as_slice());
+}
+
+#[test]
+fn noise_unknown_peer() {
+    let (server_config, _) = configs();
+    let (_, client_config) = configs();
+    let (client, server) = LoopbackStream::pair();
+    let h = go!(move || NoiseStream::accept(server, &server_config));
+    // the client doesn't trust the server key
+    assert!(NoiseStream::connect(client, &client_config).is_err());
+    assert!(h.join().unwrap().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn noise_uds() {
+    use conetty::UdsServer;
+
+    let (server_config, client_config) = configs();
+    let path = common::temp_path("noise");
+    let _server = Echo.start_noise(&path, server_config).unwrap();
+    let unix_stream = may::os::unix::net::UnixStream::connect(&path).unwrap();
+    let stream = NoiseStream::connect(unix_stream, &client_config).unwrap();
+    let mut client = MultiplexClient::new(stream).unwrap();
+    client.set_timeout(Duration::from_secs(5));
+    let client = Arc::new(client);
+
+    let mut vec = vec![];
+    for i in 0..8 {
+        let client = client.clone();
+        let h = go!(move || {
+            for j in 0..10 {
+                let mut req = ReqBuf::new();
+                write!(req, "Hello World! id={}, j={}", i, j).unwrap();
+                let rsp_frame = client.call_service(req).unwrap();
+                let rsp = rsp_frame.decode_rsp().unwrap();
+                assert_eq!(rsp, format!("Hello World! id={}, j={}", i, j).as_bytes());
+            }
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+}
+
+#[test]
+fn noise_udp() {
+    let (server_config, client_config) = configs();
+    let config = UdpConfig {
+        noise: Some(server_config),
+        ..Default::default()
+    };
+    let (_server, addr) = start_udp(Echo, config);
+
+    let config = UdpConfig {
+        noise: Some(client_config.clone()),
+        ..Default::default()
+    };
+    let mut client = UdpClient::connect_with_config(addr, config).unwrap();
+
+    // the sealed frame must be fragmented
+    let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+    let mut req = ReqBuf::new();
+    req.write_all(&data).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, data.as_slice());
+
+    let config = UdpConfig {
+        noise: Some(client_config),
+        ..Default::default()
+    };
+    let client = UdpMultiplexClient::connect_with_config(addr, config).unwrap();
+    let mut req = ReqBuf::new();
+    req.write_all(&[5u8; 16]).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, &[5u8; 16]);
+}
+
+#[test]
+fn noise_udp_restarted() {
+    let (server_config, client_config) = configs();
+    let server_config = move || UdpConfig {
+        noise: Some(server_config.clone()),
+        ..Default::default()
+    };
+    let (server, addr) = start_udp(Echo, server_config());
+    let config = UdpConfig {
+        noise: Some(client_config),
+        ..Default::default()
+    };
+    let mut client = UdpClient::connect_with_config(addr, config).unwrap();
+    let mut call = |i: u8| {
+        let mut req = ReqBuf::new();
+        req.write_all(&[i; 16]).unwrap();
+        let rsp_frame = client.call_service(req).unwrap();
+        assert_eq!(rsp_frame.decode_rsp().unwrap(), &[i; 16]);
+    };
+    call(0);
+
+    // the restarted server seals the responses by a new session,
+    // and opens the requests of the old client session
+    drop(server);
+    let _server = UdpServer::start_with_config(Echo, addr, server_config()).unwrap();
+    call(1);
+}
+
+#[test]
+fn noise_udp_rejected() {
+    let (server_config, _) = configs();
+    let config = UdpConfig {
+        noise: Some(server_config),
+        ..Default::default()
+    };
+    let (_server, addr) = start_udp(Echo, config);
+
+    // the plain frames are dropped by the server
+    let mut client = UdpClient::connect(addr).unwrap();
+    client.set_timeout(Duration::from_millis(200));
+    assert!(client.call_service(ReqBuf::new()).is_err());
+}
+
+#[test]
+fn noise_peer_key() {
+    use conetty::TcpServer;
+
+    struct WhoAmI;
+
+    impl Server for WhoAmI {
+        fn service(&self, _req: &[u8], _rsp: &mut RspBuf) -> Result<(), WireError> {
+            Err(WireError::Status("no peer key".to_owned()))
+        }
+    }
+
+    impl NoiseServer for WhoAmI {
+        fn noise_service(
+            &self,
+            peer: &[u8],
+            _req: &[u8],
+            rsp: &mut RspBuf,
+        ) -> Result<(), WireError> {
+            rsp.write_all(peer)
+                .map_err(|e| WireError::ServerSerialize(e.to_string()))
+        }
+    }
+
+    let (server_key, server_pub) = NoiseConfig::generate_keypair().unwrap();
+    let (client_key, client_pub) = NoiseConfig::generate_keypair().unwrap();
+    let server = WhoAmI
+        .start_noise(
+            "127.0.0.1:0",
+            NoiseConfig::new(&server_key).allow_any_peer(),
+        )
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let tcp_stream = may::net::TcpStream::connect(addr).unwrap();
+    let config = NoiseConfig::new(&client_key).allow_peer(&server_pub);
+    let stream = NoiseStream::connect(tcp_stream, &config).unwrap();
+    let mut client = StreamClient::new(stream);
+    let rsp_frame = client.call_service(ReqBuf::new()).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), client_pub.as_slice());
+}
+
+#[test]
+fn noise_no_peer_allowed() {
+    let (server_key, server_pub) = NoiseConfig::generate_keypair().unwrap();
+    let (client_key, _) = NoiseConfig::generate_keypair().unwrap();
+    let server_config = NoiseConfig::new(&server_key);
+    let client_config = NoiseConfig::new(&client_key).allow_peer(&server_pub);
+    let (client, server) = LoopbackStream::pair();
+    let h = go!(move || NoiseStream::accept(server, &server_config));
+    // no peer is allowed without `allow_any_peer`
+    drop(NoiseStream::connect(client, &client_config));
+    assert!(h.join().unwrap().is_err());
+}
+
+#[test]
+fn noise_udp_replayed() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // count the calls of the service
+    struct Count(Arc<AtomicUsize>);
+
+    impl Server for Count {
+        fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            rsp.write_all(req)
+                .map_err(|e| WireError::ServerSerialize(e.to_string()))
+        }
+    }
+
+    let (server_config, client_config) = configs();
+    let count = Arc::new(AtomicUsize::new(0));
+    let config = UdpConfig {
+        noise: Some(server_config),
+        rsp_cache_size: 0,
+        ..Default::default()
+    };
+    let (_server, addr) = start_udp(Count(count.clone()), config);
+
+    // the proxy sends each request to the server twice
+    let proxy = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let proxy_addr = proxy.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let mut buf = vec![0; 65536];
+        let (n, client_addr) = proxy.recv_from(&mut buf).unwrap();
+        proxy.send_to(&buf[..n], addr).unwrap();
+        proxy.send_to(&buf[..n], addr).unwrap();
+        while let Ok((n, _)) = proxy.recv_from(&mut buf) {
+            proxy.send_to(&buf[..n], client_addr).unwrap();
+        }
+    });
+
+    let config = UdpConfig {
+        noise: Some(client_config),
+        ..Default::default()
+    };
+    let mut client = UdpClient::connect_with_config(proxy_addr, config).unwrap();
+    let mut req = ReqBuf::new();
+    req.write_all(&[5u8; 16]).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), &[5u8; 16]);
+
+    // the replayed request never runs the service
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(count.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,134 @@
+#![cfg(unix)]
+
+use std::io::Write;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+use byteorder::{BigEndian, WriteBytesExt};
+use conetty::{
+    ChildClient, Client, PipeStream, ReqBuf, RspBuf, Server, StreamExt, StreamServer, WireError,
+};
+use may::go;
+
+// set for the child process that runs `child_server`
+const CHILD_SERVER: &str = "CONETTY_CHILD_SERVER";
+
+struct Echo;
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+// `cat` echoes the request frame back, so encode the req as a rsp body
+fn echo_req(data: &[u8]) -> ReqBuf {
+    let mut req = ReqBuf::new();
+    req.write_u8(0).unwrap();
+    req.write_u64::<BigEndian>(data.len() as u64).unwrap();
+    req.write_all(data).unwrap();
+    req
+}
+
+#[test]
+fn child_echo() {
+    let mut client = ChildClient::spawn(&mut Command::new("cat")).unwrap();
+    client.set_timeout(Duration::from_secs(5));
+    let client = Arc::new(client);
+
+    let mut vec = vec![];
+    for i in 0..8 {
+        let client = client.clone();
+        let h = go!(move || {
+            for j in 0..100 {
+                let data = format!("Hello World! id={}, j={}", i, j);
+                let rsp_frame = client.call_service(echo_req(data.as_bytes())).unwrap();
+                let rsp = rsp_frame.decode_rsp().unwrap();
+                assert_eq!(rsp, data.as_bytes());
+            }
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+}
+
+// a pair of streams over two pipes
+fn pipe_pair() -> (PipeStream, PipeStream) {
+    let (mut p0, mut p1) = ([0; 2], [0; 2]);
+    assert_eq!(unsafe { libc::pipe(p0.as_mut_ptr()) }, 0);
+    assert_eq!(unsafe { libc::pipe(p1.as_mut_ptr()) }, 0);
+    let a = unsafe { PipeStream::from_raw_fds(p0[0], p1[1]) }.unwrap();
+    let b = unsafe { PipeStream::from_raw_fds(p1[0], p0[1]) }.unwrap();
+    (a, b)
+}
+
+#[test]
+fn split_half_close() {
+    use std::io::Read;
+
+    let (a, mut b) = pipe_pair();
+    let (mut reader, mut writer) = a.split().unwrap();
+    writer.write_all(b"hello").unwrap();
+    // the peer reads EOF while the read half is still alive
+    drop(writer);
+    let mut buf = vec![];
+    b.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"hello");
+
+    b.write_all(b"world").unwrap();
+    let mut buf = [0u8; 5];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"world");
+}
+
+#[test]
+fn child_exited() {
+    let mut client = ChildClient::spawn(Command::new("sh").args(["-c", "exit 3"])).unwrap();
+    client.set_timeout(Duration::from_secs(1));
+
+    let ret = client.call_service(echo_req(b"hello"));
+    assert!(ret.is_err());
+    assert!(client.try_wait().unwrap().is_some());
+}
+
+// the server of the child process, a no-op in the normal test run
+#[test]
+fn child_server() {
+    if std::env::var_os(CHILD_SERVER).is_none() {
+        return;
+    }
+    // libtest writes to stdout, so the parent passes the pipe as fd 3
+    assert_eq!(unsafe { libc::dup2(3, 1) }, 1);
+    let _server = Echo.serve(PipeStream::stdio().unwrap()).unwrap();
+    // killed by the parent
+    std::thread::sleep(Duration::from_secs(30));
+}
+
+#[test]
+fn child_stdio_server() {
+    // run this test binary as the child, only with `child_server`
+    let exe = std::env::current_exe().unwrap();
+    let mut cmd = Command::new("sh");
+    cmd.args([
+        "-c",
+        "exec \"$0\" child_server --exact --nocapture 3>&1 1>&2",
+    ])
+    .arg(exe)
+    .env(CHILD_SERVER, "1");
+    let mut client = ChildClient::spawn(&mut cmd).unwrap();
+    client.set_timeout(Duration::from_secs(5));
+
+    for i in 0..10 {
+        let mut req = ReqBuf::new();
+        write!(req, "Hello World! i={}", i).unwrap();
+        let rsp_frame = client.call_service(req).unwrap();
+        let rsp = rsp_frame.decode_rsp().unwrap();
+        assert_eq!(rsp, format!("Hello World! i={}", i).as_bytes());
+    }
+    assert!(client.try_wait().unwrap().is_none());
+}
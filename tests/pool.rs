@@ -0,0 +1,150 @@
+mod common;
+
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::start_tcp;
+use conetty::{
+    Client, MultiplexClient, PoolClient, ReqBuf, RspBuf, Server, StreamClient, TcpServer, WireError,
+};
+use may::net::TcpStream;
+use may::sync::Mutex;
+use may::{coroutine, go};
+
+struct Echo;
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+// sleep before the echo, so the calls are in flight together
+struct Slow;
+
+impl Server for Slow {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        coroutine::sleep(Duration::from_millis(100));
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+fn call(client: &impl Client, msg: &str) {
+    let mut req = ReqBuf::new();
+    req.write_all(msg.as_bytes()).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, msg.as_bytes());
+}
+
+#[test]
+fn pool_multiplex() {
+    let (_server, addr) = start_tcp(Echo);
+    let pool = PoolClient::new(4, move || {
+        let mut client = MultiplexClient::new(TcpStream::connect(addr)?)?;
+        client.set_timeout(Duration::from_secs(5));
+        Ok(client)
+    });
+    // the connections are created lazily
+    assert_eq!(pool.size(), 0);
+    let pool = Arc::new(pool);
+
+    let mut vec = vec![];
+    for i in 0..8 {
+        let pool = pool.clone();
+        let h = go!(move || {
+            for j in 0..10 {
+                call(&*pool, &format!("Hello World! id={}, j={}", i, j));
+            }
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+    assert!(pool.size() >= 1 && pool.size() <= 4);
+}
+
+#[test]
+fn pool_max_concurrent() {
+    let (_server, addr) = start_tcp(Slow);
+    let mut pool = PoolClient::new(4, move || {
+        let mut client = MultiplexClient::new(TcpStream::connect(addr)?)?;
+        client.set_timeout(Duration::from_secs(5));
+        Ok(client)
+    });
+    pool.set_max_concurrent(4);
+    let pool = Arc::new(pool);
+    call(&*pool, "hello");
+
+    // the concurrent calls share the connection up to the threshold
+    let vec: Vec<_> = (0..4)
+        .map(|i| {
+            let pool = pool.clone();
+            go!(move || call(&*pool, &format!("id={}", i)))
+        })
+        .collect();
+    for j in vec {
+        j.join().unwrap();
+    }
+    assert_eq!(pool.size(), 1);
+}
+
+#[test]
+fn pool_stream_max_idle() {
+    let (_server, addr) = start_tcp(Slow);
+    let mut pool = PoolClient::new(4, move || {
+        let client = StreamClient::new(TcpStream::connect(addr)?);
+        Ok(Mutex::new(client))
+    });
+    pool.set_max_idle(1);
+    let pool = Arc::new(pool);
+
+    // the busy connections are not shared, so the pool grows to the max
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut vec = vec![];
+    for i in 0..8 {
+        let pool = pool.clone();
+        let tx = tx.clone();
+        let h = go!(move || {
+            call(&*pool, &format!("id={}", i));
+            tx.send(pool.size()).unwrap();
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+    drop(tx);
+    assert!(rx.iter().all(|size| size <= 4));
+    // only the max idle connections are kept
+    assert_eq!(pool.size(), 1);
+}
+
+#[test]
+fn pool_evict_broken() {
+    let (server, addr) = start_tcp(Echo);
+    let pool = PoolClient::new(2, move || {
+        let mut client = MultiplexClient::new(TcpStream::connect(addr)?)?;
+        client.set_timeout(Duration::from_secs(1));
+        Ok(client)
+    });
+    call(&pool, "hello");
+    assert_eq!(pool.size(), 1);
+
+    // the connection is closed by the server
+    drop(server);
+    coroutine::sleep(Duration::from_millis(100));
+    assert!(pool.call_service(ReqBuf::new()).is_err());
+    assert_eq!(pool.size(), 0);
+
+    // a new connection is created for the restarted server
+    let _server = Echo.start(addr).unwrap();
+    call(&pool, "hello again");
+    assert_eq!(pool.size(), 1);
+}
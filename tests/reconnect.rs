@@ -0,0 +1,109 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use common::start_tcp;
+use conetty::{
+    Client, ConnState, Error, ReconnectClient, ReqBuf, RspBuf, Server, TcpServer, WireError,
+};
+use may::net::{TcpListener, TcpStream};
+use may::{coroutine, go};
+
+struct Echo;
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+fn call(client: &impl Client, msg: &str) {
+    let mut req = ReqBuf::new();
+    req.write_all(msg.as_bytes()).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, msg.as_bytes());
+}
+
+fn wait_state<S: conetty::StreamExt>(client: &ReconnectClient<S>, state: ConnState) {
+    let start = Instant::now();
+    while client.state() != state {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "state={:?}",
+            state
+        );
+        coroutine::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn reconnect_after_server_restart() {
+    let (server, addr) = start_tcp(Echo);
+    let mut client = ReconnectClient::new(move || TcpStream::connect(addr)).unwrap();
+    client.set_timeout(Duration::from_secs(5));
+    client.set_backoff(Duration::from_millis(10), Duration::from_millis(100));
+    assert_eq!(client.state(), ConnState::Connected);
+    call(&client, "hello");
+
+    // the calls fail fast while the server is down
+    drop(server);
+    wait_state(&client, ConnState::Reconnecting);
+    let start = Instant::now();
+    let ret = client.call_service(ReqBuf::new());
+    assert!(matches!(ret, Err(Error::Disconnected)), "ret = {:?}", ret);
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    let _server = Echo.start(addr).unwrap();
+    wait_state(&client, ConnState::Connected);
+    call(&client, "hello again");
+    assert_eq!(client.reconnects(), 1);
+}
+
+#[test]
+fn reconnect_state_transitions() {
+    use std::sync::mpsc::channel;
+    use std::sync::Mutex;
+
+    let (server, addr) = start_tcp(Echo);
+    let mut client = ReconnectClient::new(move || TcpStream::connect(addr)).unwrap();
+    client.set_timeout(Duration::from_secs(5));
+    client.set_backoff(Duration::from_millis(10), Duration::from_millis(100));
+    let (tx, rx) = channel();
+    let tx = Mutex::new(tx);
+    client.set_on_state_change(move |from, to| tx.lock().unwrap().send((from, to)).unwrap());
+
+    // each transition is notified once, in order
+    drop(server);
+    let timeout = Duration::from_secs(5);
+    let transition = rx.recv_timeout(timeout).unwrap();
+    assert_eq!(transition, (ConnState::Connected, ConnState::Reconnecting));
+    let _server = Echo.start(addr).unwrap();
+    let transition = rx.recv_timeout(timeout).unwrap();
+    assert_eq!(transition, (ConnState::Reconnecting, ConnState::Connected));
+    call(&client, "hello again");
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn reconnect_fails_in_flight_calls() {
+    // read the request and close the connection without a response
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let _server = go!(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut buf = [0; 16];
+            stream.read_exact(&mut buf).ok();
+        }
+    });
+
+    let mut client = ReconnectClient::new(move || TcpStream::connect(addr)).unwrap();
+    client.set_timeout(Duration::from_secs(10));
+    let start = Instant::now();
+    let ret = client.call_service(ReqBuf::new());
+    assert!(matches!(ret, Err(Error::Disconnected)), "ret = {:?}", ret);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
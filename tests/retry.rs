@@ -0,0 +1,179 @@
+mod common;
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{start_tcp, start_udp};
+use conetty::{
+    CircuitBreaker, Client, Error, MultiplexClient, ReqBuf, RetryClient, RetryPolicy, RspBuf,
+    Server, UdpClient, UdpConfig, WireError,
+};
+use may::coroutine;
+use may::net::TcpStream;
+
+// the first `slow` calls take longer than the client timeout
+struct Flaky {
+    calls: Arc<AtomicUsize>,
+    slow: usize,
+}
+
+impl Server for Flaky {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        if self.calls.fetch_add(1, Ordering::SeqCst) < self.slow {
+            coroutine::sleep(Duration::from_millis(300));
+        }
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+fn flaky(slow: usize) -> (Flaky, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let server = Flaky {
+        calls: calls.clone(),
+        slow,
+    };
+    (server, calls)
+}
+
+fn client(addr: SocketAddr, policy: RetryPolicy) -> RetryClient<MultiplexClient<TcpStream>> {
+    let mut client = common::client(addr);
+    client.set_timeout(Duration::from_millis(100));
+    RetryClient::new(client, policy)
+}
+
+fn req(msg: &str) -> ReqBuf {
+    let mut req = ReqBuf::new();
+    req.write_all(msg.as_bytes()).unwrap();
+    req
+}
+
+#[test]
+fn retry_idempotent() {
+    let (server, calls) = flaky(2);
+    let (_server, addr) = start_tcp(server);
+    let client = client(addr, RetryPolicy::new(3));
+
+    let rsp_frame = client.call_idempotent(req("hello")).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), b"hello");
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    // the calls that are not marked idempotent are not retried
+    calls.store(0, Ordering::SeqCst);
+    assert!(client.call_service(req("hello")).is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn retry_through_wrappers() {
+    let (server, calls) = flaky(1);
+    let (_server, addr) = start_tcp(server);
+    let client = CircuitBreaker::new(client(addr, RetryPolicy::new(3)));
+
+    // the idempotent call is passed down to the retry client
+    let client: &dyn Client = &client;
+    let rsp_frame = client.call_idempotent(req("hello")).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), b"hello");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn retry_not_retryable() {
+    struct Reject(Arc<AtomicUsize>);
+
+    impl Server for Reject {
+        fn service(&self, _req: &[u8], _rsp: &mut RspBuf) -> Result<(), WireError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Err(WireError::ServerDeserialize("bad request".to_owned()))
+        }
+    }
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let (_server, addr) = start_tcp(Reject(calls.clone()));
+    let client = client(addr, RetryPolicy::new(3));
+
+    let rsp_frame = client.call_idempotent(req("hello")).unwrap();
+    assert!(matches!(
+        rsp_frame.decode_rsp(),
+        Err(Error::ServerDeserialize(_))
+    ));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn retry_budget() {
+    let (server, calls) = flaky(usize::MAX);
+    let (_server, addr) = start_tcp(server);
+    let mut policy = RetryPolicy::new(5);
+    // only one spare retry and no more earned
+    policy.set_budget(0.0, 1);
+    let client = client(addr, policy);
+
+    assert!(client.call_idempotent(req("a")).is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert!(client.call_idempotent(req("b")).is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn retry_udp_same_id() {
+    let (server, calls) = flaky(1);
+    let config = UdpConfig {
+        rsp_cache_size: 1024,
+        ..Default::default()
+    };
+    let (_server, addr) = start_udp(server, config);
+    let mut client = UdpClient::connect(addr).unwrap();
+    client.set_timeout(Duration::from_millis(200));
+
+    // the server drops the retried request and sends the response of the first one
+    let rsp_frame = client
+        .call_idempotent(req("hello"), &RetryPolicy::new(3))
+        .unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), b"hello");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn retry_udp_multiplex_same_id() {
+    use conetty::UdpMultiplexClient;
+
+    let (server, calls) = flaky(1);
+    let config = UdpConfig {
+        rsp_cache_size: 1024,
+        ..Default::default()
+    };
+    let (_server, addr) = start_udp(server, config);
+    let mut client = UdpMultiplexClient::connect(addr).unwrap();
+    client.set_timeout(Duration::from_millis(200));
+
+    // the retried request is answered by the cached response of the first one
+    let rsp_frame = client
+        .call_with_retry(req("hello"), &RetryPolicy::new(3))
+        .unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), b"hello");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn retry_client_udp_same_id() {
+    use conetty::UdpMultiplexClient;
+
+    let (server, calls) = flaky(1);
+    let config = UdpConfig {
+        rsp_cache_size: 1024,
+        ..Default::default()
+    };
+    let (_server, addr) = start_udp(server, config);
+    let mut client = UdpMultiplexClient::connect(addr).unwrap();
+    client.set_timeout(Duration::from_millis(200));
+    let client = RetryClient::new(client, RetryPolicy::new(3));
+
+    // the retries through the wrapper keep the frame id too
+    let rsp_frame = client.call_idempotent(req("hello")).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), b"hello");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
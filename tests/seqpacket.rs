@@ -0,0 +1,148 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::io::Write;
+use std::time::Duration;
+
+use conetty::{ReqBuf, RspBuf, SeqPacketClient, Server, UdsSeqPacketServer, WireError};
+use may::{coroutine, go};
+
+struct Echo;
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+#[test]
+fn echo() {
+    let path = common::temp_path("seqpacket");
+    let _server = Echo.start(&path).unwrap();
+    let mut client = SeqPacketClient::connect(&path).unwrap();
+
+    // each frame is one packet
+    let data = vec![5u8; 32 * 1024];
+    let mut req = ReqBuf::new();
+    req.write_all(&data).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, data.as_slice());
+}
+
+#[test]
+fn seqpacket_timeout() {
+    struct Echo;
+
+    impl Server for Echo {
+        fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+            coroutine::sleep(Duration::from_secs(1));
+            rsp.write_all(req)
+                .map_err(|e| WireError::ServerSerialize(e.to_string()))
+        }
+    }
+
+    let path = common::temp_path("seqpacket");
+    let _server = Echo.start(&path).unwrap();
+    let mut client = SeqPacketClient::connect(&path).unwrap();
+
+    client.set_timeout(Duration::from_millis(500)).unwrap();
+    let mut req = ReqBuf::new();
+    write!(req, "aaaaaa").unwrap();
+    assert!(client.call_service(req).is_err());
+
+    client.set_timeout(Duration::from_millis(1500)).unwrap();
+    let mut req = ReqBuf::new();
+    write!(req, "bbbbbb").unwrap();
+    assert!(client.call_service(req).is_ok());
+}
+
+#[test]
+fn multi_client() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let path = common::temp_path("seqpacket");
+    let _server = Echo.start(&path).unwrap();
+
+    let count = Arc::new(AtomicUsize::new(0));
+
+    let mut vec = vec![];
+    for i in 0..8 {
+        let count_ref = count.clone();
+        let path = path.clone();
+        let h = go!(move || {
+            let mut client = SeqPacketClient::connect(&path).unwrap();
+            for j in 0..10 {
+                let mut req = ReqBuf::new();
+                write!(req, "Hello World! id={}, j={}", i, j).unwrap();
+                match client.call_service(req) {
+                    Ok(_) => {
+                        count_ref.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(err) => panic!("recv err = {:?}", err),
+                }
+            }
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+
+    assert_eq!(count.load(Ordering::Relaxed), 80);
+}
+
+#[test]
+fn large_packet() {
+    use conetty::Error;
+
+    let path = common::temp_path("seqpacket");
+    let _server = Echo.start(&path).unwrap();
+    let mut client = SeqPacketClient::connect(&path).unwrap();
+    client.set_timeout(Duration::from_secs(2)).unwrap();
+
+    // bigger than the default socket buffer
+    let data = vec![6u8; 300 * 1024];
+    let mut req = ReqBuf::new();
+    req.write_all(&data).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), data.as_slice());
+
+    // near the max frame, it only fits if `net.core.wmem_max` allows it
+    // otherwise the send fails, but never hangs
+    let data = vec![7u8; 1000 * 1000];
+    let mut req = ReqBuf::new();
+    req.write_all(&data).unwrap();
+    match client.call_service(req) {
+        Ok(rsp_frame) => assert_eq!(rsp_frame.decode_rsp().unwrap(), data.as_slice()),
+        Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput),
+        Err(e) => panic!("err = {:?}", e),
+    }
+}
+
+#[test]
+fn backlog_full() {
+    use socket2::{Domain, SockAddr, Socket, Type};
+
+    let path = common::temp_path("seqpacket");
+    let _ = std::fs::remove_file(&path);
+    // the listener that accepts nothing until asked, one pending connection fills it
+    let listener = Socket::new(Domain::UNIX, Type::SEQPACKET, None).unwrap();
+    listener.bind(&SockAddr::unix(&path).unwrap()).unwrap();
+    listener.listen(0).unwrap();
+    let _first = SeqPacketClient::connect(&path).unwrap();
+
+    // the connect waits in the coroutine instead of blocking the thread
+    let connect_path = path.clone();
+    let h = go!(move || SeqPacketClient::connect(&connect_path).map(drop));
+    coroutine::sleep(Duration::from_millis(200));
+    assert!(!h.is_done());
+
+    listener.accept().unwrap();
+    h.join().unwrap().unwrap();
+    std::fs::remove_file(&path).ok();
+}
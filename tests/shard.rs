@@ -0,0 +1,94 @@
+mod common;
+
+use std::io::Write;
+use std::net::SocketAddr;
+
+use common::start_tcp;
+use conetty::{Client, Error, ReqBuf, RspBuf, Server, ShardClient, WireError};
+use may::net::TcpStream;
+
+// reply with the node tag
+struct Node(u8);
+
+impl Server for Node {
+    fn service(&self, _req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(&[self.0])
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+fn add_node(client: &ShardClient<TcpStream>, i: usize, addr: SocketAddr) {
+    client.add_node(&format!("node{}", i), move || TcpStream::connect(addr));
+}
+
+fn keys() -> Vec<Vec<u8>> {
+    (0..1000)
+        .map(|i| format!("key{}", i).into_bytes())
+        .collect()
+}
+
+#[test]
+fn shard_route() {
+    let servers: Vec<_> = (0..3).map(|i| start_tcp(Node(i))).collect();
+    let client = ShardClient::new();
+    for (i, (_, addr)) in servers.iter().enumerate() {
+        add_node(&client, i, *addr);
+    }
+
+    for key in keys().iter().take(30) {
+        let rsp_frame = client.call_service(key, ReqBuf::new()).unwrap();
+        let tag = rsp_frame.decode_rsp().unwrap()[0];
+        assert_eq!(client.node_for(key), Some(format!("node{}", tag)));
+
+        // the same node is called by the client of the key
+        let rsp_frame = client.for_key(key).call_service(ReqBuf::new()).unwrap();
+        assert_eq!(rsp_frame.decode_rsp().unwrap(), &[tag]);
+    }
+}
+
+#[test]
+fn shard_no_nodes() {
+    let client = ShardClient::<TcpStream>::new();
+    let ret = client.for_key(b"key").call_service(ReqBuf::new());
+    assert!(matches!(ret, Err(Error::NoNodes)), "ret = {:?}", ret);
+}
+
+#[test]
+fn shard_minimal_movement() {
+    // the nodes are never connected
+    let addr = common::free_addr();
+    let client = ShardClient::new();
+    assert!(client.node_for(b"key").is_none());
+    for i in 0..3 {
+        add_node(&client, i, addr);
+    }
+    let keys = keys();
+    let before: Vec<_> = keys.iter().map(|k| client.node_for(k).unwrap()).collect();
+    // the keys are spread over all the nodes
+    for i in 0..3 {
+        let n = before
+            .iter()
+            .filter(|&n| *n == format!("node{}", i))
+            .count();
+        assert!(n > 200, "node{} owns {} keys", i, n);
+    }
+
+    // only the keys of the removed node are moved
+    assert!(client.remove_node("node1"));
+    assert!(!client.remove_node("node1"));
+    for (key, old) in keys.iter().zip(&before) {
+        let new = client.node_for(key).unwrap();
+        if old != "node1" {
+            assert_eq!(&new, old);
+        }
+    }
+
+    // the moved keys all go to the added node
+    add_node(&client, 1, addr);
+    add_node(&client, 3, addr);
+    let after: Vec<_> = keys.iter().map(|k| client.node_for(k).unwrap()).collect();
+    for (new, old) in after.iter().zip(&before) {
+        assert!(new == old || new == "node3");
+    }
+    assert!(after.iter().any(|n| n == "node3"));
+}
@@ -0,0 +1,127 @@
+#![cfg(target_os = "linux")]
+
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use conetty::{
+    Client, MultiplexClient, ReqBuf, RspBuf, Server, ShmStream, StreamClient, StreamExt,
+    StreamServer, WireError,
+};
+use may::go;
+
+struct Echo;
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+#[test]
+fn echo() {
+    let (client_stream, server_stream) = ShmStream::pair().unwrap();
+    let _server = Echo.serve(server_stream).unwrap();
+    let mut client = StreamClient::new(client_stream);
+
+    // bigger than the ring size
+    let data: Vec<u8> = (0..1_000_000).map(|i| i as u8).collect();
+    let mut req = ReqBuf::new();
+    req.write_all(&data).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, data.as_slice());
+}
+
+#[test]
+fn multiplex_client() {
+    let (client_stream, server_stream) = ShmStream::pair().unwrap();
+    let _server = Echo.serve(server_stream).unwrap();
+    let mut client = MultiplexClient::new(client_stream).unwrap();
+    client.set_timeout(Duration::from_secs(5));
+    let client = Arc::new(client);
+
+    let mut vec = vec![];
+    for i in 0..8 {
+        let client = client.clone();
+        let h = go!(move || {
+            for j in 0..100 {
+                let mut req = ReqBuf::new();
+                write!(req, "Hello World! id={}, j={}", i, j).unwrap();
+                let rsp_frame = client.call_service(req).unwrap();
+                let rsp = rsp_frame.decode_rsp().unwrap();
+                assert_eq!(rsp, format!("Hello World! id={}, j={}", i, j).as_bytes());
+            }
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+}
+
+#[test]
+fn peer_closed() {
+    use std::io::Read;
+
+    let (mut a, b) = ShmStream::pair().unwrap();
+    drop(b);
+    let mut buf = [0u8; 8];
+    assert_eq!(a.read(&mut buf).unwrap(), 0);
+    assert!(a.write(b"hello").is_err());
+}
+
+#[test]
+fn split_half_close() {
+    use std::io::Read;
+
+    let (a, mut b) = ShmStream::pair().unwrap();
+    let (mut reader, mut writer) = a.split().unwrap();
+    writer.write_all(b"hello").unwrap();
+    // the peer reads EOF while the read half is still alive
+    drop(writer);
+    let mut buf = vec![];
+    b.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"hello");
+
+    b.write_all(b"world").unwrap();
+    let mut buf = [0u8; 5];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"world");
+}
+
+#[test]
+fn corrupted_ring() {
+    use std::io::{ErrorKind, Read};
+
+    let (mut a, mut b) = ShmStream::pair().unwrap();
+    a.write_all(b"hello").unwrap();
+    // a broken peer moves the head of the ring far beyond the tail
+    let head = (1u64 << 40).to_ne_bytes();
+    let memfd = a.raw_fds().memfd;
+    let ret = unsafe { libc::pwrite(memfd, head.as_ptr() as *const libc::c_void, 8, 0) };
+    assert_eq!(ret, 8);
+
+    let mut buf = [0u8; 8];
+    let err = b.read(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    let err = a.write(b"hello").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn peer_crashed() {
+    use std::io::Read;
+
+    let (mut a, b) = ShmStream::pair().unwrap();
+    // the peer is gone without setting the closed flag, like a crashed process
+    let link = b.raw_fds().link;
+    std::mem::forget(b);
+    unsafe { libc::close(link) };
+
+    let mut buf = [0u8; 8];
+    assert_eq!(a.read(&mut buf).unwrap(), 0);
+    assert!(a.write(b"hello").is_err());
+}
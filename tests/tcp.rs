@@ -1,7 +1,10 @@
-use std::io::Write;
-use std::time::Duration;
+mod common;
 
-use conetty::{ReqBuf, RspBuf, Server, StreamClient, TcpServer, WireError};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use common::start_tcp;
+use conetty::{Client, Error, MultiplexClient, ReqBuf, RspBuf, Server, StreamClient, WireError};
 use may::{coroutine, go};
 
 struct Echo;
@@ -15,8 +18,7 @@ impl Server for Echo {
 
 #[test]
 fn echo() {
-    let addr = ("127.0.0.1", 2000);
-    let _server = Echo.start(addr).unwrap();
+    let (_server, addr) = start_tcp(Echo);
 
     let tcp_stream = may::net::TcpStream::connect(addr).unwrap();
     let mut client = StreamClient::new(tcp_stream);
@@ -40,8 +42,7 @@ fn tcp_timeout() {
         }
     }
 
-    let addr = ("127.0.0.1", 4000);
-    let _server = Echo.start(addr).unwrap();
+    let (_server, addr) = start_tcp(Echo);
     let tcp_stream = may::net::TcpStream::connect(addr).unwrap();
     let mut client = StreamClient::new(tcp_stream);
 
@@ -61,8 +62,7 @@ fn multi_client() {
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
 
-    let addr = ("127.0.0.1", 3000);
-    let _server = Echo.start(addr).unwrap();
+    let (_server, addr) = start_tcp(Echo);
 
     let count = Arc::new(AtomicUsize::new(0));
 
@@ -92,3 +92,232 @@ fn multi_client() {
 
     assert_eq!(count.load(Ordering::Relaxed), 80);
 }
+
+#[test]
+fn multiplex_disconnected() {
+    // read the request and close the connection without a response
+    let listener = may::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let _server = go!(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 16];
+        stream.read_exact(&mut buf).ok();
+    });
+
+    let tcp_stream = may::net::TcpStream::connect(addr).unwrap();
+    let mut client = MultiplexClient::new(tcp_stream).unwrap();
+    client.set_timeout(Duration::from_secs(10));
+
+    // the pending call is woken up when the connection is closed
+    let start = Instant::now();
+    let ret = client.call_service(ReqBuf::new());
+    assert!(matches!(ret, Err(Error::Disconnected)), "ret = {:?}", ret);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // the later calls fail fast
+    let ret = client.call_service(ReqBuf::new());
+    assert!(matches!(ret, Err(Error::Disconnected)), "ret = {:?}", ret);
+}
+
+// sleep for the milliseconds in the request before the echo
+struct Sleep;
+
+impl Server for Sleep {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        coroutine::sleep(Duration::from_millis(req[0] as u64));
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+#[test]
+fn multiplex_late_rsp() {
+    let (_server, addr) = start_tcp(Sleep);
+    let tcp_stream = may::net::TcpStream::connect(addr).unwrap();
+    let mut client = MultiplexClient::new(tcp_stream).unwrap();
+    client.set_timeout(Duration::from_millis(100));
+
+    let mut req = ReqBuf::new();
+    req.write_all(&[200]).unwrap();
+    assert!(client.call_service(req).is_err());
+
+    // the late response of the first call must not answer this one
+    let mut req = ReqBuf::new();
+    req.write_all(&[150, 2]).unwrap();
+    client.set_timeout(Duration::from_secs(1));
+    let rsp_frame = client.call_service(req).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), &[150, 2]);
+}
+
+#[test]
+fn multiplex_start_call() {
+    let (_server, addr) = start_tcp(Sleep);
+    let tcp_stream = may::net::TcpStream::connect(addr).unwrap();
+    let mut client = MultiplexClient::new(tcp_stream).unwrap();
+    client.set_timeout(Duration::from_secs(5));
+
+    // all the calls are in flight from one coroutine
+    let mut handles = vec![];
+    for &ms in &[200u8, 10, 100, 150] {
+        let mut req = ReqBuf::new();
+        req.write_all(&[ms]).unwrap();
+        handles.push(client.start_call(req).unwrap());
+    }
+    assert!(!handles[0].is_ready());
+
+    let (i, rsp_frame) = client.wait_any(&mut handles).unwrap().unwrap();
+    assert_eq!(i, 1);
+    assert_eq!(rsp_frame.unwrap().decode_rsp().unwrap(), &[10]);
+
+    let rsp: Vec<_> = client
+        .wait_all(handles)
+        .into_iter()
+        .map(|r| r.unwrap().decode_rsp().unwrap()[0])
+        .collect();
+    assert_eq!(rsp, [200, 100, 150]);
+    assert!(client.wait_any(&mut vec![]).unwrap().is_none());
+}
+
+#[test]
+fn multiplex_wait_any_timeout() {
+    let (_server, addr) = start_tcp(Sleep);
+    let tcp_stream = may::net::TcpStream::connect(addr).unwrap();
+    let mut client = MultiplexClient::new(tcp_stream).unwrap();
+    client.set_timeout(Duration::from_millis(50));
+
+    let mut handles = vec![];
+    for &ms in &[200u8, 150] {
+        let mut req = ReqBuf::new();
+        req.write_all(&[ms]).unwrap();
+        handles.push(client.start_call(req).unwrap());
+    }
+    let ret = client.wait_any(&mut handles);
+    assert!(matches!(ret, Err(Error::Timeout)), "ret = {:?}", ret);
+
+    // the handles are still usable after the timeout
+    assert_eq!(handles.len(), 2);
+    let h = handles.remove(1);
+    let rsp_frame = h.wait_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), &[150]);
+
+    // the dropped call is forgotten, its response is discarded
+    drop(handles);
+    let mut req = ReqBuf::new();
+    req.write_all(&[0]).unwrap();
+    let rsp_frame = client
+        .start_call(req)
+        .unwrap()
+        .wait_timeout(Duration::from_secs(1));
+    assert_eq!(rsp_frame.unwrap().decode_rsp().unwrap(), &[0]);
+}
+
+#[test]
+fn stream_call_batch() {
+    let (_server, addr) = start_tcp(Sleep);
+    let tcp_stream = may::net::TcpStream::connect(addr).unwrap();
+    let mut client = StreamClient::new(tcp_stream);
+
+    // the later requests are answered first
+    let delays = [150u8, 100, 50, 0];
+    let reqs = delays
+        .iter()
+        .map(|&ms| {
+            let mut req = ReqBuf::new();
+            req.write_all(&[ms]).unwrap();
+            req
+        })
+        .collect();
+    let rsp: Vec<_> = client
+        .call_batch(reqs)
+        .unwrap()
+        .iter()
+        .map(|f| f.decode_rsp().unwrap()[0])
+        .collect();
+    assert_eq!(rsp, delays);
+
+    // the single calls still work after the batch
+    let mut req = ReqBuf::new();
+    req.write_all(&[0]).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), &[0]);
+    assert!(client.call_batch(vec![]).unwrap().is_empty());
+}
+
+#[test]
+fn stream_call_batch_large() {
+    let (_server, addr) = start_tcp(Sleep);
+    let tcp_stream = may::net::TcpStream::connect(addr).unwrap();
+    let mut client = StreamClient::new(tcp_stream);
+
+    // the batch is much bigger than the socket buffers
+    let reqs = (0..256u32)
+        .map(|i| {
+            let mut req = ReqBuf::new();
+            req.write_all(&[0]).unwrap();
+            req.write_all(&i.to_be_bytes()).unwrap();
+            req.write_all(&[0; 16 * 1024]).unwrap();
+            req
+        })
+        .collect();
+    let rsps = client.call_batch(reqs).unwrap();
+    assert_eq!(rsps.len(), 256);
+    for (i, rsp_frame) in rsps.iter().enumerate() {
+        let rsp = rsp_frame.decode_rsp().unwrap();
+        assert_eq!(rsp[1..5], (i as u32).to_be_bytes());
+    }
+}
+
+#[test]
+fn stream_call_batch_many() {
+    let (_server, addr) = start_tcp(Sleep);
+    let tcp_stream = may::net::TcpStream::connect(addr).unwrap();
+    let mut client = StreamClient::new(tcp_stream);
+
+    // more small requests in a window than the slices of one vectored write
+    let reqs = (0..5000u32)
+        .map(|i| {
+            let mut req = ReqBuf::new();
+            req.write_all(&[0]).unwrap();
+            req.write_all(&i.to_be_bytes()).unwrap();
+            req
+        })
+        .collect();
+    let rsps = client.call_batch(reqs).unwrap();
+    assert_eq!(rsps.len(), 5000);
+    for (i, rsp_frame) in rsps.iter().enumerate() {
+        let rsp = rsp_frame.decode_rsp().unwrap();
+        assert_eq!(rsp[1..5], (i as u32).to_be_bytes());
+    }
+}
+
+#[test]
+fn multiplex_cancel_hooks() {
+    use conetty::CancelToken;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let (_server, addr) = start_tcp(Echo);
+    let tcp_stream = may::net::TcpStream::connect(addr).unwrap();
+    let client = MultiplexClient::new(tcp_stream).unwrap();
+
+    // the long lived token is shared by the calls
+    let token = CancelToken::new();
+    for _ in 0..100 {
+        client.call_cancellable(ReqBuf::new(), &token).unwrap();
+    }
+
+    // only the hooks that are still held run on cancel
+    let runs = Arc::new(AtomicUsize::new(0));
+    let hook = |runs: &Arc<AtomicUsize>| {
+        let runs = runs.clone();
+        move || {
+            runs.fetch_add(1, Ordering::SeqCst);
+        }
+    };
+    let _held = token.on_cancel(hook(&runs));
+    drop(token.on_cancel(hook(&runs)));
+    token.cancel();
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    let ret = client.call_cancellable(ReqBuf::new(), &token);
+    assert!(matches!(ret, Err(Error::Cancelled)), "ret = {:?}", ret);
+}
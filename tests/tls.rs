@@ -0,0 +1,185 @@
+#![cfg(feature = "tls")]
+
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use conetty::{
+    Client, MultiplexClient, PeerIdentity, ReqBuf, RspBuf, Server, StreamClient, TlsAcceptor,
+    TlsConnector, TlsServer, WireError,
+};
+use may::go;
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+
+struct Echo;
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+impl TlsServer for Echo {}
+
+// the self signed ca and the pem encoded (cert, key) signed by it
+struct Certs {
+    ca: String,
+    server: (String, String),
+    client: (String, String),
+}
+
+fn certs() -> Certs {
+    let mut params = CertificateParams::new(vec![]);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "conetty test ca");
+    let ca = Certificate::from_params(params).unwrap();
+
+    let sign = |name: &str| {
+        let mut params = CertificateParams::new(vec![name.to_owned()]);
+        params.distinguished_name.push(DnType::CommonName, name);
+        let cert = Certificate::from_params(params).unwrap();
+        (
+            cert.serialize_pem_with_signer(&ca).unwrap(),
+            cert.serialize_private_key_pem(),
+        )
+    };
+
+    Certs {
+        server: sign("localhost"),
+        client: sign("client"),
+        ca: ca.serialize_pem().unwrap(),
+    }
+}
+
+#[test]
+fn tls_echo() {
+    let certs = certs();
+    let (cert, key) = &certs.server;
+    let acceptor = TlsAcceptor::new(cert.as_bytes(), key.as_bytes()).unwrap();
+    let server = Echo.start_tls("127.0.0.1:0", acceptor).unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let connector = TlsConnector::new(certs.ca.as_bytes()).unwrap();
+    let stream = connector.connect(addr, "localhost").unwrap();
+    let mut client = StreamClient::new(stream);
+
+    // bigger than one tls record
+    let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+    let mut req = ReqBuf::new();
+    req.write_all(&data).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, data.as_slice());
+
+    // the server name must match the certificate
+    assert!(connector.connect(addr, "example.com").is_err());
+}
+
+#[test]
+fn tls_client_auth() {
+    struct WhoAmI;
+
+    impl Server for WhoAmI {
+        fn service(&self, _req: &[u8], _rsp: &mut RspBuf) -> Result<(), WireError> {
+            Err(WireError::Status("no peer identity".to_owned()))
+        }
+    }
+
+    impl TlsServer for WhoAmI {
+        fn tls_service(
+            &self,
+            peer: Option<&PeerIdentity>,
+            _req: &[u8],
+            rsp: &mut RspBuf,
+        ) -> Result<(), WireError> {
+            let peer = peer.ok_or_else(|| WireError::Status("no peer".to_owned()))?;
+            rsp.write_all(peer.end_entity())
+                .map_err(|e| WireError::ServerSerialize(e.to_string()))
+        }
+    }
+
+    let certs = certs();
+    let (cert, key) = &certs.server;
+    let acceptor =
+        TlsAcceptor::with_client_auth(cert.as_bytes(), key.as_bytes(), certs.ca.as_bytes())
+            .unwrap();
+    let server = WhoAmI.start_tls("127.0.0.1:0", acceptor).unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (cert, key) = &certs.client;
+    let connector =
+        TlsConnector::with_client_cert(certs.ca.as_bytes(), cert.as_bytes(), key.as_bytes())
+            .unwrap();
+    let stream = connector.connect(addr, "localhost").unwrap();
+    let mut client = MultiplexClient::new(stream).unwrap();
+    client.set_timeout(Duration::from_secs(5));
+    let client = Arc::new(client);
+
+    let der = rustls_pemfile::certs(&mut cert.as_bytes())
+        .unwrap()
+        .remove(0);
+    let mut vec = vec![];
+    for _ in 0..8 {
+        let client = client.clone();
+        let der = der.clone();
+        let h = go!(move || {
+            for _ in 0..10 {
+                let rsp_frame = client.call_service(ReqBuf::new()).unwrap();
+                let rsp = rsp_frame.decode_rsp().unwrap();
+                assert_eq!(rsp, der.as_slice());
+            }
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+
+    // the client without certificate is rejected
+    let connector = TlsConnector::new(certs.ca.as_bytes()).unwrap();
+    if let Ok(stream) = connector.connect(addr, "localhost") {
+        // tls 1.3 client finishes the handshake before the server verifies it
+        let mut client = StreamClient::new(stream);
+        assert!(client.call_service(ReqBuf::new()).is_err());
+    }
+}
+
+#[test]
+fn tls_multiplex_large() {
+    let certs = certs();
+    let (cert, key) = &certs.server;
+    let acceptor = TlsAcceptor::new(cert.as_bytes(), key.as_bytes()).unwrap();
+    let server = Echo.start_tls("127.0.0.1:0", acceptor).unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let connector = TlsConnector::new(certs.ca.as_bytes()).unwrap();
+    let stream = connector.connect(addr, "localhost").unwrap();
+    let mut client = MultiplexClient::new(stream).unwrap();
+    client.set_timeout(Duration::from_secs(10));
+    let client = Arc::new(client);
+
+    // both sides write more than the socket buffers at the same time,
+    // the writers must not block the readers of the same connection
+    let mut vec = vec![];
+    for i in 0..8u8 {
+        let client = client.clone();
+        let h = go!(move || {
+            let data = vec![i; 256 * 1024];
+            for _ in 0..4 {
+                let mut req = ReqBuf::new();
+                req.write_all(&data).unwrap();
+                let rsp_frame = client.call_service(req).unwrap();
+                assert_eq!(rsp_frame.decode_rsp().unwrap(), data.as_slice());
+            }
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+}
@@ -1,7 +1,10 @@
+mod common;
+
 use std::io::Write;
 use std::time::Duration;
 
-use conetty::{ReqBuf, RspBuf, Server, UdpClient, UdpServer, WireError};
+use common::start_udp;
+use conetty::{ReqBuf, RspBuf, Server, UdpClient, UdpConfig, WireError};
 use may::{coroutine, go};
 
 struct Echo;
@@ -15,8 +18,7 @@ impl Server for Echo {
 
 #[test]
 fn echo() {
-    let addr = ("127.0.0.1", 2000);
-    let _server = Echo.start(addr).unwrap();
+    let (_server, addr) = start_udp(Echo, UdpConfig::default());
     let mut client = UdpClient::connect(addr).unwrap();
 
     let mut req = ReqBuf::new();
@@ -26,6 +28,20 @@ fn echo() {
     assert_eq!(rsp, &[5u8; 16]);
 }
 
+#[test]
+fn large_payload() {
+    let (_server, addr) = start_udp(Echo, UdpConfig::default());
+    let mut client = UdpClient::connect(addr).unwrap();
+
+    // bigger than the max datagram size, must be fragmented
+    let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+    let mut req = ReqBuf::new();
+    req.write_all(&data).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, data.as_slice());
+}
+
 #[test]
 fn tcp_timeout() {
     struct Echo;
@@ -38,8 +54,7 @@ fn tcp_timeout() {
         }
     }
 
-    let addr = ("127.0.0.1", 4000);
-    let _server = Echo.start(addr).unwrap();
+    let (_server, addr) = start_udp(Echo, UdpConfig::default());
     let mut client = UdpClient::connect(addr).unwrap();
 
     client.set_timeout(Duration::from_millis(500));
@@ -53,13 +68,46 @@ fn tcp_timeout() {
     assert!(client.call_service(req).is_ok());
 }
 
+#[test]
+fn retransmit() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Counter(Arc<AtomicUsize>);
+
+    impl Server for Counter {
+        fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            coroutine::sleep(Duration::from_millis(300));
+            rsp.write_all(req)
+                .map_err(|e| WireError::ServerSerialize(e.to_string()))
+        }
+    }
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let config = UdpConfig {
+        rsp_cache_size: 1024,
+        ..Default::default()
+    };
+    let (_server, addr) = start_udp(Counter(count.clone()), config);
+    let mut client = UdpClient::connect(addr).unwrap();
+    client.set_timeout(Duration::from_secs(2));
+    client.set_retransmit(Duration::from_millis(50));
+
+    let mut req = ReqBuf::new();
+    write!(req, "cccccc").unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    assert_eq!(rsp_frame.decode_rsp().unwrap(), b"cccccc");
+    // the resent requests are not processed again
+    assert_eq!(count.load(Ordering::Relaxed), 1);
+}
+
 #[test]
 fn multi_client() {
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
 
-    let addr = ("127.0.0.1", 3000);
-    let _server = Echo.start(addr).unwrap();
+    let (_server, addr) = start_udp(Echo, UdpConfig::default());
 
     let count = Arc::new(AtomicUsize::new(0));
 
@@ -88,3 +136,93 @@ fn multi_client() {
 
     assert_eq!(count.load(Ordering::Relaxed), 80);
 }
+
+#[test]
+fn multiplex_client() {
+    use conetty::{Client, UdpMultiplexClient};
+    use std::sync::Arc;
+
+    let (_server, addr) = start_udp(Echo, UdpConfig::default());
+    let mut client = UdpMultiplexClient::connect(addr).unwrap();
+    client.set_timeout(Duration::from_secs(2));
+    let client = Arc::new(client);
+
+    let mut vec = vec![];
+    for i in 0..8 {
+        let client = client.clone();
+        let h = go!(move || {
+            for j in 0..10 {
+                let mut req = ReqBuf::new();
+                write!(req, "Hello World! id={}, j={}", i, j).unwrap();
+                let rsp_frame = client.call_service(req).unwrap();
+                let rsp = rsp_frame.decode_rsp().unwrap();
+                assert_eq!(rsp, format!("Hello World! id={}, j={}", i, j).as_bytes());
+            }
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+}
+
+#[test]
+fn multiplex_sequential() {
+    use conetty::{Client, UdpMultiplexClient};
+
+    let (_server, addr) = start_udp(Echo, UdpConfig::default());
+    let mut client = UdpMultiplexClient::connect(addr).unwrap();
+    client.set_timeout(Duration::from_secs(2));
+
+    // each call has its own id, the cached response of the last one is not returned
+    for msg in &["first", "second", "third"] {
+        let mut req = ReqBuf::new();
+        req.write_all(msg.as_bytes()).unwrap();
+        let rsp_frame = client.call_service(req).unwrap();
+        assert_eq!(rsp_frame.decode_rsp().unwrap(), msg.as_bytes());
+    }
+}
+
+#[test]
+fn batch_io() {
+    let config = UdpConfig {
+        recv_buf_size: 1472,
+        ..Default::default()
+    };
+    let (_server, addr) = start_udp(Echo, config);
+
+    // the datagram bigger than the receive buffer is dropped, not the batch
+    let sock = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    sock.send_to(&[0u8; 4000], addr).unwrap();
+
+    // the concurrent requests are received and answered in batches
+    let mut vec = vec![];
+    for i in 0..64 {
+        vec.push(go!(move || {
+            let mut client = UdpClient::connect(addr).unwrap();
+            client.set_timeout(Duration::from_secs(2));
+            let mut req = ReqBuf::new();
+            write!(req, "batch {}", i).unwrap();
+            let rsp_frame = client.call_service(req).unwrap();
+            assert_eq!(
+                rsp_frame.decode_rsp().unwrap(),
+                format!("batch {}", i).as_bytes()
+            );
+        }));
+    }
+    for h in vec {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn small_mtu() {
+    // the max frame would need more fragments than the header can count
+    let config = UdpConfig {
+        mtu: 25,
+        ..Default::default()
+    };
+    let err = UdpClient::connect_with_config(common::free_addr(), config).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
@@ -1,5 +1,7 @@
 #![cfg(unix)]
 
+mod common;
+
 use std::io::Write;
 use std::time::Duration;
 
@@ -17,9 +19,9 @@ impl Server for Echo {
 
 #[test]
 fn echo() {
-    let path = "/tmp/test_another";
-    let _server = Echo.start(path).unwrap();
-    let unix_stream = may::os::unix::net::UnixStream::connect(path).unwrap();
+    let path = common::temp_path("uds");
+    let _server = Echo.start(&path).unwrap();
+    let unix_stream = may::os::unix::net::UnixStream::connect(&path).unwrap();
     let mut client = StreamClient::new(unix_stream);
 
     let mut req = ReqBuf::new();
@@ -41,9 +43,9 @@ fn uds_timeout() {
         }
     }
 
-    let path = "/tmp/test_uds1";
-    let _server = Echo.start(path).unwrap();
-    let unix_stream = may::os::unix::net::UnixStream::connect(path).unwrap();
+    let path = common::temp_path("uds");
+    let _server = Echo.start(&path).unwrap();
+    let unix_stream = may::os::unix::net::UnixStream::connect(&path).unwrap();
     let mut client = StreamClient::new(unix_stream);
 
     client.set_timeout(Duration::from_millis(500)).unwrap();
@@ -62,16 +64,17 @@ fn multi_client() {
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
 
-    let path = "/tmp/test_uds2";
-    let _server = Echo.start(path).unwrap();
+    let path = common::temp_path("uds");
+    let _server = Echo.start(&path).unwrap();
 
     let count = Arc::new(AtomicUsize::new(0));
 
     let mut vec = vec![];
     for i in 0..8 {
         let count_ref = count.clone();
+        let path = path.clone();
         let h = go!(move || {
-            let unix_stream = may::os::unix::net::UnixStream::connect(path).unwrap();
+            let unix_stream = may::os::unix::net::UnixStream::connect(&path).unwrap();
             let mut client = StreamClient::new(unix_stream);
             for j in 0..10 {
                 let mut req = ReqBuf::new();
@@ -93,3 +96,23 @@ fn multi_client() {
 
     assert_eq!(count.load(Ordering::Relaxed), 80);
 }
+
+#[test]
+fn split_half_close() {
+    use conetty::StreamExt;
+    use std::io::Read;
+
+    let (a, mut b) = may::os::unix::net::UnixStream::pair().unwrap();
+    let (mut reader, mut writer) = a.split().unwrap();
+    writer.write_all(b"hello").unwrap();
+    // the write side is shut down while the read half is still alive
+    drop(writer);
+    let mut buf = vec![];
+    b.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"hello");
+
+    b.write_all(b"world").unwrap();
+    let mut buf = [0u8; 5];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"world");
+}
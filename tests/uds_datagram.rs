@@ -0,0 +1,97 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::io::Write;
+use std::time::Duration;
+
+use conetty::{ReqBuf, RspBuf, Server, UdsDatagramClient, UdsDatagramServer, WireError};
+use may::{coroutine, go};
+
+struct Echo;
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+#[test]
+fn echo() {
+    let path = common::temp_path("dgram");
+    let _server = Echo.start(&path).unwrap();
+    let mut client = UdsDatagramClient::connect(&path).unwrap();
+
+    // bigger than the udp buffers
+    let data = vec![5u8; 32 * 1024];
+    let mut req = ReqBuf::new();
+    req.write_all(&data).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, data.as_slice());
+}
+
+#[test]
+fn uds_datagram_timeout() {
+    struct Echo;
+
+    impl Server for Echo {
+        fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+            coroutine::sleep(Duration::from_secs(1));
+            rsp.write_all(req)
+                .map_err(|e| WireError::ServerSerialize(e.to_string()))
+        }
+    }
+
+    let path = common::temp_path("dgram");
+    let _server = Echo.start(&path).unwrap();
+    let mut client = UdsDatagramClient::connect(&path).unwrap();
+
+    client.set_timeout(Duration::from_millis(500));
+    let mut req = ReqBuf::new();
+    write!(req, "aaaaaa").unwrap();
+    assert!(client.call_service(req).is_err());
+
+    client.set_timeout(Duration::from_millis(1500));
+    let mut req = ReqBuf::new();
+    write!(req, "bbbbbb").unwrap();
+    assert!(client.call_service(req).is_ok());
+}
+
+#[test]
+fn multi_client() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let path = common::temp_path("dgram");
+    let _server = Echo.start(&path).unwrap();
+
+    let count = Arc::new(AtomicUsize::new(0));
+
+    let mut vec = vec![];
+    for i in 0..8 {
+        let count_ref = count.clone();
+        let path = path.clone();
+        let h = go!(move || {
+            let mut client = UdsDatagramClient::connect(&path).unwrap();
+            for j in 0..10 {
+                let mut req = ReqBuf::new();
+                write!(req, "Hello World! id={}, j={}", i, j).unwrap();
+                match client.call_service(req) {
+                    Ok(_) => {
+                        count_ref.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(err) => panic!("recv err = {:?}", err),
+                }
+            }
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+
+    assert_eq!(count.load(Ordering::Relaxed), 80);
+}
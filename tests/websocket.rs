@@ -0,0 +1,149 @@
+#![cfg(feature = "websocket")]
+
+use std::io::{Cursor, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use conetty::{
+    Client, Frame, LoopbackStream, MultiplexClient, ReqBuf, RspBuf, Server, StreamClient,
+    StreamServer, TcpServer, WireError, WsStream,
+};
+use may::go;
+use tungstenite::protocol::frame::coding::{CloseCode, Control, Data, OpCode};
+use tungstenite::protocol::frame::FrameSocket;
+use tungstenite::Message;
+
+struct Echo;
+
+impl Server for Echo {
+    fn service(&self, req: &[u8], rsp: &mut RspBuf) -> Result<(), WireError> {
+        rsp.write_all(req)
+            .map_err(|e| WireError::ServerSerialize(e.to_string()))
+    }
+}
+
+#[test]
+fn ws_echo() {
+    let server = Echo.start_ws("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let url = format!("ws://{}/", addr);
+    let stream = WsStream::connect(addr, &url).unwrap();
+    let mut client = MultiplexClient::new(stream).unwrap();
+    client.set_timeout(Duration::from_secs(5));
+    let client = Arc::new(client);
+
+    let mut vec = vec![];
+    for i in 0..8 {
+        let client = client.clone();
+        let h = go!(move || {
+            for j in 0..10 {
+                let mut req = ReqBuf::new();
+                write!(req, "Hello World! id={}, j={}", i, j).unwrap();
+                let rsp_frame = client.call_service(req).unwrap();
+                let rsp = rsp_frame.decode_rsp().unwrap();
+                assert_eq!(rsp, format!("Hello World! id={}, j={}", i, j).as_bytes());
+            }
+        });
+        vec.push(h);
+    }
+
+    for j in vec {
+        j.join().unwrap();
+    }
+}
+
+#[test]
+fn ws_raw_client() {
+    let server = Echo.start_ws("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let url = format!("ws://{}/", addr);
+    let stream = may::net::TcpStream::connect(addr).unwrap();
+    let (mut ws, _) = tungstenite::client(url.as_str(), stream).unwrap();
+
+    // the ping is answered by the server
+    ws.send(Message::Ping(b"ping".to_vec())).unwrap();
+    match ws.read().unwrap() {
+        Message::Pong(data) => assert_eq!(data, b"ping"),
+        msg => panic!("unexpected message: {:?}", msg),
+    }
+
+    // each binary message carries one frame
+    let mut req = ReqBuf::new();
+    req.write_all(b"hello").unwrap();
+    ws.send(Message::Binary(req.finish(5))).unwrap();
+    match ws.read().unwrap() {
+        Message::Binary(data) => {
+            let rsp_frame = Frame::decode_from(&mut Cursor::new(&data)).unwrap();
+            assert_eq!(rsp_frame.id, 5);
+            assert_eq!(rsp_frame.decode_rsp().unwrap(), b"hello");
+        }
+        msg => panic!("unexpected message: {:?}", msg),
+    }
+}
+
+#[test]
+fn ws_unmasked_frame() {
+    let server = Echo.start_ws("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let url = format!("ws://{}/", addr);
+    let stream = may::net::TcpStream::connect(addr).unwrap();
+    let (mut ws, _) = tungstenite::client(url.as_str(), stream).unwrap();
+
+    // the client frame without the mask is a protocol error
+    let mut socket = FrameSocket::new(ws.get_mut());
+    let mut req = ReqBuf::new();
+    req.write_all(b"hello").unwrap();
+    let frame = tungstenite::protocol::frame::Frame::message(
+        req.finish(7),
+        OpCode::Data(Data::Binary),
+        true,
+    );
+    socket.send(frame).unwrap();
+
+    // the server answers with the close frame instead of the response
+    let frame = socket.read(None).unwrap().unwrap();
+    assert_eq!(frame.header().opcode, OpCode::Control(Control::Close));
+    let code = u16::from_be_bytes([frame.payload()[0], frame.payload()[1]]);
+    assert_eq!(CloseCode::from(code), CloseCode::Protocol);
+}
+
+#[test]
+fn ws_continuation_without_message() {
+    let server = Echo.start_ws("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let url = format!("ws://{}/", addr);
+    let stream = may::net::TcpStream::connect(addr).unwrap();
+    let (mut ws, _) = tungstenite::client(url.as_str(), stream).unwrap();
+
+    // the continuation frame must follow an unfinished binary message
+    let mut socket = FrameSocket::new(ws.get_mut());
+    let mut frame = tungstenite::protocol::frame::Frame::message(
+        b"hello".to_vec(),
+        OpCode::Data(Data::Continue),
+        true,
+    );
+    frame.header_mut().mask = Some([1, 2, 3, 4]);
+    socket.send(frame).unwrap();
+
+    let frame = socket.read(None).unwrap().unwrap();
+    assert_eq!(frame.header().opcode, OpCode::Control(Control::Close));
+    let code = u16::from_be_bytes([frame.payload()[0], frame.payload()[1]]);
+    assert_eq!(CloseCode::from(code), CloseCode::Protocol);
+}
+
+#[test]
+fn ws_loopback() {
+    let (client, server) = LoopbackStream::pair();
+    let h = go!(move || WsStream::accept(server).unwrap());
+    let stream = WsStream::connect_stream(client, "ws://localhost/").unwrap();
+    let _server = Echo.serve(h.join().unwrap()).unwrap();
+    let mut client = StreamClient::new(stream);
+
+    // bigger than the websocket 64k length boundary
+    let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+    let mut req = ReqBuf::new();
+    req.write_all(&data).unwrap();
+    let rsp_frame = client.call_service(req).unwrap();
+    let rsp = rsp_frame.decode_rsp().unwrap();
+    assert_eq!(rsp, data.as_slice());
+}